@@ -0,0 +1,52 @@
+// Copyright 2019 The Gotts Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Mock Alpha Vantage API server, replaying the recorded responses of a
+//! fixtures directory. Point the oracle at it with `alpha_vantage_endpoint`
+//! in its config file, i.e.
+//!
+//! ```text
+//! cargo run --features mock --example mock_server -- \
+//!     --addr 127.0.0.1:8080 --fixtures alphavantage/tests/mock
+//! ```
+
+use exitfailure::ExitFailure;
+use gotts_oracle_alphavantage::mock::{Fixtures, MockServer};
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::thread;
+use structopt::StructOpt;
+
+#[derive(StructOpt)]
+#[structopt(name = "mock_server", about = "Mock Alpha Vantage API server")]
+struct Opt {
+	/// Address to listen on
+	#[structopt(long = "addr", default_value = "127.0.0.1:8080")]
+	addr: SocketAddr,
+	/// Directory of the recorded JSON responses
+	#[structopt(long = "fixtures", parse(from_os_str))]
+	fixtures: PathBuf,
+}
+
+fn main() -> Result<(), ExitFailure> {
+	let opt = Opt::from_args();
+	let fixtures = Fixtures::from_dir(&opt.fixtures)?;
+	let server = MockServer::start(&opt.addr, fixtures)?;
+	println!("Mock Alpha Vantage API listening on {}", server.endpoint());
+
+	// serve until killed
+	loop {
+		thread::park();
+	}
+}
@@ -12,18 +12,42 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use crate::digital_currency;
 use crate::exchange_rate;
+use crate::metrics;
 use crate::time_series;
 use failure;
 use failure_derive::Fail;
+use futures::future::{self, Either};
+use futures::{Future, Stream};
 use reqwest;
+use reqwest::r#async as async_reqwest;
+use std::time::{Duration, Instant};
 
-const URL_ENDPOINT: &str = "https://www.alphavantage.co/query";
+/// Endpoint of the Alpha Vantage API.
+pub const URL_ENDPOINT: &str = "https://www.alphavantage.co/query";
+
+/// Default timeout of a request, from the connection to the end of the
+/// response body.
+pub const DEFAULT_TIMEOUT_SECS: u64 = 30;
+
+/// Daily, weekly or monthly time series of a digital currency.
+pub type DigitalCurrencySeries =
+	digital_currency::DigitalCurrencyTimeSeries<digital_currency::DigitalCurrencyEntry>;
+
+/// Intraday time series of a digital currency.
+pub type CryptoIntradaySeries =
+	digital_currency::DigitalCurrencyTimeSeries<digital_currency::CryptoIntradayEntry>;
+
+/// A future resolving to the result of an API call.
+pub type ClientFuture<T> = Box<dyn Future<Item = T, Error = Error> + Send>;
 
 /// A client for the Alpha Vantage API.
 pub struct Client {
 	key: String,
+	endpoint: String,
 	client: reqwest::Client,
+	async_client: async_reqwest::Client,
 }
 
 /// Set of errors which can occur when calling the API.
@@ -42,6 +66,12 @@ pub enum Error {
 		/// HTTP error code.
 		code: u16,
 	},
+	/// The API key is over its call frequency or its daily limit.
+	#[fail(display = "rate limited: {}", message)]
+	RateLimited {
+		/// Message returned by the API.
+		message: String,
+	},
 	/// Error parsing the result returned from the API.
 	#[fail(display = "parsing error: {}", error)]
 	ParsingError {
@@ -54,10 +84,32 @@ pub enum Error {
 impl Client {
 	/// Create a new client using the specified API `key`.
 	pub fn new(key: &str) -> Client {
-		let client = reqwest::Client::new();
+		Client::with_timeout(key, Duration::from_secs(DEFAULT_TIMEOUT_SECS))
+	}
+
+	/// Create a new client using the specified API `key`, whose requests
+	/// time out after `timeout`.
+	pub fn with_timeout(key: &str, timeout: Duration) -> Client {
+		Client::with_endpoint(key, URL_ENDPOINT, timeout)
+	}
+
+	/// Create a new client using the specified API `key`, querying the API at
+	/// `endpoint` instead of Alpha Vantage, i.e. a staging proxy or a mock,
+	/// and whose requests time out after `timeout`.
+	pub fn with_endpoint(key: &str, endpoint: &str, timeout: Duration) -> Client {
+		let client = reqwest::Client::builder()
+			.timeout(timeout)
+			.build()
+			.expect("failed to build the HTTP client");
+		let async_client = async_reqwest::Client::builder()
+			.timeout(timeout)
+			.build()
+			.expect("failed to build the async HTTP client");
 		Client {
 			key: String::from(key),
+			endpoint: endpoint.to_owned(),
 			client,
+			async_client,
 		}
 	}
 
@@ -101,12 +153,244 @@ impl Client {
 			("from_currency", from_currency_code),
 			("to_currency", to_currency_code),
 		];
-		let response = self.api_call(function, &params)?;
-		let result =
-			exchange_rate::parser::parse(response).map_err(|error| Error::ParsingError {
-				error: error.compat(),
-			})?;
-		Ok(result)
+		self.api_call(function, &params, |response| {
+			exchange_rate::parser::parse(response)
+		})
+	}
+
+	/// Retrieve intraday exchange rates from the currency `from_symbol` to the currency
+	/// `to_symbol`, the latest 100 entries or the full history as per `output_size`.
+	pub fn get_fx_intraday(
+		&self,
+		from_symbol: &str,
+		to_symbol: &str,
+		interval: time_series::IntradayInterval,
+		output_size: time_series::OutputSize,
+	) -> Result<time_series::FxTimeSeries, Error> {
+		let function = time_series::Function::FxIntraDay(interval);
+		self.get_fx_time_series(&function, from_symbol, to_symbol, Some(output_size))
+	}
+
+	/// Retrieve daily exchange rates from the currency `from_symbol` to the currency
+	/// `to_symbol`, the latest 100 entries or up to 20 years as per `output_size`.
+	pub fn get_fx_daily(
+		&self,
+		from_symbol: &str,
+		to_symbol: &str,
+		output_size: time_series::OutputSize,
+	) -> Result<time_series::FxTimeSeries, Error> {
+		let function = time_series::Function::FxDaily;
+		self.get_fx_time_series(&function, from_symbol, to_symbol, Some(output_size))
+	}
+
+	/// Retrieve weekly exchange rates from the currency `from_symbol` to the currency
+	/// `to_symbol`, including up to 20 years of historical data.
+	pub fn get_fx_weekly(
+		&self,
+		from_symbol: &str,
+		to_symbol: &str,
+	) -> Result<time_series::FxTimeSeries, Error> {
+		let function = time_series::Function::FxWeekly;
+		self.get_fx_time_series(&function, from_symbol, to_symbol, None)
+	}
+
+	/// Retrieve monthly exchange rates from the currency `from_symbol` to the currency
+	/// `to_symbol`, including up to 20 years of historical data.
+	pub fn get_fx_monthly(
+		&self,
+		from_symbol: &str,
+		to_symbol: &str,
+	) -> Result<time_series::FxTimeSeries, Error> {
+		let function = time_series::Function::FxMonthly;
+		self.get_fx_time_series(&function, from_symbol, to_symbol, None)
+	}
+
+	/// Retrieve intraday prices of the digital currency `symbol` in the market currency
+	/// `market`, the latest 100 entries or the full history as per `output_size`.
+	pub fn get_crypto_intraday(
+		&self,
+		symbol: &str,
+		market: &str,
+		interval: time_series::IntradayInterval,
+		output_size: time_series::OutputSize,
+	) -> Result<CryptoIntradaySeries, Error> {
+		let function = time_series::Function::CryptoIntraDay(interval);
+		let params = digital_currency_params(&function, symbol, market, Some(output_size));
+		self.api_call(function.to_string(), &params, |response| {
+			digital_currency::parser::parse_intraday(&function, response)
+		})
+	}
+
+	/// Retrieve daily prices of the digital currency `symbol`, both in the market currency
+	/// `market` and in USD.
+	pub fn get_digital_currency_daily(
+		&self,
+		symbol: &str,
+		market: &str,
+	) -> Result<DigitalCurrencySeries, Error> {
+		let function = time_series::Function::DigitalCurrencyDaily;
+		self.get_digital_currency(&function, symbol, market)
+	}
+
+	/// Retrieve weekly prices of the digital currency `symbol`, both in the market currency
+	/// `market` and in USD.
+	pub fn get_digital_currency_weekly(
+		&self,
+		symbol: &str,
+		market: &str,
+	) -> Result<DigitalCurrencySeries, Error> {
+		let function = time_series::Function::DigitalCurrencyWeekly;
+		self.get_digital_currency(&function, symbol, market)
+	}
+
+	/// Retrieve monthly prices of the digital currency `symbol`, both in the market currency
+	/// `market` and in USD.
+	pub fn get_digital_currency_monthly(
+		&self,
+		symbol: &str,
+		market: &str,
+	) -> Result<DigitalCurrencySeries, Error> {
+		let function = time_series::Function::DigitalCurrencyMonthly;
+		self.get_digital_currency(&function, symbol, market)
+	}
+
+	/// Asynchronous version of `get_time_series_intraday`.
+	pub fn get_time_series_intraday_async(
+		&self,
+		symbol: &str,
+		interval: time_series::IntradayInterval,
+	) -> ClientFuture<time_series::TimeSeries> {
+		let function = time_series::Function::IntraDay(interval);
+		self.get_time_series_async(function, symbol)
+	}
+
+	/// Asynchronous version of `get_time_series_daily`.
+	pub fn get_time_series_daily_async(
+		&self,
+		symbol: &str,
+	) -> ClientFuture<time_series::TimeSeries> {
+		self.get_time_series_async(time_series::Function::Daily, symbol)
+	}
+
+	/// Asynchronous version of `get_time_series_weekly`.
+	pub fn get_time_series_weekly_async(
+		&self,
+		symbol: &str,
+	) -> ClientFuture<time_series::TimeSeries> {
+		self.get_time_series_async(time_series::Function::Weekly, symbol)
+	}
+
+	/// Asynchronous version of `get_time_series_monthly`.
+	pub fn get_time_series_monthly_async(
+		&self,
+		symbol: &str,
+	) -> ClientFuture<time_series::TimeSeries> {
+		self.get_time_series_async(time_series::Function::Monthly, symbol)
+	}
+
+	/// Asynchronous version of `get_exchange_rate`. The returned future must
+	/// be run on a tokio runtime.
+	pub fn get_exchange_rate_async(
+		&self,
+		from_currency_code: &str,
+		to_currency_code: &str,
+	) -> ClientFuture<exchange_rate::ExchangeRate> {
+		let function = "CURRENCY_EXCHANGE_RATE";
+		let params = vec![
+			("from_currency", from_currency_code),
+			("to_currency", to_currency_code),
+		];
+		Box::new(self.api_call_async(function, &params, |body| exchange_rate::parser::parse(body)))
+	}
+
+	/// Asynchronous version of `get_fx_intraday`.
+	pub fn get_fx_intraday_async(
+		&self,
+		from_symbol: &str,
+		to_symbol: &str,
+		interval: time_series::IntradayInterval,
+		output_size: time_series::OutputSize,
+	) -> ClientFuture<time_series::FxTimeSeries> {
+		let function = time_series::Function::FxIntraDay(interval);
+		self.get_fx_time_series_async(function, from_symbol, to_symbol, Some(output_size))
+	}
+
+	/// Asynchronous version of `get_fx_daily`.
+	pub fn get_fx_daily_async(
+		&self,
+		from_symbol: &str,
+		to_symbol: &str,
+		output_size: time_series::OutputSize,
+	) -> ClientFuture<time_series::FxTimeSeries> {
+		let function = time_series::Function::FxDaily;
+		self.get_fx_time_series_async(function, from_symbol, to_symbol, Some(output_size))
+	}
+
+	/// Asynchronous version of `get_fx_weekly`.
+	pub fn get_fx_weekly_async(
+		&self,
+		from_symbol: &str,
+		to_symbol: &str,
+	) -> ClientFuture<time_series::FxTimeSeries> {
+		let function = time_series::Function::FxWeekly;
+		self.get_fx_time_series_async(function, from_symbol, to_symbol, None)
+	}
+
+	/// Asynchronous version of `get_fx_monthly`.
+	pub fn get_fx_monthly_async(
+		&self,
+		from_symbol: &str,
+		to_symbol: &str,
+	) -> ClientFuture<time_series::FxTimeSeries> {
+		let function = time_series::Function::FxMonthly;
+		self.get_fx_time_series_async(function, from_symbol, to_symbol, None)
+	}
+
+	/// Asynchronous version of `get_crypto_intraday`.
+	pub fn get_crypto_intraday_async(
+		&self,
+		symbol: &str,
+		market: &str,
+		interval: time_series::IntradayInterval,
+		output_size: time_series::OutputSize,
+	) -> ClientFuture<CryptoIntradaySeries> {
+		let function = time_series::Function::CryptoIntraDay(interval);
+		let params = digital_currency_params(&function, symbol, market, Some(output_size));
+		Box::new(
+			self.api_call_async(function.to_string(), &params, move |body| {
+				digital_currency::parser::parse_intraday(&function, body)
+			}),
+		)
+	}
+
+	/// Asynchronous version of `get_digital_currency_daily`.
+	pub fn get_digital_currency_daily_async(
+		&self,
+		symbol: &str,
+		market: &str,
+	) -> ClientFuture<DigitalCurrencySeries> {
+		let function = time_series::Function::DigitalCurrencyDaily;
+		self.get_digital_currency_async(function, symbol, market)
+	}
+
+	/// Asynchronous version of `get_digital_currency_weekly`.
+	pub fn get_digital_currency_weekly_async(
+		&self,
+		symbol: &str,
+		market: &str,
+	) -> ClientFuture<DigitalCurrencySeries> {
+		let function = time_series::Function::DigitalCurrencyWeekly;
+		self.get_digital_currency_async(function, symbol, market)
+	}
+
+	/// Asynchronous version of `get_digital_currency_monthly`.
+	pub fn get_digital_currency_monthly_async(
+		&self,
+		symbol: &str,
+		market: &str,
+	) -> ClientFuture<DigitalCurrencySeries> {
+		let function = time_series::Function::DigitalCurrencyMonthly;
+		self.get_digital_currency_async(function, symbol, market)
 	}
 
 	fn get_time_series(
@@ -115,39 +399,274 @@ impl Client {
 		symbol: &str,
 	) -> Result<time_series::TimeSeries, Error> {
 		let mut params = vec![("symbol", symbol)];
-		if let time_series::Function::IntraDay(interval) = function {
+		if let Some(interval) = function.interval() {
 			params.push(("interval", interval.to_string()));
 		}
-		let response = self.api_call(function.to_string(), &params)?;
-		let result = time_series::parser::parse(function, response).map_err(|error| {
-			Error::ParsingError {
-				error: error.compat(),
-			}
-		})?;
-		Ok(result)
+		self.api_call(function.to_string(), &params, |response| {
+			time_series::parser::parse(function, response)
+		})
 	}
 
-	fn api_call(
+	fn get_time_series_async(
 		&self,
-		function: &str,
-		params: &[(&str, &str)],
-	) -> Result<reqwest::Response, Error> {
+		function: time_series::Function,
+		symbol: &str,
+	) -> ClientFuture<time_series::TimeSeries> {
+		let mut params = vec![("symbol", symbol)];
+		if let Some(interval) = function.interval() {
+			params.push(("interval", interval.to_string()));
+		}
+		Box::new(
+			self.api_call_async(function.to_string(), &params, move |body| {
+				time_series::parser::parse(&function, body)
+			}),
+		)
+	}
+
+	fn get_fx_time_series(
+		&self,
+		function: &time_series::Function,
+		from_symbol: &str,
+		to_symbol: &str,
+		output_size: Option<time_series::OutputSize>,
+	) -> Result<time_series::FxTimeSeries, Error> {
+		let params = fx_params(function, from_symbol, to_symbol, output_size);
+		self.api_call(function.to_string(), &params, |response| {
+			time_series::parser::parse_fx(function, response)
+		})
+	}
+
+	fn get_fx_time_series_async(
+		&self,
+		function: time_series::Function,
+		from_symbol: &str,
+		to_symbol: &str,
+		output_size: Option<time_series::OutputSize>,
+	) -> ClientFuture<time_series::FxTimeSeries> {
+		let params = fx_params(&function, from_symbol, to_symbol, output_size);
+		Box::new(
+			self.api_call_async(function.to_string(), &params, move |body| {
+				time_series::parser::parse_fx(&function, body)
+			}),
+		)
+	}
+
+	fn get_digital_currency(
+		&self,
+		function: &time_series::Function,
+		symbol: &str,
+		market: &str,
+	) -> Result<DigitalCurrencySeries, Error> {
+		let params = digital_currency_params(function, symbol, market, None);
+		self.api_call(function.to_string(), &params, |response| {
+			digital_currency::parser::parse(function, response)
+		})
+	}
+
+	fn get_digital_currency_async(
+		&self,
+		function: time_series::Function,
+		symbol: &str,
+		market: &str,
+	) -> ClientFuture<DigitalCurrencySeries> {
+		let params = digital_currency_params(&function, symbol, market, None);
+		Box::new(
+			self.api_call_async(function.to_string(), &params, move |body| {
+				digital_currency::parser::parse(&function, body)
+			}),
+		)
+	}
+
+	/// Call the API, recording the call once its response is parsed by `parse`.
+	fn api_call<T, F>(&self, function: &str, params: &[(&str, &str)], parse: F) -> Result<T, Error>
+	where
+		F: FnOnce(reqwest::Response) -> Result<T, failure::Error>,
+	{
 		let mut query = vec![("function", function), ("apikey", &self.key)];
 		query.extend(params);
-		let response = self
+		let start = Instant::now();
+		let result = self
 			.client
-			.get(URL_ENDPOINT)
+			.get(&self.endpoint)
 			.query(&query)
 			.send()
-			.map_err(|error| Error::ConnectionError {
-				error: failure::Error::from(error).compat(),
-			})?;
-		let status = response.status();
-		if status != reqwest::StatusCode::OK {
-			return Err(Error::ServerError {
-				code: status.as_u16(),
+			.map_err(connection_error)
+			.and_then(|response| {
+				let status = response.status();
+				if status != reqwest::StatusCode::OK {
+					return Err(Error::ServerError {
+						code: status.as_u16(),
+					});
+				}
+				parse(response).map_err(parsing_error)
 			});
+		metrics::observe(function, &metrics::pair_label(params), start, &result);
+		result
+	}
+
+	/// Call the API, returning a future of the response body parsed by
+	/// `parse`. The call is recorded once the body is parsed.
+	fn api_call_async<T, F>(
+		&self,
+		function: &str,
+		params: &[(&str, &str)],
+		parse: F,
+	) -> impl Future<Item = T, Error = Error>
+	where
+		F: FnOnce(&[u8]) -> Result<T, failure::Error>,
+	{
+		let mut query = vec![("function", function), ("apikey", &self.key)];
+		query.extend(params);
+		let function = function.to_owned();
+		let pair = metrics::pair_label(params);
+		let start = Instant::now();
+		self.async_client
+			.get(&self.endpoint)
+			.query(&query)
+			.send()
+			.map_err(connection_error)
+			.and_then(|response| {
+				let status = response.status();
+				if status != reqwest::StatusCode::OK {
+					return Either::A(future::err(Error::ServerError {
+						code: status.as_u16(),
+					}));
+				}
+				Either::B(response.into_body().concat2().map_err(connection_error))
+			})
+			.and_then(move |body| parse(&body[..]).map_err(parsing_error))
+			.then(move |result| {
+				metrics::observe(&function, &pair, start, &result);
+				result
+			})
+	}
+}
+
+fn fx_params<'a>(
+	function: &time_series::Function,
+	from_symbol: &'a str,
+	to_symbol: &'a str,
+	output_size: Option<time_series::OutputSize>,
+) -> Vec<(&'static str, &'a str)> {
+	let mut params = vec![("from_symbol", from_symbol), ("to_symbol", to_symbol)];
+	if let Some(interval) = function.interval() {
+		params.push(("interval", interval.to_string()));
+	}
+	if let Some(output_size) = output_size {
+		params.push(("outputsize", output_size.to_string()));
+	}
+	params
+}
+
+fn digital_currency_params<'a>(
+	function: &time_series::Function,
+	symbol: &'a str,
+	market: &'a str,
+	output_size: Option<time_series::OutputSize>,
+) -> Vec<(&'static str, &'a str)> {
+	let mut params = vec![("symbol", symbol), ("market", market)];
+	if let Some(interval) = function.interval() {
+		params.push(("interval", interval.to_string()));
+	}
+	if let Some(output_size) = output_size {
+		params.push(("outputsize", output_size.to_string()));
+	}
+	params
+}
+
+fn connection_error(error: reqwest::Error) -> Error {
+	Error::ConnectionError {
+		error: failure::Error::from(error).compat(),
+	}
+}
+
+/// The API reports the throttling of the key in the response body
+fn parsing_error(error: failure::Error) -> Error {
+	match error.downcast::<exchange_rate::Throttled>() {
+		Ok(throttled) => Error::RateLimited {
+			message: throttled.0,
+		},
+		Err(error) => Error::ParsingError {
+			error: error.compat(),
+		},
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use hyper::service::service_fn;
+	use hyper::{Body, Response, Server};
+	use std::time::Instant;
+	use tokio::runtime::Runtime;
+	use tokio::timer::Delay;
+
+	/// Start a mock of the API on a local port, answering `body` to every
+	/// request after `delay`. Returns the endpoint to query.
+	fn mock_api(runtime: &mut Runtime, body: &'static [u8], delay: Duration) -> String {
+		let addr = ([127, 0, 0, 1], 0).into();
+		let server = Server::bind(&addr).serve(move || {
+			service_fn(move |_req| {
+				Delay::new(Instant::now() + delay).map(move |_| Response::new(Body::from(body)))
+			})
+		});
+		let endpoint = format!("http://{}/query", server.local_addr());
+		runtime.spawn(server.map_err(|e| panic!("mock API error: {}", e)));
+		endpoint
+	}
+
+	fn client(endpoint: String, timeout: Duration) -> Client {
+		Client::with_endpoint("demo", &endpoint, timeout)
+	}
+
+	#[test]
+	fn concurrent_requests() {
+		let mut runtime = Runtime::new().unwrap();
+		let body = include_bytes!("../tests/json/currency_exchange_rate.json");
+		let endpoint = mock_api(&mut runtime, body, Duration::from_millis(500));
+		let client = client(endpoint, Duration::from_secs(10));
+
+		let start = Instant::now();
+		let queries: Vec<_> = (0..4)
+			.map(|_| client.get_exchange_rate_async("EUR", "USD"))
+			.collect();
+		let rates = runtime.block_on(future::join_all(queries)).unwrap();
+		assert_eq!(rates.len(), 4);
+		assert!(rates.iter().all(|r| r.rate == 1.16665014));
+		// the requests are in flight at the same time
+		assert!(start.elapsed() < Duration::from_millis(1500));
+	}
+
+	#[test]
+	fn request_timeout() {
+		let mut runtime = Runtime::new().unwrap();
+		let body = include_bytes!("../tests/json/currency_exchange_rate.json");
+		let endpoint = mock_api(&mut runtime, body, Duration::from_secs(5));
+		let client = client(endpoint, Duration::from_millis(200));
+
+		let start = Instant::now();
+		match runtime.block_on(client.get_exchange_rate_async("EUR", "USD")) {
+			Err(Error::ConnectionError { .. }) => {}
+			r => panic!("unexpected {:?}", r),
+		}
+		assert!(start.elapsed() < Duration::from_secs(5));
+	}
+
+	#[test]
+	fn rate_limited() {
+		let mut runtime = Runtime::new().unwrap();
+		let body = include_bytes!("../tests/json/throttled_note.json");
+		let endpoint = mock_api(&mut runtime, body, Duration::from_millis(0));
+		let client = client(endpoint, Duration::from_secs(10));
+
+		match runtime.block_on(client.get_exchange_rate_async("EUR", "USD")) {
+			Err(Error::RateLimited { message }) => assert!(message.contains("5 calls per minute")),
+			r => panic!("unexpected {:?}", r),
 		}
-		Ok(response)
+		// recorded as throttled once the body is parsed, not as a success
+		assert_eq!(
+			metrics::errors("CURRENCY_EXCHANGE_RATE", "EUR2USD", "throttled"),
+			1
+		);
 	}
 }
@@ -15,7 +15,8 @@
 use chrono::prelude::*;
 use chrono_tz::Tz;
 use failure::{err_msg, Error};
-use serde::de::{self, Deserialize, Deserializer};
+use serde::de::{self, Deserializer};
+use serde::Deserialize;
 use std::fmt::Display;
 use std::str::FromStr;
 
@@ -32,6 +33,24 @@ where
 	T::from_str(&s).map_err(de::Error::custom)
 }
 
+/// Deserialize a number the API sends either as a string or as a number.
+pub(crate) fn from_str_or_number<'de, D>(deserializer: D) -> Result<f64, D::Error>
+where
+	D: Deserializer<'de>,
+{
+	#[derive(Deserialize)]
+	#[serde(untagged)]
+	enum StrOrNumber {
+		Str(String),
+		Number(f64),
+	}
+
+	match StrOrNumber::deserialize(deserializer)? {
+		StrOrNumber::Str(s) => f64::from_str(&s).map_err(de::Error::custom),
+		StrOrNumber::Number(n) => Ok(n),
+	}
+}
+
 pub(crate) fn parse_date(value: &str, time_zone: Tz) -> Result<DateTime<Tz>, Error> {
 	if value.contains(':') {
 		let datetime = NaiveDateTime::parse_from_str(value, DATETIME_FORMAT)?;
@@ -0,0 +1,259 @@
+// Copyright 2019 The Gotts Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::exchange_rate::Currency;
+use chrono::DateTime;
+use chrono_tz::Tz;
+use serde::Deserialize;
+
+/// Represents a time series for a digital currency, quoted in a market currency.
+#[derive(Debug)]
+pub struct DigitalCurrencyTimeSeries<E> {
+	/// Digital currency the time series refers to.
+	pub currency: Currency,
+	/// Market currency the prices are quoted in.
+	pub market: Currency,
+	/// Date the information was last refreshed at.
+	pub last_refreshed: DateTime<Tz>,
+	/// Entries in the time series, sorted by ascending dates.
+	pub entries: Vec<E>,
+}
+
+/// Represents the daily, weekly or monthly prices of a digital currency, both
+/// in the market currency and in USD.
+#[derive(Debug, PartialEq)]
+pub struct DigitalCurrencyEntry {
+	/// Date.
+	pub date: DateTime<Tz>,
+	/// Open price, in the market currency.
+	pub open: f64,
+	/// High price, in the market currency.
+	pub high: f64,
+	/// Low price, in the market currency.
+	pub low: f64,
+	/// Close price, in the market currency.
+	pub close: f64,
+	/// Open price, in USD.
+	pub open_usd: f64,
+	/// High price, in USD.
+	pub high_usd: f64,
+	/// Low price, in USD.
+	pub low_usd: f64,
+	/// Close price, in USD.
+	pub close_usd: f64,
+	/// Trading volume, in the digital currency.
+	pub volume: f64,
+	/// Market capitalization, in USD.
+	pub market_cap_usd: f64,
+}
+
+/// Represents the intraday prices of a digital currency, in the market currency.
+#[derive(Debug, PartialEq)]
+pub struct CryptoIntradayEntry {
+	/// Date.
+	pub date: DateTime<Tz>,
+	/// Open price.
+	pub open: f64,
+	/// High price.
+	pub high: f64,
+	/// Low price.
+	pub low: f64,
+	/// Close price.
+	pub close: f64,
+	/// Trading volume, in the digital currency.
+	pub volume: f64,
+}
+
+pub(crate) mod parser {
+	use super::*;
+	use crate::deserialize::{from_str, from_str_or_number};
+	use crate::time_series::parser::{metadata_value, parse_raw, RawTimeSeries};
+	use crate::time_series::Function;
+	use failure::{format_err, Error};
+	use std::collections::HashMap;
+	use std::io::Read;
+
+	#[derive(Debug, Deserialize)]
+	struct CryptoIntradayEntryHelper {
+		#[serde(rename = "1. open", deserialize_with = "from_str")]
+		pub open: f64,
+		#[serde(rename = "2. high", deserialize_with = "from_str")]
+		pub high: f64,
+		#[serde(rename = "3. low", deserialize_with = "from_str")]
+		pub low: f64,
+		#[serde(rename = "4. close", deserialize_with = "from_str")]
+		pub close: f64,
+		#[serde(rename = "5. volume", deserialize_with = "from_str_or_number")]
+		pub volume: f64,
+	}
+
+	fn header<E>(raw: &RawTimeSeries<E>) -> Result<(Currency, Currency), Error> {
+		let currency = Currency {
+			code: metadata_value(&raw.metadata, "2. Digital Currency Code")?,
+			name: metadata_value(&raw.metadata, "3. Digital Currency Name")?,
+		};
+		let market = Currency {
+			code: metadata_value(&raw.metadata, "4. Market Code")?,
+			name: metadata_value(&raw.metadata, "5. Market Name")?,
+		};
+		Ok((currency, market))
+	}
+
+	/// The entries of the daily, weekly and monthly series are keyed after the
+	/// market currency, i.e. "1a. open (CNY)".
+	fn entry_value(entry: &HashMap<String, String>, key: &str) -> Result<f64, Error> {
+		entry
+			.get(key)
+			.ok_or_else(|| format_err!("missing {}", key))?
+			.parse()
+			.map_err(|_| format_err!("error parsing {}", key))
+	}
+
+	pub(crate) fn parse(
+		function: &Function,
+		reader: impl Read,
+	) -> Result<DigitalCurrencyTimeSeries<DigitalCurrencyEntry>, Error> {
+		let raw = parse_raw::<HashMap<String, String>>(function, reader)?;
+		let (currency, market) = header(&raw)?;
+
+		let code = &market.code;
+		let mut entries = vec![];
+		for (date, v) in raw.entries.iter() {
+			entries.push(DigitalCurrencyEntry {
+				date: *date,
+				open: entry_value(v, &format!("1a. open ({})", code))?,
+				high: entry_value(v, &format!("2a. high ({})", code))?,
+				low: entry_value(v, &format!("3a. low ({})", code))?,
+				close: entry_value(v, &format!("4a. close ({})", code))?,
+				open_usd: entry_value(v, "1b. open (USD)")?,
+				high_usd: entry_value(v, "2b. high (USD)")?,
+				low_usd: entry_value(v, "3b. low (USD)")?,
+				close_usd: entry_value(v, "4b. close (USD)")?,
+				volume: entry_value(v, "5. volume")?,
+				market_cap_usd: entry_value(v, "6. market cap (USD)")?,
+			});
+		}
+
+		Ok(DigitalCurrencyTimeSeries {
+			currency,
+			market,
+			last_refreshed: raw.last_refreshed,
+			entries,
+		})
+	}
+
+	pub(crate) fn parse_intraday(
+		function: &Function,
+		reader: impl Read,
+	) -> Result<DigitalCurrencyTimeSeries<CryptoIntradayEntry>, Error> {
+		let raw = parse_raw::<CryptoIntradayEntryHelper>(function, reader)?;
+		let (currency, market) = header(&raw)?;
+
+		let entries = raw
+			.entries
+			.into_iter()
+			.map(|(date, v)| CryptoIntradayEntry {
+				date,
+				open: v.open,
+				high: v.high,
+				low: v.low,
+				close: v.close,
+				volume: v.volume,
+			})
+			.collect();
+
+		Ok(DigitalCurrencyTimeSeries {
+			currency,
+			market,
+			last_refreshed: raw.last_refreshed,
+			entries,
+		})
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::deserialize::parse_date;
+	use crate::time_series::{Function, IntradayInterval};
+	use chrono_tz::UTC;
+	use std::io::BufReader;
+
+	#[test]
+	fn parse_daily() {
+		let data: &[u8] = include_bytes!("../tests/json/digital_currency_daily.json");
+		let time_series = parser::parse(&Function::DigitalCurrencyDaily, BufReader::new(data))
+			.expect("failed to parse entries");
+		assert_eq!(time_series.currency.code, "BTC");
+		assert_eq!(time_series.market.name, "Chinese Yuan");
+		assert_eq!(time_series.entries.len(), 2);
+		assert_eq!(
+			time_series.entries[1],
+			DigitalCurrencyEntry {
+				date: parse_date("2019-07-05", UTC).unwrap(),
+				open: 77640.18,
+				high: 78312.54,
+				low: 74506.31,
+				close: 75712.47,
+				open_usd: 11293.10,
+				high_usd: 11390.90,
+				low_usd: 10837.27,
+				close_usd: 11012.66,
+				volume: 5612.96,
+				market_cap_usd: 61813454.63,
+			}
+		);
+
+		// the series of another function is missing
+		let data: &[u8] = include_bytes!("../tests/json/digital_currency_daily.json");
+		assert!(parser::parse(&Function::DigitalCurrencyWeekly, BufReader::new(data)).is_err());
+	}
+
+	#[test]
+	fn parse_intraday() {
+		let data: &[u8] = include_bytes!("../tests/json/crypto_intraday_5min.json");
+		let time_series = parser::parse_intraday(
+			&Function::CryptoIntraDay(IntradayInterval::FiveMinutes),
+			BufReader::new(data),
+		)
+		.expect("failed to parse entries");
+		assert_eq!(time_series.currency.code, "ETH");
+		assert_eq!(time_series.market.code, "USD");
+		assert_eq!(
+			time_series.last_refreshed,
+			parse_date("2019-07-05 14:55:00", UTC).unwrap()
+		);
+		assert_eq!(
+			time_series.entries,
+			vec![
+				CryptoIntradayEntry {
+					date: parse_date("2019-07-05 14:50:00", UTC).unwrap(),
+					open: 286.41,
+					high: 287.02,
+					low: 286.1,
+					close: 286.77,
+					volume: 1534.0,
+				},
+				CryptoIntradayEntry {
+					date: parse_date("2019-07-05 14:55:00", UTC).unwrap(),
+					open: 286.77,
+					high: 287.5,
+					low: 286.52,
+					close: 287.31,
+					volume: 918.0,
+				},
+			]
+		);
+	}
+}
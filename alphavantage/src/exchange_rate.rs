@@ -15,6 +15,7 @@
 use chrono::{DateTime, Utc};
 use chrono_tz::Tz;
 use failure::format_err;
+use failure_derive::Fail;
 use serde::{Deserialize, Serialize};
 
 /// Represents a currency.
@@ -69,6 +70,12 @@ pub struct ExchangeRateResult {
 	pub date: DateTime<Utc>,
 }
 
+/// The API call was refused because the API key is over its call frequency
+/// or its daily limit, reported by the API as a "Note" or an "Information".
+#[derive(Debug, Clone, Eq, PartialEq, Fail)]
+#[fail(display = "throttled: {}", _0)]
+pub struct Throttled(pub String);
+
 pub(crate) mod parser {
 	use super::*;
 	use crate::deserialize::{from_str, parse_date};
@@ -79,6 +86,10 @@ pub(crate) mod parser {
 	struct ExchangeRateHelper {
 		#[serde(rename = "Error Message")]
 		error: Option<String>,
+		#[serde(rename = "Note")]
+		note: Option<String>,
+		#[serde(rename = "Information")]
+		information: Option<String>,
 		#[serde(rename = "Realtime Currency Exchange Rate")]
 		data: Option<RealtimeExchangeRate>,
 	}
@@ -108,9 +119,11 @@ pub(crate) mod parser {
 			return Err(format_err!("received error: {}", error));
 		}
 
-		let data = helper
-			.data
-			.ok_or_else(|| err_msg("missing exchange rate data"))?;
+		let data = match (helper.data, helper.note.or(helper.information)) {
+			(Some(data), _) => data,
+			(None, Some(message)) => return Err(Throttled(message).into()),
+			(None, None) => return Err(err_msg("missing exchange rate data")),
+		};
 
 		let time_zone: Tz = data
 			.time_zone
@@ -160,8 +173,25 @@ mod tests {
 					code: "USD".to_string(),
 				},
 				rate: 1.16665014,
-				date: parse_date("2018-06-23 10:27:49", UTC).unwrap(),
+				date: parse_date("2018-06-23 10:27:49", UTC)
+					.unwrap()
+					.with_timezone(&Utc),
 			}
 		);
 	}
+
+	#[test]
+	fn parse_throttled() {
+		let data: &[u8] = include_bytes!("../tests/json/throttled_note.json");
+		let error = parser::parse(BufReader::new(data)).unwrap_err();
+		let throttled = error.downcast_ref::<Throttled>().expect("not throttled");
+		assert!(throttled.0.contains("5 calls per minute"));
+
+		let data: &[u8] = include_bytes!("../tests/json/throttled_information.json");
+		let error = parser::parse(BufReader::new(data)).unwrap_err();
+		assert!(error.downcast_ref::<Throttled>().is_some());
+
+		let error = parser::parse(BufReader::new(&b"{}"[..])).unwrap_err();
+		assert!(error.downcast_ref::<Throttled>().is_none());
+	}
 }
@@ -23,16 +23,32 @@ extern crate chrono;
 extern crate chrono_tz;
 extern crate failure;
 extern crate failure_derive;
+extern crate futures;
+#[macro_use]
+extern crate lazy_static;
+#[macro_use]
+extern crate prometheus;
+#[cfg(feature = "mock")]
+extern crate hyper;
 extern crate reqwest;
 extern crate serde;
 extern crate serde_json;
+#[cfg(feature = "mock")]
+extern crate url;
 
 mod client;
 mod deserialize;
+mod metrics;
 
+pub mod digital_currency;
 pub mod exchange_rate;
+#[cfg(feature = "mock")]
+pub mod mock;
 pub mod time_series;
 
 pub use crate::client::Client;
-pub use crate::client::Error;
+pub use crate::client::{
+	ClientFuture, CryptoIntradaySeries, DigitalCurrencySeries, Error, DEFAULT_TIMEOUT_SECS,
+	URL_ENDPOINT,
+};
 pub use crate::exchange_rate::ExchangeRateResult;
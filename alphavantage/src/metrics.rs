@@ -0,0 +1,103 @@
+// Copyright 2019 The Gotts Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Metrics of the API calls, registered in the default prometheus registry.
+
+use crate::client::Error;
+use prometheus::{HistogramVec, IntCounterVec};
+use std::time::Instant;
+
+lazy_static! {
+	static ref REQUESTS: IntCounterVec = register_int_counter_vec!(
+		"gotts_oracle_vendor_requests_total",
+		"Requests to the Alpha Vantage API",
+		&["function", "pair"]
+	)
+	.unwrap();
+	static ref ERRORS: IntCounterVec = register_int_counter_vec!(
+		"gotts_oracle_vendor_errors_total",
+		"Failed requests to the Alpha Vantage API, on a connection error, an HTTP status error, a throttled call or an unparsable response",
+		&["function", "pair", "kind"]
+	)
+	.unwrap();
+	static ref LATENCY: HistogramVec = register_histogram_vec!(
+		"gotts_oracle_vendor_request_duration_seconds",
+		"Duration of the requests to the Alpha Vantage API",
+		&["function", "pair"]
+	)
+	.unwrap();
+}
+
+/// Pair of the API call, as `FROM2TO`, or the symbol of the stock.
+pub fn pair_label(params: &[(&str, &str)]) -> String {
+	let param = |names: &[&str]| {
+		params
+			.iter()
+			.find(|(name, _)| names.contains(name))
+			.map(|(_, value)| *value)
+	};
+	match (
+		param(&["from_currency", "from_symbol", "symbol"]),
+		param(&["to_currency", "to_symbol", "market"]),
+	) {
+		(Some(from), Some(to)) => format!("{}2{}", from, to),
+		(Some(symbol), None) => symbol.to_string(),
+		_ => String::new(),
+	}
+}
+
+/// Record an API call started at `start`.
+pub fn observe<T>(function: &str, pair: &str, start: Instant, result: &Result<T, Error>) {
+	REQUESTS.with_label_values(&[function, pair]).inc();
+	let elapsed = start.elapsed();
+	LATENCY
+		.with_label_values(&[function, pair])
+		.observe(elapsed.as_secs() as f64 + elapsed.subsec_nanos() as f64 / 1e9);
+	if let Err(e) = result {
+		let kind = match e {
+			Error::ConnectionError { .. } => "connection",
+			Error::ServerError { .. } => "status",
+			Error::RateLimited { .. } => "throttled",
+			Error::ParsingError { .. } => "parse",
+		};
+		ERRORS.with_label_values(&[function, pair, kind]).inc();
+	}
+}
+
+/// Count of the failed API calls of `kind`.
+#[cfg(test)]
+pub(crate) fn errors(function: &str, pair: &str, kind: &str) -> i64 {
+	ERRORS.with_label_values(&[function, pair, kind]).get()
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn pair_labels() {
+		let params = [("from_currency", "USD"), ("to_currency", "CNY")];
+		assert_eq!(pair_label(&params), "USD2CNY");
+		let params = [
+			("from_symbol", "EUR"),
+			("to_symbol", "USD"),
+			("interval", "5min"),
+		];
+		assert_eq!(pair_label(&params), "EUR2USD");
+		let params = [("symbol", "BTC"), ("market", "USD")];
+		assert_eq!(pair_label(&params), "BTC2USD");
+		assert_eq!(pair_label(&[("symbol", "MSFT")]), "MSFT");
+		assert_eq!(pair_label(&[]), "");
+	}
+}
@@ -0,0 +1,220 @@
+// Copyright 2019 The Gotts Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! A mock of the Alpha Vantage API, replaying recorded JSON responses, so
+//! that the oracle can be tested without network.
+//!
+//! A call is answered with the fixture named after its function and its
+//! parameter values, in the order they are sent, i.e.
+//! `CURRENCY_EXCHANGE_RATE.EUR.USD.json` for the exchange rate from EUR to
+//! USD, or else with the fixture named after its function only, i.e.
+//! `CURRENCY_EXCHANGE_RATE.json`. A fixture is answered with the HTTP status
+//! given as a last suffix of its name, if any, i.e.
+//! `CURRENCY_EXCHANGE_RATE.USD.GBP.503.json`, with 200 otherwise. Unknown
+//! calls are answered with an Alpha Vantage error message.
+
+use futures::sync::oneshot;
+use futures::Future;
+use hyper::server::conn::AddrIncoming;
+use hyper::service::service_fn_ok;
+use hyper::{rt, Body, Request, Response, Server, StatusCode};
+use std::collections::HashMap;
+use std::ffi::OsStr;
+use std::fs;
+use std::io;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread;
+use url::form_urlencoded;
+
+const UNKNOWN_CALL: &str = r#"{
+    "Error Message": "Invalid API call. Please retry or visit the documentation (https://www.alphavantage.co/documentation/) for CURRENCY_EXCHANGE_RATE."
+}"#;
+
+/// A recorded response
+#[derive(Debug, Clone)]
+pub struct MockResponse {
+	/// HTTP status code
+	pub status: u16,
+	/// Response body
+	pub body: Vec<u8>,
+}
+
+/// The recorded responses, by call
+#[derive(Debug, Clone, Default)]
+pub struct Fixtures {
+	responses: HashMap<String, MockResponse>,
+}
+
+impl Fixtures {
+	/// An empty set of fixtures
+	pub fn new() -> Fixtures {
+		Fixtures {
+			responses: HashMap::new(),
+		}
+	}
+
+	/// Load the `.json` fixtures of a directory, named after the calls they
+	/// answer and their HTTP status, if not 200.
+	pub fn from_dir<P: AsRef<Path>>(dir: P) -> io::Result<Fixtures> {
+		let mut fixtures = Fixtures::new();
+		for entry in fs::read_dir(dir)? {
+			let path = entry?.path();
+			if path.extension() != Some(OsStr::new("json")) {
+				continue;
+			}
+			if let Some(stem) = path.file_stem().and_then(|stem| stem.to_str()) {
+				let (call, status) = call_status(stem);
+				fixtures.insert(call, status, fs::read(&path)?);
+			}
+		}
+		Ok(fixtures)
+	}
+
+	/// Answer a call, i.e. "CURRENCY_EXCHANGE_RATE.EUR.USD", with a response.
+	pub fn insert<B: Into<Vec<u8>>>(&mut self, call: &str, status: u16, body: B) {
+		self.responses.insert(
+			call.to_owned(),
+			MockResponse {
+				status,
+				body: body.into(),
+			},
+		);
+	}
+
+	/// The response to a call, given as its function and parameter values
+	pub fn response(&self, function: &str, values: &[String]) -> MockResponse {
+		let mut call = function.to_owned();
+		for value in values {
+			call.push('.');
+			call.push_str(value);
+		}
+		self.responses
+			.get(&call)
+			.or_else(|| self.responses.get(function))
+			.cloned()
+			.unwrap_or_else(|| MockResponse {
+				status: 200,
+				body: UNKNOWN_CALL.as_bytes().to_vec(),
+			})
+	}
+
+	fn answer(&self, req: &Request<Body>) -> Response<Body> {
+		let query = req.uri().query().unwrap_or("");
+		let mut function = String::new();
+		let mut values = vec![];
+		for (name, value) in form_urlencoded::parse(query.as_bytes()) {
+			match name.as_ref() {
+				"function" => function = value.into_owned(),
+				"apikey" => {}
+				_ => values.push(value.into_owned()),
+			}
+		}
+
+		let response = self.response(&function, &values);
+		let mut builder = Response::builder();
+		builder
+			.status(StatusCode::from_u16(response.status).unwrap_or(StatusCode::OK))
+			.header("Content-Type", "application/json");
+		builder.body(response.body.into()).unwrap()
+	}
+}
+
+/// The call and the HTTP status of a fixture, from its file name without the
+/// extension, i.e. "CURRENCY_EXCHANGE_RATE.USD.GBP.503".
+fn call_status(stem: &str) -> (&str, u16) {
+	if let Some(dot) = stem.rfind('.') {
+		if let Ok(status) = StatusCode::from_bytes(stem[dot + 1..].as_bytes()) {
+			return (&stem[..dot], status.as_u16());
+		}
+	}
+	(stem, 200)
+}
+
+/// A mock Alpha Vantage API server, serving on its own thread until it's
+/// stopped or dropped.
+pub struct MockServer {
+	addr: SocketAddr,
+	requests: Arc<AtomicUsize>,
+	shutdown_sender: Option<oneshot::Sender<()>>,
+	thread: Option<thread::JoinHandle<()>>,
+}
+
+impl MockServer {
+	/// Start replaying the fixtures on `addr`, port 0 picking a free port.
+	pub fn start(addr: &SocketAddr, fixtures: Fixtures) -> io::Result<MockServer> {
+		let incoming = AddrIncoming::bind(addr)
+			.map_err(|e| io::Error::new(io::ErrorKind::Other, e.to_string()))?;
+		let addr = incoming.local_addr();
+		let fixtures = Arc::new(fixtures);
+		let requests = Arc::new(AtomicUsize::new(0));
+		let (tx, rx) = oneshot::channel::<()>();
+
+		let counter = requests.clone();
+		let server = Server::builder(incoming)
+			.serve(move || {
+				let fixtures = fixtures.clone();
+				let counter = counter.clone();
+				service_fn_ok(move |req| {
+					counter.fetch_add(1, Ordering::SeqCst);
+					fixtures.answer(&req)
+				})
+			})
+			.with_graceful_shutdown(rx)
+			.map_err(|e| eprintln!("mock Alpha Vantage server error: {}", e));
+		let thread = thread::Builder::new()
+			.name("mock_alphavantage".to_string())
+			.spawn(move || rt::run(server))?;
+
+		Ok(MockServer {
+			addr,
+			requests,
+			shutdown_sender: Some(tx),
+			thread: Some(thread),
+		})
+	}
+
+	/// The address the server listens on
+	pub fn addr(&self) -> SocketAddr {
+		self.addr
+	}
+
+	/// The endpoint to give to the client, see `Client::with_endpoint`
+	pub fn endpoint(&self) -> String {
+		format!("http://{}/query", self.addr)
+	}
+
+	/// Number of requests answered so far
+	pub fn requests(&self) -> usize {
+		self.requests.load(Ordering::SeqCst)
+	}
+
+	/// Stop the server, waiting for its thread to exit
+	pub fn stop(&mut self) {
+		if let Some(tx) = self.shutdown_sender.take() {
+			let _ = tx.send(());
+		}
+		if let Some(thread) = self.thread.take() {
+			let _ = thread.join();
+		}
+	}
+}
+
+impl Drop for MockServer {
+	fn drop(&mut self) {
+		self.stop();
+	}
+}
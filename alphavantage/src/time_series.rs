@@ -73,12 +73,66 @@ pub struct Entry {
 	pub volume: u64,
 }
 
+#[derive(Debug, Clone, Copy)]
+/// Represents the size of a daily or intraday time series.
+pub enum OutputSize {
+	/// The latest 100 entries.
+	Compact,
+	/// The full history, up to 20 years for the daily series.
+	Full,
+}
+
+impl OutputSize {
+	pub(crate) fn to_string(self) -> &'static str {
+		match self {
+			OutputSize::Compact => "compact",
+			OutputSize::Full => "full",
+		}
+	}
+}
+
+/// Represents a foreign exchange time series for a given currency pair.
+#[derive(Debug)]
+pub struct FxTimeSeries {
+	/// Currency the time series is quoted for.
+	pub from: String,
+	/// Currency the time series is quoted in.
+	pub to: String,
+	/// Date the information was last refreshed at.
+	pub last_refreshed: DateTime<Tz>,
+	/// Entries in the time series, sorted by ascending dates.
+	pub entries: Vec<FxEntry>,
+}
+
+/// Represents a set of exchange rates for a given period in the time series.
+#[derive(Debug, PartialEq)]
+pub struct FxEntry {
+	/// Date.
+	pub date: DateTime<Tz>,
+	/// Open rate.
+	pub open: f64,
+	/// High rate.
+	pub high: f64,
+	/// Low rate.
+	pub low: f64,
+	/// Close rate.
+	pub close: f64,
+}
+
 #[derive(Debug, Clone)]
 pub(crate) enum Function {
 	IntraDay(IntradayInterval),
 	Daily,
 	Weekly,
 	Monthly,
+	FxIntraDay(IntradayInterval),
+	FxDaily,
+	FxWeekly,
+	FxMonthly,
+	CryptoIntraDay(IntradayInterval),
+	DigitalCurrencyDaily,
+	DigitalCurrencyWeekly,
+	DigitalCurrencyMonthly,
 }
 
 impl Function {
@@ -89,6 +143,58 @@ impl Function {
 			Daily => "TIME_SERIES_DAILY",
 			Weekly => "TIME_SERIES_WEEKLY",
 			Monthly => "TIME_SERIES_MONTHLY",
+			FxIntraDay(_) => "FX_INTRADAY",
+			FxDaily => "FX_DAILY",
+			FxWeekly => "FX_WEEKLY",
+			FxMonthly => "FX_MONTHLY",
+			CryptoIntraDay(_) => "CRYPTO_INTRADAY",
+			DigitalCurrencyDaily => "DIGITAL_CURRENCY_DAILY",
+			DigitalCurrencyWeekly => "DIGITAL_CURRENCY_WEEKLY",
+			DigitalCurrencyMonthly => "DIGITAL_CURRENCY_MONTHLY",
+		}
+	}
+
+	pub(crate) fn interval(&self) -> Option<IntradayInterval> {
+		match self {
+			Function::IntraDay(interval)
+			| Function::FxIntraDay(interval)
+			| Function::CryptoIntraDay(interval) => Some(*interval),
+			_ => None,
+		}
+	}
+
+	/// Metadata keys of the last refreshed date and of the time zone
+	fn metadata_keys(&self) -> (&'static str, &'static str) {
+		use self::Function::*;
+		match self {
+			IntraDay(_) => ("3. Last Refreshed", "6. Time Zone"),
+			Daily => ("3. Last Refreshed", "5. Time Zone"),
+			Weekly | Monthly => ("3. Last Refreshed", "4. Time Zone"),
+			FxIntraDay(_) => ("4. Last Refreshed", "7. Time Zone"),
+			FxDaily => ("5. Last Refreshed", "6. Time Zone"),
+			FxWeekly | FxMonthly => ("4. Last Refreshed", "5. Time Zone"),
+			CryptoIntraDay(_) => ("6. Last Refreshed", "9. Time Zone"),
+			DigitalCurrencyDaily | DigitalCurrencyWeekly | DigitalCurrencyMonthly => {
+				("6. Last Refreshed", "7. Time Zone")
+			}
+		}
+	}
+
+	fn time_series_key(&self) -> String {
+		use self::Function::*;
+		match self {
+			IntraDay(interval) => format!("Time Series ({})", interval.to_string()),
+			Daily => "Time Series (Daily)".to_string(),
+			Weekly => "Weekly Time Series".to_string(),
+			Monthly => "Monthly Time Series".to_string(),
+			FxIntraDay(interval) => format!("Time Series FX ({})", interval.to_string()),
+			FxDaily => "Time Series FX (Daily)".to_string(),
+			FxWeekly => "Time Series FX (Weekly)".to_string(),
+			FxMonthly => "Time Series FX (Monthly)".to_string(),
+			CryptoIntraDay(interval) => format!("Time Series Crypto ({})", interval.to_string()),
+			DigitalCurrencyDaily => "Time Series (Digital Currency Daily)".to_string(),
+			DigitalCurrencyWeekly => "Time Series (Digital Currency Weekly)".to_string(),
+			DigitalCurrencyMonthly => "Time Series (Digital Currency Monthly)".to_string(),
 		}
 	}
 }
@@ -96,8 +202,10 @@ impl Function {
 pub(crate) mod parser {
 	use super::*;
 	use crate::deserialize::{from_str, parse_date};
+	use crate::exchange_rate::Throttled;
 	use chrono_tz::Tz;
 	use failure::{err_msg, Error};
+	use serde::de::DeserializeOwned;
 	use std::collections::HashMap;
 	use std::io::Read;
 
@@ -116,35 +224,57 @@ pub(crate) mod parser {
 	}
 
 	#[derive(Debug, Deserialize)]
-	pub struct TimeSeriesHelper {
+	struct FxEntryHelper {
+		#[serde(rename = "1. open", deserialize_with = "from_str")]
+		pub open: f64,
+		#[serde(rename = "2. high", deserialize_with = "from_str")]
+		pub high: f64,
+		#[serde(rename = "3. low", deserialize_with = "from_str")]
+		pub low: f64,
+		#[serde(rename = "4. close", deserialize_with = "from_str")]
+		pub close: f64,
+	}
+
+	#[derive(Debug, Deserialize)]
+	pub struct TimeSeriesHelper<E> {
 		#[serde(rename = "Error Message")]
 		error: Option<String>,
+		#[serde(rename = "Note")]
+		note: Option<String>,
+		#[serde(rename = "Information")]
+		information: Option<String>,
 		#[serde(rename = "Meta Data")]
 		metadata: Option<HashMap<String, String>>,
 		#[serde(flatten)]
-		time_series: Option<HashMap<String, HashMap<String, EntryHelper>>>,
+		time_series: Option<HashMap<String, HashMap<String, E>>>,
 	}
 
-	pub(crate) fn parse(function: &Function, reader: impl Read) -> Result<TimeSeries, Error> {
-		let helper: TimeSeriesHelper = serde_json::from_reader(reader)?;
+	/// A time series as returned by the API, before it's typed.
+	pub(crate) struct RawTimeSeries<E> {
+		pub metadata: HashMap<String, String>,
+		pub last_refreshed: DateTime<Tz>,
+		/// Entries sorted by ascending dates
+		pub entries: Vec<(DateTime<Tz>, E)>,
+	}
+
+	pub(crate) fn parse_raw<E: DeserializeOwned>(
+		function: &Function,
+		reader: impl Read,
+	) -> Result<RawTimeSeries<E>, Error> {
+		let helper: TimeSeriesHelper<E> = serde_json::from_reader(reader)?;
 
 		if let Some(error) = helper.error {
 			return Err(format_err!("received error: {}", error));
 		}
 
-		let metadata = helper.metadata.ok_or_else(|| err_msg("missing metadata"))?;
-
-		let symbol = metadata
-			.get("2. Symbol")
-			.ok_or_else(|| err_msg("missing symbol"))?
-			.to_string();
-
-		let time_zone_key = match function {
-			Function::IntraDay(_) => "6. Time Zone",
-			Function::Daily => "5. Time Zone",
-			Function::Weekly | Function::Monthly => "4. Time Zone",
+		let metadata = match (helper.metadata, helper.note.or(helper.information)) {
+			(Some(metadata), _) => metadata,
+			(None, Some(message)) => return Err(Throttled(message).into()),
+			(None, None) => return Err(err_msg("missing metadata")),
 		};
 
+		let (last_refreshed_key, time_zone_key) = function.metadata_keys();
+
 		let time_zone: Tz = metadata
 			.get(time_zone_key)
 			.ok_or_else(|| err_msg("missing time zone"))?
@@ -152,45 +282,87 @@ pub(crate) mod parser {
 			.map_err(|_| err_msg("error parsing time zone"))?;
 
 		let last_refreshed = metadata
-			.get("3. Last Refreshed")
+			.get(last_refreshed_key)
 			.ok_or_else(|| err_msg("missing last refreshed"))
 			.map(|v| parse_date(v, time_zone))??;
 
-		let time_series_key = match function {
-			Function::IntraDay(interval) => format!("Time Series ({})", interval.to_string()),
-			Function::Daily => "Time Series (Daily)".to_string(),
-			Function::Weekly => "Weekly Time Series".to_string(),
-			Function::Monthly => "Monthly Time Series".to_string(),
-		};
-
-		let time_series_map = helper
+		let mut time_series_map = helper
 			.time_series
 			.ok_or_else(|| err_msg("missing time series"))?;
 
 		let time_series = time_series_map
-			.get(&time_series_key)
+			.remove(&function.time_series_key())
 			.ok_or_else(|| err_msg("missing requested time series"))?;
 
-		let mut entries: Vec<Entry> = vec![];
+		let mut entries = vec![];
+		for (d, v) in time_series {
+			entries.push((parse_date(&d, time_zone)?, v));
+		}
+		entries.sort_by_key(|e| e.0);
+
+		Ok(RawTimeSeries {
+			metadata,
+			last_refreshed,
+			entries,
+		})
+	}
+
+	pub(crate) fn metadata_value(
+		metadata: &HashMap<String, String>,
+		key: &str,
+	) -> Result<String, Error> {
+		metadata
+			.get(key)
+			.cloned()
+			.ok_or_else(|| format_err!("missing {}", key))
+	}
+
+	pub(crate) fn parse(function: &Function, reader: impl Read) -> Result<TimeSeries, Error> {
+		let raw = parse_raw::<EntryHelper>(function, reader)?;
+		let symbol = metadata_value(&raw.metadata, "2. Symbol")?;
 
-		for (d, v) in time_series.iter() {
-			let date = parse_date(d, time_zone)?;
-			let entry = Entry {
+		let entries = raw
+			.entries
+			.into_iter()
+			.map(|(date, v)| Entry {
 				date,
 				open: v.open,
 				high: v.high,
 				low: v.low,
 				close: v.close,
 				volume: v.volume,
-			};
-			entries.push(entry);
-		}
-
-		entries.sort_by_key(|e| e.date);
+			})
+			.collect();
 
 		let time_series = TimeSeries {
 			symbol,
-			last_refreshed,
+			last_refreshed: raw.last_refreshed,
+			entries,
+		};
+		Ok(time_series)
+	}
+
+	pub(crate) fn parse_fx(function: &Function, reader: impl Read) -> Result<FxTimeSeries, Error> {
+		let raw = parse_raw::<FxEntryHelper>(function, reader)?;
+		let from = metadata_value(&raw.metadata, "2. From Symbol")?;
+		let to = metadata_value(&raw.metadata, "3. To Symbol")?;
+
+		let entries = raw
+			.entries
+			.into_iter()
+			.map(|(date, v)| FxEntry {
+				date,
+				open: v.open,
+				high: v.high,
+				low: v.low,
+				close: v.close,
+			})
+			.collect();
+
+		let time_series = FxTimeSeries {
+			from,
+			to,
+			last_refreshed: raw.last_refreshed,
 			entries,
 		};
 		Ok(time_series)
@@ -202,6 +374,7 @@ mod tests {
 	use super::*;
 	use crate::deserialize::parse_date;
 	use chrono_tz::US::Eastern;
+	use chrono_tz::UTC;
 	use std::io::BufReader;
 
 	#[test]
@@ -326,4 +499,76 @@ mod tests {
 			}
 		);
 	}
+
+	#[test]
+	fn parse_fx_intraday() {
+		let data: &[u8] = include_bytes!("../tests/json/fx_intraday_5min.json");
+		let time_series = parser::parse_fx(
+			&Function::FxIntraDay(IntradayInterval::FiveMinutes),
+			BufReader::new(data),
+		)
+		.expect("failed to parse entries");
+		assert_eq!(time_series.from, "EUR");
+		assert_eq!(time_series.to, "USD");
+		assert_eq!(
+			time_series.last_refreshed,
+			parse_date("2019-07-05 14:55:00", UTC).unwrap()
+		);
+		assert_eq!(time_series.entries.len(), 3);
+		assert_eq!(
+			time_series.entries[0],
+			FxEntry {
+				date: parse_date("2019-07-05 14:45:00", UTC).unwrap(),
+				open: 1.1225,
+				high: 1.1227,
+				low: 1.1221,
+				close: 1.1223,
+			}
+		);
+	}
+
+	#[test]
+	fn parse_fx_daily_weekly_monthly() {
+		let data: &[u8] = include_bytes!("../tests/json/fx_daily.json");
+		let time_series = parser::parse_fx(&Function::FxDaily, BufReader::new(data))
+			.expect("failed to parse entries");
+		assert_eq!(time_series.entries.len(), 3);
+		assert_eq!(
+			time_series.entries[2],
+			FxEntry {
+				date: parse_date("2019-07-05", UTC).unwrap(),
+				open: 1.1277,
+				high: 1.1291,
+				low: 1.1209,
+				close: 1.1219,
+			}
+		);
+
+		let data: &[u8] = include_bytes!("../tests/json/fx_weekly.json");
+		let time_series = parser::parse_fx(&Function::FxWeekly, BufReader::new(data))
+			.expect("failed to parse entries");
+		assert_eq!(time_series.entries.len(), 2);
+		assert_eq!(
+			time_series.entries[0].date,
+			parse_date("2019-06-30", UTC).unwrap()
+		);
+
+		let data: &[u8] = include_bytes!("../tests/json/fx_monthly.json");
+		let time_series = parser::parse_fx(&Function::FxMonthly, BufReader::new(data))
+			.expect("failed to parse entries");
+		assert_eq!(time_series.entries[1].close, 1.1219);
+
+		// the series of another function is missing
+		let data: &[u8] = include_bytes!("../tests/json/fx_monthly.json");
+		assert!(parser::parse_fx(&Function::FxWeekly, BufReader::new(data)).is_err());
+	}
+
+	#[test]
+	fn parse_fx_throttled() {
+		let data: &[u8] = include_bytes!("../tests/json/throttled_note.json");
+		let error = parser::parse_fx(&Function::FxDaily, BufReader::new(data)).unwrap_err();
+		assert!(error
+			.downcast_ref::<crate::exchange_rate::Throttled>()
+			.is_some());
+	}
 }
@@ -0,0 +1,134 @@
+// Copyright 2019 The Gotts Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Authentication of the API calls with the API secret
+
+use crate::router::{Handler, HandlerObj, ResponseFuture};
+use crate::web::response;
+use futures::future::ok;
+use hyper::header::{HeaderValue, AUTHORIZATION, WWW_AUTHENTICATE};
+use hyper::{Body, Request, Response, StatusCode};
+use ring::constant_time::verify_slices_are_equal;
+use std::collections::HashSet;
+
+/// User of the basic authentication, the API secret being the password
+pub const API_SECRET_USER: &'static str = "gotts";
+
+lazy_static! {
+	pub static ref GOTTS_BASIC_REALM: HeaderValue =
+		HeaderValue::from_str("Basic realm=GottsOracleAPI").unwrap();
+}
+
+/// Access policy of a route
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Access {
+	/// Open to anyone, the routes reading the oracle data
+	Public,
+	/// Requires the API secret, the routes changing the oracle data or state
+	Owner,
+}
+
+/// Value of the basic authorization header with the API secret
+pub fn basic_auth(api_secret: &str) -> String {
+	format!(
+		"Basic {}",
+		base64::encode(&format!("{}:{}", API_SECRET_USER, api_secret))
+	)
+}
+
+/// Check of the API secret of a request, given either as the basic auth
+/// password of the `API_SECRET_USER` or as a bearer token.
+pub struct ApiSecretAuth {
+	api_basic_auth: String,
+	api_bearer_auth: String,
+}
+
+impl ApiSecretAuth {
+	pub fn new(api_secret: &str) -> ApiSecretAuth {
+		ApiSecretAuth {
+			api_basic_auth: basic_auth(api_secret),
+			api_bearer_auth: format!("Bearer {}", api_secret),
+		}
+	}
+
+	/// Whether the request carries the API secret.
+	pub fn is_authorized(&self, req: &Request<Body>) -> bool {
+		match req.headers().get(AUTHORIZATION) {
+			Some(value) => {
+				let value = value.as_bytes();
+				verify_slices_are_equal(value, self.api_basic_auth.as_bytes()).is_ok()
+					|| verify_slices_are_equal(value, self.api_bearer_auth.as_bytes()).is_ok()
+			}
+			None => false,
+		}
+	}
+}
+
+/// Authentication middleware, requiring the API secret on the owner routes.
+/// The public routes are let through.
+pub struct BasicAuthMiddleware {
+	auth: ApiSecretAuth,
+	owner_routes: HashSet<String>,
+}
+
+impl BasicAuthMiddleware {
+	/// Create the middleware, with the paths of the routes of the owner
+	pub fn new(api_secret: &str, owner_routes: Vec<String>) -> BasicAuthMiddleware {
+		BasicAuthMiddleware {
+			auth: ApiSecretAuth::new(api_secret),
+			owner_routes: owner_routes.into_iter().collect(),
+		}
+	}
+
+	/// The access policy of a route
+	pub fn access(&self, path: &str) -> Access {
+		if self.owner_routes.contains(path) {
+			Access::Owner
+		} else {
+			Access::Public
+		}
+	}
+}
+
+impl Handler for BasicAuthMiddleware {
+	fn call(
+		&self,
+		req: Request<Body>,
+		mut handlers: Box<dyn Iterator<Item = HandlerObj>>,
+	) -> ResponseFuture {
+		let next_handler = match handlers.next() {
+			Some(h) => h,
+			None => return response(StatusCode::INTERNAL_SERVER_ERROR, "no handler found"),
+		};
+		if self.access(req.uri().path()) == Access::Public || self.auth.is_authorized(&req) {
+			next_handler.call(req, handlers)
+		} else {
+			warn!(
+				"Unauthorized API call: {} {}",
+				req.method(),
+				req.uri().path()
+			);
+			unauthorized_response(&GOTTS_BASIC_REALM)
+		}
+	}
+}
+
+fn unauthorized_response(basic_realm: &HeaderValue) -> ResponseFuture {
+	let response = Response::builder()
+		.status(StatusCode::UNAUTHORIZED)
+		.header(WWW_AUTHENTICATE, basic_realm)
+		.body(Body::empty())
+		.unwrap();
+	Box::new(ok(response))
+}
@@ -0,0 +1,354 @@
+// Copyright 2019 The Gotts Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Read-through cache of the exchange rates served on demand, so that the
+//! client traffic doesn't hit the rate-limited price sources: the freshest
+//! stored rate is served until it's older than the max age, and only then
+//! it's fetched live from the price sources.
+
+use crate::rest::{Error, ErrorKind};
+use chrono::{DateTime, Duration, Utc};
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use gotts_oracle_alphavantage::exchange_rate::ExchangeRateResult;
+use gotts_oracle_lib::{OracleBackend, QuoteValidator};
+use gotts_oracle_sources::Aggregator;
+use gotts_oracle_util::Mutex;
+
+/// Read-through cache of the exchange rates, over the oracle storage
+pub struct ExchangeCache {
+	/// Max age of a cached rate, beyond which it's fetched live
+	max_age: Duration,
+	/// Sanity filters on the rates fetched live
+	validator: Arc<QuoteValidator>,
+	/// Date of the last live fetch, per pair id. The concurrent requests of
+	/// a pair are serialized on it, so that a single fetch is in flight.
+	last_fetches: Mutex<HashMap<String, Arc<Mutex<Option<DateTime<Utc>>>>>>,
+}
+
+impl ExchangeCache {
+	/// Create a new cache. A zero `max_age` always fetches the rates live.
+	pub fn new(max_age: Duration, validator: Arc<QuoteValidator>) -> ExchangeCache {
+		ExchangeCache {
+			max_age,
+			validator,
+			last_fetches: Mutex::new(HashMap::new()),
+		}
+	}
+
+	/// Get the rate of a pair, from the storage if it's younger than the max
+	/// age, or else live from the price sources, then validated and stored.
+	/// The currency codes are expected as configured, the pair id being
+	/// case sensitive.
+	pub fn get_rate<T: ?Sized>(
+		&self,
+		oracle: &Mutex<T>,
+		aggregator: &Aggregator,
+		from: &str,
+		to: &str,
+	) -> Result<ExchangeRateResult, Error>
+	where
+		T: OracleBackend,
+	{
+		let id = format!("{}2{}", from, to);
+		let slot = self
+			.last_fetches
+			.lock()
+			.entry(id.clone())
+			.or_insert_with(|| Arc::new(Mutex::new(None)))
+			.clone();
+
+		// the requests of this pair wait here for the fetch in flight, if any,
+		// and are then served what it has stored
+		let mut last_fetch = slot.lock();
+		let now = Utc::now();
+		let latest = oracle.lock().latest(&id)?;
+		if let Some(rate) = latest {
+			// the quotes aren't refreshed while the markets are closed, so that
+			// a rate is as fresh as its last fetch
+			let fetched = last_fetch.map_or(rate.date, |date| date.max(rate.date));
+			if now - fetched < self.max_age {
+				return Ok(rate);
+			}
+		}
+
+		let aggregated = aggregator.aggregate(from, to).map_err(|e| {
+			ErrorKind::Vendor(format!("query price sources failed on {}: {}", id, e))
+		})?;
+		*last_fetch = Some(now);
+		let rate = aggregated.rate.clone();
+
+		// validate and save the query data into local database for aggregation
+		let mut oracle = oracle.lock();
+		self.validator.validate(&*oracle, &rate)?;
+		let mut batch = oracle.batch()?;
+		batch.save_aggregated(aggregated)?;
+		batch.commit()?;
+
+		Ok(rate)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::foreign::Foreign;
+	use gotts_oracle_config::{PairsConfig, ServerConfig, ValidationConfig};
+	use gotts_oracle_lib::{LMDBBackend, OracleKeypair, Resolution};
+	use gotts_oracle_sources::{Error as SourceError, PriceSource, SourceRegistry};
+	use std::sync::atomic::{AtomicUsize, Ordering};
+	use std::{fs, thread, time};
+
+	/// Price source counting its calls, slow enough for the concurrent
+	/// requests to overlap
+	struct CountingSource {
+		calls: AtomicUsize,
+		rate: Option<f64>,
+	}
+
+	impl PriceSource for CountingSource {
+		fn name(&self) -> &str {
+			"counting"
+		}
+
+		fn get_rate(&self, from: &str, to: &str) -> Result<ExchangeRateResult, SourceError> {
+			self.calls.fetch_add(1, Ordering::SeqCst);
+			thread::sleep(time::Duration::from_millis(100));
+			match self.rate {
+				Some(rate) => Ok(ExchangeRateResult {
+					from: from.to_owned(),
+					to: to.to_owned(),
+					rate,
+					date: Utc::now(),
+				}),
+				None => Err(SourceError::SourceError {
+					source: "counting".to_owned(),
+					message: "unavailable".to_owned(),
+				}),
+			}
+		}
+	}
+
+	struct Fixture {
+		db_root: String,
+		oracle: Arc<Mutex<LMDBBackend>>,
+		aggregator: Arc<Aggregator>,
+		source: Arc<CountingSource>,
+	}
+
+	impl Fixture {
+		fn new(name: &str, rate: Option<f64>) -> Fixture {
+			let db_root = std::env::temp_dir()
+				.join(format!("gotts_oracle_cache_{}", name))
+				.to_string_lossy()
+				.into_owned();
+			let _ = fs::remove_dir_all(&db_root);
+			let config = ServerConfig {
+				db_root: db_root.clone(),
+				..ServerConfig::default()
+			};
+			let oracle = Arc::new(Mutex::new(LMDBBackend::new(config).unwrap()));
+			let source = Arc::new(CountingSource {
+				calls: AtomicUsize::new(0),
+				rate,
+			});
+			let mut registry = SourceRegistry::new();
+			registry.register(source.clone()).unwrap();
+			Fixture {
+				db_root,
+				oracle,
+				aggregator: Arc::new(Aggregator::new(registry, 1)),
+				source,
+			}
+		}
+
+		fn cache(&self, max_age: Duration) -> ExchangeCache {
+			let validator = Arc::new(QuoteValidator::new(ValidationConfig::default()));
+			ExchangeCache::new(max_age, validator)
+		}
+
+		fn foreign(&self) -> Foreign<LMDBBackend> {
+			Foreign {
+				oracle: self.oracle.clone(),
+				aggregator: Arc::downgrade(&self.aggregator),
+				pairs: PairsConfig::default(),
+				keypair: Arc::new(OracleKeypair::from_seed(&[1; 32]).unwrap()),
+				max_staleness: Duration::minutes(1),
+				cache: Arc::new(self.cache(Duration::minutes(1))),
+			}
+		}
+
+		fn calls(&self) -> usize {
+			self.source.calls.load(Ordering::SeqCst)
+		}
+
+		fn save(&self, rate: f64, age: Duration) {
+			let date = Utc::now() - age;
+			let mut oracle = self.oracle.lock();
+			let mut batch = oracle.batch().unwrap();
+			let rate = ExchangeRateResult {
+				from: "EUR".to_owned(),
+				to: "USD".to_owned(),
+				rate,
+				date,
+			};
+			batch.save(date, rate).unwrap();
+			batch.commit().unwrap();
+		}
+	}
+
+	impl Drop for Fixture {
+		fn drop(&mut self) {
+			let _ = fs::remove_dir_all(&self.db_root);
+		}
+	}
+
+	#[test]
+	fn serve_fresh_stored_rate() {
+		let fixture = Fixture::new("fresh", Some(1.2));
+		fixture.save(1.1, Duration::seconds(10));
+		let cache = fixture.cache(Duration::minutes(1));
+
+		let rate = cache
+			.get_rate(&fixture.oracle, &fixture.aggregator, "EUR", "USD")
+			.unwrap();
+		assert_eq!(rate.rate, 1.1);
+		assert_eq!(fixture.calls(), 0);
+	}
+
+	#[test]
+	fn fetch_stale_rate() {
+		let fixture = Fixture::new("stale", Some(1.2));
+		fixture.save(1.1, Duration::minutes(5));
+		let cache = fixture.cache(Duration::minutes(1));
+
+		let rate = cache
+			.get_rate(&fixture.oracle, &fixture.aggregator, "EUR", "USD")
+			.unwrap();
+		assert_eq!(rate.rate, 1.2);
+		assert_eq!(fixture.calls(), 1);
+
+		// the fetched rate is stored, and served until it's stale
+		let rate = cache
+			.get_rate(&fixture.oracle, &fixture.aggregator, "EUR", "USD")
+			.unwrap();
+		assert_eq!(rate.rate, 1.2);
+		assert_eq!(fixture.calls(), 1);
+	}
+
+	#[test]
+	fn single_fetch_in_flight() {
+		let fixture = Fixture::new("in_flight", Some(1.2));
+		let cache = Arc::new(fixture.cache(Duration::minutes(1)));
+
+		let threads: Vec<_> = (0..4)
+			.map(|_| {
+				let cache = cache.clone();
+				let oracle = fixture.oracle.clone();
+				let aggregator = fixture.aggregator.clone();
+				thread::spawn(move || cache.get_rate(&oracle, &aggregator, "EUR", "USD"))
+			})
+			.collect();
+		for thread in threads {
+			assert_eq!(thread.join().unwrap().unwrap().rate, 1.2);
+		}
+		assert_eq!(fixture.calls(), 1);
+	}
+
+	#[test]
+	fn price_sources_error() {
+		let fixture = Fixture::new("error", None);
+		let cache = fixture.cache(Duration::minutes(1));
+
+		let res = cache.get_rate(&fixture.oracle, &fixture.aggregator, "EUR", "USD");
+		match res.unwrap_err().kind() {
+			ErrorKind::Vendor(_) => (),
+			kind => panic!("unexpected error {:?}", kind),
+		}
+		assert_eq!(fixture.calls(), 1);
+	}
+
+	#[test]
+	fn pair_case_ignored() {
+		let fixture = Fixture::new("case", Some(1.2));
+		let foreign = fixture.foreign();
+
+		let rate = foreign
+			.get_rate("eur".to_owned(), "usd".to_owned())
+			.unwrap();
+		assert_eq!((rate.from.as_str(), rate.to.as_str()), ("EUR", "USD"));
+		foreign
+			.get_rate("EUR".to_owned(), "Usd".to_owned())
+			.unwrap();
+		assert_eq!(fixture.calls(), 1);
+		assert!(foreign
+			.get_rate("EUR".to_owned(), "XXX".to_owned())
+			.is_err());
+	}
+
+	#[test]
+	fn unsupported_pair_rejected() {
+		let fixture = Fixture::new("unsupported", Some(1.2));
+		fixture.save(1.1, Duration::seconds(10));
+		let foreign = fixture.foreign();
+		let now = Utc::now();
+
+		let rate = foreign
+			.rate_at("eur2usd", now, Duration::minutes(1))
+			.unwrap();
+		assert_eq!((rate.from.as_str(), rate.rate), ("EUR", 1.1));
+		let twap = foreign
+			.twap("Eur2Usd", Duration::minutes(1), Duration::minutes(5))
+			.unwrap();
+		assert_eq!(twap.rate, 1.1);
+		foreign
+			.get_rate_at("eur".to_owned(), "usd".to_owned(), now.timestamp())
+			.unwrap();
+
+		let unsupported = |res: Result<(), Error>| match res.unwrap_err().kind() {
+			ErrorKind::Argument(_) => (),
+			kind => panic!("unexpected error {:?}", kind),
+		};
+		unsupported(
+			foreign
+				.rate_at("EUR2XXX", now, Duration::minutes(1))
+				.map(|_| ()),
+		);
+		unsupported(
+			foreign
+				.rate_at("EUR", now, Duration::minutes(1))
+				.map(|_| ()),
+		);
+		unsupported(
+			foreign
+				.twap("EUR2XXX", Duration::minutes(1), Duration::minutes(5))
+				.map(|_| ()),
+		);
+		unsupported(
+			foreign
+				.get_twap("EUR".to_owned(), "XXX".to_owned(), "1m".to_owned())
+				.map(|_| ()),
+		);
+		unsupported(
+			foreign
+				.candles("EUR2XXX", Resolution::Minute, now - Duration::hours(1), now)
+				.map(|_| ()),
+		);
+		assert!(foreign
+			.candles("eur2usd", Resolution::Minute, now - Duration::hours(1), now)
+			.unwrap()
+			.is_empty());
+	}
+}
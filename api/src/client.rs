@@ -15,11 +15,12 @@
 
 //! High level JSON/HTTP client API
 
+use crate::auth::basic_auth;
 use crate::rest::{Error, ErrorKind};
 use failure::{Fail, ResultExt};
 use futures::future::{err, ok, Either};
 use http::uri::{InvalidUri, Uri};
-use hyper::header::{ACCEPT, CONTENT_TYPE, USER_AGENT};
+use hyper::header::{ACCEPT, AUTHORIZATION, CONTENT_TYPE, USER_AGENT};
 use hyper::rt::{Future, Stream};
 use hyper::{Body, Client, Request};
 use hyper_rustls;
@@ -130,7 +131,7 @@ where
 fn build_request(
 	url: &str,
 	method: &str,
-	_api_secret: Option<String>,
+	api_secret: Option<String>,
 	body: Option<String>,
 ) -> Result<Request<Body>, Error> {
 	let uri = url.parse::<Uri>().map_err::<Error, _>(|e: InvalidUri| {
@@ -138,6 +139,9 @@ fn build_request(
 			.into()
 	})?;
 	let mut builder = Request::builder();
+	if let Some(api_secret) = api_secret {
+		builder.header(AUTHORIZATION, basic_auth(&api_secret));
+	}
 
 	builder
 		.method(method)
@@ -15,19 +15,29 @@
 
 //! Foreign API External Definition
 
+use crate::cache::ExchangeCache;
 use crate::handlers::version_api::{Version, VersionHandler};
+use crate::handlers::{ApiConfig, ApiContext};
 use crate::rest::*;
-use gotts_oracle_alphavantage::exchange_rate::{ExchangeRate, ExchangeRateResult};
-use gotts_oracle_lib::OracleBackend;
+use gotts_oracle_alphavantage::exchange_rate::ExchangeRateResult;
+use gotts_oracle_config::PairsConfig;
+use gotts_oracle_lib::cross_rate::DEFAULT_MAX_LEG_AGE_SECS;
+use gotts_oracle_lib::twap::{self, DEFAULT_MAX_GAP_SECS};
+use gotts_oracle_lib::{
+	Candle, CrossRate, CurrencyGraph, OracleBackend, OracleKeypair, Resolution, SignedPriceSet,
+	Twap,
+};
+use gotts_oracle_sources::Aggregator;
 use gotts_oracle_util::Mutex;
 
-use chrono::{DateTime, Duration, Utc};
+use chrono::{DateTime, Duration, TimeZone, Utc};
 use std::sync::Arc;
 use std::sync::Weak;
 
 /// Main interface into all node API functions.
 /// Node APIs are split into two seperate blocks of functionality
-/// called the ['Owner'](struct.Owner.html) and ['Foreign'](struct.Foreign.html) APIs
+/// called the ['Owner'](struct.Owner.html) and ['Foreign'](struct.Foreign.html) APIs,
+/// the Foreign one only reading the oracle data.
 ///
 /// Methods in this API are intended to be 'single use'.
 ///
@@ -38,26 +48,43 @@ where
 {
 	/// Oracle instance
 	pub oracle: Arc<Mutex<T>>,
-	/// Vendor API client
-	pub client: Weak<gotts_oracle_alphavantage::Client>,
+	/// Price sources aggregator
+	pub aggregator: Weak<Aggregator>,
+	/// Configured currency pairs
+	pub pairs: PairsConfig,
+	/// Oracle key, signing the aggregated prices
+	pub keypair: Arc<OracleKeypair>,
+	/// Max staleness of the point-in-time lookups
+	pub max_staleness: Duration,
+	/// Read-through cache of the exchange rates
+	pub cache: Arc<ExchangeCache>,
 }
 
 impl<T: ?Sized> Foreign<T>
 where
 	T: OracleBackend + Send + Sync + 'static,
 {
-	/// Create a new API instance with the chain, transaction pool, peers and `sync_state`. All subsequent
-	/// API calls will operate on this instance of node API.
+	/// Create a new API instance over the oracle state shared with the daemon,
+	/// with its own read-through cache of the exchange rates.
 	///
 	/// # Arguments
-	/// * `chain` - A non-owning reference of the chain.
+	/// * `context` - The oracle state shared with the daemon.
+	/// * `config` - The configuration of the API server.
 	///
 	/// # Returns
-	/// * An instance of the Node holding references to the current chain, transaction pool, peers and sync_state.
+	/// * An instance of the Foreign API, holding a non-owning reference of the aggregator.
 	///
 
-	pub fn new(oracle: Arc<Mutex<T>>, client: Weak<gotts_oracle_alphavantage::Client>) -> Self {
-		Foreign { oracle, client }
+	pub fn new(context: &ApiContext<T>, config: &ApiConfig) -> Self {
+		let cache = ExchangeCache::new(config.exchange_max_age, context.validator.clone());
+		Foreign {
+			oracle: context.oracle.clone(),
+			aggregator: Arc::downgrade(&context.aggregator),
+			pairs: config.pairs.clone(),
+			keypair: context.keypair.clone(),
+			max_staleness: config.max_staleness,
+			cache: Arc::new(cache),
+		}
 	}
 
 	/// Returns the oracle version and block header version (used by gotts node).
@@ -73,7 +100,9 @@ where
 		version_handler.get_version()
 	}
 
-	/// Returns the Exchange Rate.
+	/// Returns the Exchange Rate. The stored rate is returned if it's younger
+	/// than the configured max age, otherwise the median of all the price
+	/// sources is fetched live and stored.
 	///
 	/// # Arguments
 	/// * `from` - exchange rate from
@@ -85,41 +114,17 @@ where
 	/// * or [`Error`](struct.Error.html) if an error is encountered.
 	///
 	pub fn get_rate(&self, from: String, to: String) -> Result<ExchangeRateResult, Error> {
-		let arc_client = w(&self.client)?;
-
-		let exchange_rate = crossbeam::scope(|scope| {
-			let handle = scope.spawn(move |_| -> Result<ExchangeRate, Error> {
-				let exchange_result = arc_client.get_exchange_rate(&from, &to);
-				let result = match exchange_result {
-					Ok(result) => Ok(result),
-					Err(_e) => Err(ErrorKind::RequestError(
-						"query alphavantage failed!".to_owned(),
-					))?,
-				};
-
-				result
-			});
-
-			handle.join().unwrap()
-		});
-
-		let result: ExchangeRate = exchange_rate.unwrap().unwrap();
-		let exchange_rate_result = ExchangeRateResult {
-			from: result.from.code.to_string(),
-			to: result.to.code.to_string(),
-			rate: result.rate,
-			date: result.date,
-		};
-
-		// save the query data into local database for aggregation
-		{
-			let mut oracle = self.oracle.lock();
-			let mut batch = oracle.batch()?;
-			batch.save(result.date, exchange_rate_result.clone())?;
-			batch.commit()?;
-		}
-
-		Ok(exchange_rate_result)
+		// the rates are stored and cached under the configured codes
+		let (from, to) = self
+			.pairs
+			.find(&from, &to)
+			.ok_or(ErrorKind::Argument(format!(
+				"unsupported pair {}2{}",
+				from, to
+			)))?;
+		let arc_aggregator = w(&self.aggregator)?;
+		self.cache
+			.get_rate(&self.oracle, &arc_aggregator, &from, &to)
 	}
 
 	/// Returns the recent Exchange Rate.
@@ -139,81 +144,180 @@ where
 		items: usize,
 	) -> Result<Vec<ExchangeRateResult>, Error> {
 		let oracle = self.oracle.lock();
-		let mut rates: Vec<ExchangeRateResult> = if prefix.is_empty() {
-			oracle.iter().collect()
-		} else {
-			oracle.iter_id(&prefix).collect()
-		};
-		rates.sort_by_key(|rate| std::cmp::Reverse(rate.date.clone()));
-		rates.truncate(items);
+		let rates = oracle.recent(&prefix, items)?;
 
 		Ok(rates)
 	}
 
-	/// Compact the exchange rate data, clean the data beyond x minutes.
+	/// Returns the exchange rate at a point in time, that is the latest one
+	/// dated at or before it, no older than the configured max staleness.
 	///
 	/// # Arguments
-	/// * `minutes` - compact data which beyond specified minutes.
+	/// * `from` - exchange rate from
+	/// * `to` - exchange rate to.
+	/// * `time` - unix timestamp, in seconds, of the point in time.
 	///
 	/// # Returns
 	/// * Result Containing:
-	/// * An usize of how many items has been cleaned.
-	/// * or [`Error`](struct.Error.html) if an error is encountered.
+	/// * A [`ExchangeRateResult`](types/struct.ExchangeRateResult.html)
+	/// * or a NotFound [`Error`](struct.Error.html) if there's no rate at or before `time`,
+	/// a Stale one if it's older than the max staleness.
 	///
-	pub fn compact(&self, minutes: u32) -> Result<usize, Error> {
-		let cutoff_time: DateTime<Utc> = Utc::now() - Duration::minutes(minutes as i64);
-
-		// read all rate data from local database
-		let mut oracle = self.oracle.lock();
-		let mut batch = oracle.batch()?;
-		let mut total_cleaned = 0;
-		for rate in batch.iter() {
-			if rate.date < cutoff_time {
-				total_cleaned += 1;
-				let mut id = rate.from.clone();
-				id.push('2');
-				id.push_str(&rate.to);
-				batch.delete(&id, rate.date)?;
-			}
+	pub fn get_rate_at(
+		&self,
+		from: String,
+		to: String,
+		time: i64,
+	) -> Result<ExchangeRateResult, Error> {
+		let time = Utc
+			.timestamp_opt(time, 0)
+			.single()
+			.ok_or(ErrorKind::Argument("invalid time".to_owned()))?;
+		let mut id = from;
+		id.push('2');
+		id.push_str(&to);
+
+		self.rate_at(&id, time, self.max_staleness)
+	}
+
+	/// Returns the exchange rate of the configured pair `id` at a point in
+	/// time, no older than `max_staleness`, read from the candles once the
+	/// history of `time` is compacted.
+	pub fn rate_at(
+		&self,
+		id: &str,
+		time: DateTime<Utc>,
+		max_staleness: Duration,
+	) -> Result<ExchangeRateResult, Error> {
+		if time.checked_sub_signed(max_staleness).is_none() {
+			return Err(ErrorKind::Argument(
+				"max staleness out of range of the time".to_owned(),
+			))?;
 		}
-		batch.commit()?;
+		let id = self.pair_id(id)?;
+		let oracle = self.oracle.lock();
+		let rate = oracle.rate_at(&id, time, max_staleness)?;
 
-		Ok(total_cleaned)
+		Ok(rate)
 	}
 
-	/// Get aggregated exchange rates.
+	/// Returns the time-weighted average price of a pair over a window ending
+	/// now. An observation holds for at most 5 minutes, the periods without any
+	/// fresh observation are reported as gaps.
 	///
 	/// # Arguments
+	/// * `from` - exchange rate from
+	/// * `to` - exchange rate to.
+	/// * `window` - length of the window, i.e. "15m", in seconds "s", minutes "m",
+	/// hours "h" or days "d", up to 7 days.
 	///
 	/// # Returns
 	/// * Result Containing:
-	/// * A [`Vec<ExchangeRateResult>`](types/struct.ExchangeRateResult.html)
+	/// * A [`Twap`](types/struct.Twap.html)
+	/// * or a NotFound [`Error`](struct.Error.html) if there's no observation in the window.
+	///
+	pub fn get_twap(&self, from: String, to: String, window: String) -> Result<Twap, Error> {
+		let window = twap::parse_window(&window).ok_or(ErrorKind::Argument(
+			"invalid window, i.e. 30s, 15m, 1h or 1d, up to 7d".to_owned(),
+		))?;
+		let mut id = from;
+		id.push('2');
+		id.push_str(&to);
+
+		self.twap(&id, window, Duration::seconds(DEFAULT_MAX_GAP_SECS))
+	}
+
+	/// Returns the time-weighted average price of the configured pair `id`
+	/// over a window ending now, an observation holding for at most `max_gap`.
+	pub fn twap(&self, id: &str, window: Duration, max_gap: Duration) -> Result<Twap, Error> {
+		let id = self.pair_id(id)?;
+		let oracle = self.oracle.lock();
+		let twap = twap::twap_of(&*oracle, &id, window, Utc::now(), max_gap)?;
+
+		Ok(twap)
+	}
+
+	/// Returns the candles of the configured pair `id` at `resolution`,
+	/// starting from `from` to `to` inclusive.
+	pub fn candles(
+		&self,
+		id: &str,
+		resolution: Resolution,
+		from: DateTime<Utc>,
+		to: DateTime<Utc>,
+	) -> Result<Vec<Candle>, Error> {
+		let id = self.pair_id(id)?;
+		let oracle = self.oracle.lock();
+		let candles = oracle.candles_range(&id, resolution, from, to).collect();
+
+		Ok(candles)
+	}
+
+	/// Get aggregated exchange rates, the latest rate of each configured pair,
+	/// signed by the oracle key.
+	///
+	/// # Arguments
+	///
+	/// # Returns
+	/// * Result Containing:
+	/// * A [`SignedPriceSet`](types/struct.SignedPriceSet.html)
 	/// * or [`Error`](struct.Error.html) if an error is encountered.
 	///
-	pub fn get_aggregated(&self) -> Result<Vec<ExchangeRateResult>, Error> {
+	pub fn get_aggregated(&self) -> Result<SignedPriceSet, Error> {
 		let oracle = self.oracle.lock();
-		let mut rates: Vec<ExchangeRateResult> = oracle.iter().collect();
-		rates.sort_by_key(|rate| std::cmp::Reverse(rate.date.clone()));
-
-		let currencies_a = vec!["EUR", "GBP", "BTC", "ETH"];
-		let currencies_b = vec!["CNY", "JPY", "CAD"];
-		let mut aggregated: Vec<ExchangeRateResult> =
-			Vec::with_capacity(currencies_a.len() + currencies_b.len());
-		for from in currencies_a {
-			let index = rates
-				.iter()
-				.position(|r| r.from == from && r.to == "USD")
+		let pairs = self.pairs.pairs();
+		let mut aggregated: Vec<ExchangeRateResult> = Vec::with_capacity(pairs.len());
+		for (from, to) in pairs {
+			let rate = oracle
+				.latest(&format!("{}2{}", from, to))?
 				.ok_or(ErrorKind::NotFound)?;
-			aggregated.push(rates[index].clone());
+			aggregated.push(rate);
 		}
-		for to in currencies_b {
-			let index = rates
-				.iter()
-				.position(|r| r.from == "USD" && r.to == to)
-				.ok_or(ErrorKind::NotFound)?;
-			aggregated.push(rates[index].clone());
+
+		Ok(self.keypair.sign(aggregated)?)
+	}
+
+	/// Get a cross exchange rate, derived from the latest stored quotes. Uses a
+	/// direct quote if any, a path through the base currency otherwise, or
+	/// the shortest path of fresh quotes.
+	///
+	/// # Arguments
+	/// * `from` - exchange rate from
+	/// * `to` - exchange rate to.
+	///
+	/// # Returns
+	/// * Result Containing:
+	/// * A [`CrossRate`](types/struct.CrossRate.html) with the path of currencies used and the date of the oldest leg
+	/// * or [`Error`](struct.Error.html) if an error is encountered.
+	///
+	pub fn get_cross_rate(&self, from: String, to: String) -> Result<CrossRate, Error> {
+		self.cross_rate(&from, &to, Duration::seconds(DEFAULT_MAX_LEG_AGE_SECS))
+	}
+
+	/// Returns a cross exchange rate, from the quotes no older than `max_age`.
+	pub fn cross_rate(&self, from: &str, to: &str, max_age: Duration) -> Result<CrossRate, Error> {
+		if from.is_empty() || to.is_empty() || from == to {
+			return Err(ErrorKind::Argument(
+				"two different currencies are required".to_owned(),
+			))?;
 		}
 
-		Ok(aggregated)
+		let oracle = self.oracle.lock();
+		let graph = CurrencyGraph::from_backend(&*oracle)?;
+		let cross_rate = graph
+			.cross_rate(from, to, &self.pairs.base_currency, max_age)
+			.ok_or(ErrorKind::NotFound)?;
+
+		Ok(cross_rate)
+	}
+
+	/// The id of the configured pair `id`, ignoring case, as it's stored, or
+	/// an Argument error if the pair isn't configured.
+	fn pair_id(&self, id: &str) -> Result<String, Error> {
+		let (from, to) = self
+			.pairs
+			.find_id(id)
+			.ok_or(ErrorKind::Argument(format!("unsupported pair {}", id)))?;
+		Ok(format!("{}2{}", from, to))
 	}
 }
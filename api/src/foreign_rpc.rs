@@ -19,7 +19,7 @@ use crate::foreign::Foreign;
 use crate::handlers::version_api::Version;
 use crate::rest::ErrorKind;
 use gotts_oracle_alphavantage::exchange_rate::ExchangeRateResult;
-use gotts_oracle_lib::OracleBackend;
+use gotts_oracle_lib::{CrossRate, OracleBackend, SignedPriceSet, Twap};
 
 /// Public definition used to generate Oracle jsonrpc api.
 /// * When running `gotts_oracle` with defaults, the V1 json api is available at
@@ -34,8 +34,15 @@ pub trait ForeignRpc: Sync + Send {
 		prefix: String,
 		items: usize,
 	) -> Result<Vec<ExchangeRateResult>, ErrorKind>;
-	fn compact(&self, minutes: u32) -> Result<usize, ErrorKind>;
-	fn get_aggregated(&self) -> Result<Vec<ExchangeRateResult>, ErrorKind>;
+	fn get_rate_at(
+		&self,
+		from: String,
+		to: String,
+		time: i64,
+	) -> Result<ExchangeRateResult, ErrorKind>;
+	fn get_twap(&self, from: String, to: String, window: String) -> Result<Twap, ErrorKind>;
+	fn get_aggregated(&self) -> Result<SignedPriceSet, ErrorKind>;
+	fn get_cross_rate(&self, from: String, to: String) -> Result<CrossRate, ErrorKind>;
 }
 
 impl<T: ?Sized> ForeignRpc for Foreign<T>
@@ -58,11 +65,24 @@ where
 		Foreign::get_recent(self, prefix, items).map_err(|e| e.kind().clone())
 	}
 
-	fn compact(&self, minutes: u32) -> Result<usize, ErrorKind> {
-		Foreign::compact(self, minutes).map_err(|e| e.kind().clone())
+	fn get_rate_at(
+		&self,
+		from: String,
+		to: String,
+		time: i64,
+	) -> Result<ExchangeRateResult, ErrorKind> {
+		Foreign::get_rate_at(self, from, to, time).map_err(|e| e.kind().clone())
+	}
+
+	fn get_twap(&self, from: String, to: String, window: String) -> Result<Twap, ErrorKind> {
+		Foreign::get_twap(self, from, to, window).map_err(|e| e.kind().clone())
 	}
 
-	fn get_aggregated(&self) -> Result<Vec<ExchangeRateResult>, ErrorKind> {
+	fn get_aggregated(&self) -> Result<SignedPriceSet, ErrorKind> {
 		Foreign::get_aggregated(self).map_err(|e| e.kind().clone())
 	}
+
+	fn get_cross_rate(&self, from: String, to: String) -> Result<CrossRate, ErrorKind> {
+		Foreign::get_cross_rate(self, from, to).map_err(|e| e.kind().clone())
+	}
 }
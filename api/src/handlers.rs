@@ -17,58 +17,159 @@ pub mod server_api;
 pub mod version_api;
 
 use self::server_api::{
-	AggregateHandler, CompactHandler, ExchangeHandler, IndexHandler, RecentHandler,
+	AggregateHandler, CandlesHandler, CrossHandler, ExchangeHandler, IndexHandler, MetricsHandler,
+	PriceAtHandler, RateLimitStatsHandler, RecentHandler, SnapshotHandler, TwapHandler,
+	ValidationHandler,
 };
 
+use crate::auth::BasicAuthMiddleware;
 use crate::foreign::Foreign;
 use crate::foreign_rpc::ForeignRpc;
-use crate::rest::{ApiServer, Error, ErrorKind, TLSConfig};
-use crate::router::ResponseFuture;
+use crate::owner::Owner;
+use crate::owner_rpc::OwnerRpc;
+use crate::rate_limit::RateLimitMiddleware;
+use crate::rest::{ApiServer, Error, ErrorKind, LoggingMiddleware, TLSConfig};
+use crate::router::{HandlerObj, ResponseFuture};
 use crate::router::{Router, RouterError};
 use crate::web::*;
 
+use chrono::Duration;
 use easy_jsonrpc_mw::{Handler, MaybeReply};
 use futures::future::ok;
 use futures::Future;
 use hyper::{Body, Request, Response, StatusCode};
 use serde::Serialize;
 use std::net::SocketAddr;
+use std::path::PathBuf;
 use std::sync::Arc;
-use std::sync::Weak;
 use std::thread;
 
-use gotts_oracle_alphavantage as alphavantage;
-use gotts_oracle_lib::OracleBackend;
-use gotts_oracle_util::Mutex;
+use gotts_oracle_config::{CandlesConfig, PairsConfig, RateLimitConfig};
+use gotts_oracle_lib::{OracleBackend, OracleKeypair, QuoteValidator};
+use gotts_oracle_sources::{Aggregator, PollControl};
+use gotts_oracle_util::{Mutex, RwLock};
+
+/// The oracle state the API handlers share with the daemon
+pub struct ApiContext<T: ?Sized>
+where
+	T: OracleBackend + Send + Sync + 'static,
+{
+	/// Oracle instance
+	pub oracle: Arc<Mutex<T>>,
+	/// Price sources aggregator
+	pub aggregator: Arc<Aggregator>,
+	/// Oracle key, signing the aggregated prices
+	pub keypair: Arc<OracleKeypair>,
+	/// Sanity filters on the quotes
+	pub validator: Arc<QuoteValidator>,
+	/// Candles retention, changed by the owner API
+	pub candles: Arc<RwLock<CandlesConfig>>,
+	/// Control of the polling daemon, by the owner API
+	pub control: Arc<PollControl>,
+}
+
+/// Configuration of the API server
+#[derive(Clone)]
+pub struct ApiConfig {
+	/// Address the server listens on
+	pub addr: String,
+	/// TLS configuration, if the server is served over TLS
+	pub tls_config: Option<TLSConfig>,
+	/// API secret of the owner routes, which are disabled without one
+	pub api_secret: Option<String>,
+	/// Rate limits of the calls of each client
+	pub rate_limit: RateLimitConfig,
+	/// Configured currency pairs
+	pub pairs: PairsConfig,
+	/// Max staleness of the point-in-time lookups
+	pub max_staleness: Duration,
+	/// Max age of the exchange rates served from the storage
+	pub exchange_max_age: Duration,
+	/// Directory the snapshots of the database are written to
+	pub snapshot_dir: PathBuf,
+	/// Configuration file the oracle was started with
+	pub config_file: Option<PathBuf>,
+}
 
 /// Start all server HTTP handlers. Register all of them with Router
-/// and runs the corresponding HTTP server.
+/// and runs the corresponding HTTP server. Returns the server, to stop it,
+/// and the handle of its thread, which exits once the server is stopped.
 ///
-/// Hyper currently has a bug that prevents clean shutdown. In order
-/// to avoid having references kept forever by handlers, we only pass
-/// weak references. Note that this likely means a crash if the handlers are
-/// used after a server shutdown (which should normally never happen,
+/// In order to avoid having references kept forever by handlers, we only
+/// pass weak references. Note that this likely means a crash if the handlers
+/// are used after a server shutdown (which should normally never happen,
 /// except during tests).
+///
+/// The candles retention and the poll control are shared with the daemon,
+/// the owner API changing them while the oracle runs.
+///
+/// The calls are logged and counted, then the calls of each client are rate
+/// limited, before being authenticated.
 pub fn start_rest_apis<T: ?Sized>(
-	oracle: Arc<Mutex<T>>,
-	client: Arc<alphavantage::Client>,
-	addr: String,
-	tls_config: Option<TLSConfig>,
-) -> Result<thread::JoinHandle<()>, Error>
+	context: ApiContext<T>,
+	config: ApiConfig,
+) -> Result<(ApiServer, thread::JoinHandle<()>), Error>
 where
 	T: OracleBackend + Send + Sync + 'static,
 {
-	let mut router = build_router(oracle.clone(), client.clone())?;
-	let json_api_handler_v1 = JsonAPIHandlerV1::new(oracle, Arc::downgrade(&client));
-	router.add_route("/v1/json", Arc::new(json_api_handler_v1))?;
+	let foreign = Arc::new(Foreign::new(&context, &config));
+	let mut router = build_router(&context, foreign.clone())?;
+	router.add_route("/v1/json", Arc::new(JsonAPIHandlerV1::new(foreign)))?;
+
+	router.add_middleware(Arc::new(LoggingMiddleware {}));
+	let rate_limit = Arc::new(RateLimitMiddleware::new(
+		config.rate_limit,
+		config.api_secret.as_ref().map(|s| s.as_str()),
+	));
+	router.add_middleware(rate_limit.clone());
+
+	// the owner routes require the API secret, they're disabled without one.
+	// The metrics are owner routes too, the scrapers authenticating with the
+	// API secret as a bearer token or a basic auth password.
+	match config.api_secret {
+		Some(api_secret) => {
+			let owner_routes: Vec<(&'static str, HandlerObj)> = vec![
+				(
+					"/v1/owner",
+					Arc::new(OwnerAPIHandlerV1::new(
+						context.oracle.clone(),
+						context.candles,
+						context.control,
+						config.config_file,
+					)),
+				),
+				(
+					"/v1/owner/snapshot",
+					Arc::new(SnapshotHandler::new(
+						context.oracle.clone(),
+						config.snapshot_dir,
+					)),
+				),
+				(
+					"/v1/owner/rate_limits",
+					Arc::new(RateLimitStatsHandler::new(rate_limit)),
+				),
+				("/metrics", Arc::new(MetricsHandler::new(context.oracle))),
+			];
+			let auth = BasicAuthMiddleware::new(
+				&api_secret,
+				owner_routes.iter().map(|(r, _)| r.to_string()).collect(),
+			);
+			for (route, handler) in owner_routes {
+				router.add_route(route, handler)?;
+			}
+			router.add_middleware(Arc::new(auth));
+		}
+		None => warn!("No API secret configured, the owner routes and the metrics are disabled."),
+	}
 
 	let mut apis = ApiServer::new();
 
-	info!("Starting HTTP API server at {}.", addr);
-	let socket_addr: SocketAddr = addr.parse().expect("unable to parse socket address");
-	let res = apis.start(socket_addr, router, tls_config);
+	info!("Starting HTTP API server at {}.", config.addr);
+	let socket_addr: SocketAddr = config.addr.parse().expect("unable to parse socket address");
+	let res = apis.start(socket_addr, router, config.tls_config);
 	match res {
-		Ok(handle) => Ok(handle),
+		Ok(handle) => Ok((apis, handle)),
 		Err(e) => {
 			error!("HTTP API server failed to start. Err: {}", e);
 			Err(ErrorKind::Internal("apis failed to start".to_owned()).into())
@@ -83,10 +184,8 @@ pub struct JsonAPIHandlerV1<T: ?Sized>
 where
 	T: OracleBackend + Send + Sync + 'static,
 {
-	/// Oracle instance
-	pub oracle: Arc<Mutex<T>>,
-	/// Vendor API client
-	pub client: Weak<gotts_oracle_alphavantage::Client>,
+	/// Foreign API, shared with the REST handlers
+	pub foreign: Arc<Foreign<T>>,
 }
 
 impl<T: ?Sized> JsonAPIHandlerV1<T>
@@ -94,17 +193,17 @@ where
 	T: OracleBackend + Send + Sync + 'static,
 {
 	/// Create a new foreign API handler for GET methods
-	pub fn new(oracle: Arc<Mutex<T>>, client: Weak<gotts_oracle_alphavantage::Client>) -> Self {
-		JsonAPIHandlerV1 { oracle, client }
+	pub fn new(foreign: Arc<Foreign<T>>) -> Self {
+		JsonAPIHandlerV1 { foreign }
 	}
 
 	fn call_api(
 		&self,
 		req: Request<Body>,
-		api: Foreign<T>,
+		api: Arc<Foreign<T>>,
 	) -> Box<dyn Future<Item = serde_json::Value, Error = Error> + Send> {
 		Box::new(parse_body(req).and_then(move |val: serde_json::Value| {
-			let foreign_api = &api as &dyn ForeignRpc;
+			let foreign_api = &*api as &dyn ForeignRpc;
 			match foreign_api.handle_request(val) {
 				MaybeReply::Reply(r) => ok(r),
 				MaybeReply::DontReply => {
@@ -117,9 +216,8 @@ where
 	}
 
 	fn handle_post_request(&self, req: Request<Body>) -> NodeResponseFuture {
-		let api = Foreign::new(self.oracle.clone(), self.client.clone());
 		Box::new(
-			self.call_api(req, api)
+			self.call_api(req, self.foreign.clone())
 				.and_then(|resp| ok(json_response_pretty(&resp))),
 		)
 	}
@@ -145,6 +243,92 @@ where
 	}
 }
 
+/// V1 Json API Handler/Wrapper for owner functions
+pub struct OwnerAPIHandlerV1<T: ?Sized>
+where
+	T: OracleBackend + Send + Sync + 'static,
+{
+	/// Oracle instance
+	pub oracle: Arc<Mutex<T>>,
+	/// Candles retention, shared with the daemon
+	pub candles: Arc<RwLock<CandlesConfig>>,
+	/// Control of the polling daemon
+	pub control: Arc<PollControl>,
+	/// Configuration file the oracle was started with
+	pub config_file: Option<PathBuf>,
+}
+
+impl<T: ?Sized> OwnerAPIHandlerV1<T>
+where
+	T: OracleBackend + Send + Sync + 'static,
+{
+	/// Create a new owner API handler for POST methods
+	pub fn new(
+		oracle: Arc<Mutex<T>>,
+		candles: Arc<RwLock<CandlesConfig>>,
+		control: Arc<PollControl>,
+		config_file: Option<PathBuf>,
+	) -> Self {
+		OwnerAPIHandlerV1 {
+			oracle,
+			candles,
+			control,
+			config_file,
+		}
+	}
+
+	fn call_api(
+		&self,
+		req: Request<Body>,
+		api: Owner<T>,
+	) -> Box<dyn Future<Item = serde_json::Value, Error = Error> + Send> {
+		Box::new(parse_body(req).and_then(move |val: serde_json::Value| {
+			let owner_api = &api as &dyn OwnerRpc;
+			match owner_api.handle_request(val) {
+				MaybeReply::Reply(r) => ok(r),
+				MaybeReply::DontReply => {
+					// Since it's http, we need to return something. We return [] because jsonrpc
+					// clients will parse it as an empty batch response.
+					ok(serde_json::json!([]))
+				}
+			}
+		}))
+	}
+
+	fn handle_post_request(&self, req: Request<Body>) -> NodeResponseFuture {
+		let api = Owner::new(
+			self.oracle.clone(),
+			self.candles.clone(),
+			self.control.clone(),
+			self.config_file.clone(),
+		);
+		Box::new(
+			self.call_api(req, api)
+				.and_then(|resp| ok(json_response_pretty(&resp))),
+		)
+	}
+}
+
+impl<T: ?Sized> crate::router::Handler for OwnerAPIHandlerV1<T>
+where
+	T: OracleBackend + Send + Sync + 'static,
+{
+	fn post(&self, req: Request<Body>) -> ResponseFuture {
+		Box::new(
+			self.handle_post_request(req)
+				.and_then(|r| ok(r))
+				.or_else(|e| {
+					error!("Request Error: {:?}", e);
+					ok(create_error_response(e))
+				}),
+		)
+	}
+
+	fn options(&self, _req: Request<Body>) -> ResponseFuture {
+		Box::new(ok(create_ok_response("{}")))
+	}
+}
+
 // pretty-printed version of above
 fn json_response_pretty<T>(s: &T) -> Response<Body>
 where
@@ -203,9 +387,12 @@ fn response<T: Into<Body>>(status: StatusCode, text: T) -> Response<Body> {
 	builder.body(text.into()).unwrap()
 }
 
+/// Build the router of the foreign REST routes. The handlers of the foreign
+/// lookups delegate to the Foreign API, so that the REST and JSON-RPC APIs
+/// answer alike.
 pub fn build_router<T: ?Sized>(
-	oracle: Arc<Mutex<T>>,
-	client: Arc<alphavantage::Client>,
+	context: &ApiContext<T>,
+	foreign: Arc<Foreign<T>>,
 ) -> Result<Router, RouterError>
 where
 	T: OracleBackend + Send + Sync + 'static,
@@ -213,23 +400,35 @@ where
 	let route_list = vec![
 		"/v1/rest/exchange".to_string(),
 		"/v1/rest/recent".to_string(),
-		"/v1/rest/compact".to_string(),
 		"/v1/rest/aggregated".to_string(),
+		"/v1/rest/cross".to_string(),
+		"/v1/rest/candles".to_string(),
+		"/v1/rest/price_at".to_string(),
+		"/v1/rest/validation".to_string(),
+		"/v1/rest/twap".to_string(),
 	];
 
 	let index_handler = IndexHandler { list: route_list };
-	let exchange_handler = ExchangeHandler::new(oracle.clone(), Arc::downgrade(&client));
-	let recent_handler = RecentHandler::new(oracle.clone());
-	let compact_handler = CompactHandler::new(oracle.clone());
-	let aggregated_handler = AggregateHandler::new(oracle.clone());
+	let exchange_handler = ExchangeHandler::new(foreign.clone());
+	let recent_handler = RecentHandler::new(foreign.clone());
+	let aggregated_handler = AggregateHandler::new(foreign.clone());
+	let cross_handler = CrossHandler::new(foreign.clone());
+	let candles_handler = CandlesHandler::new(foreign.clone());
+	let price_at_handler = PriceAtHandler::new(foreign.clone());
+	let validation_handler = ValidationHandler::new(context.validator.clone());
+	let twap_handler = TwapHandler::new(foreign);
 
 	let mut router = Router::new();
 
 	router.add_route("/v1/rest", Arc::new(index_handler))?;
 	router.add_route("/v1/rest/exchange", Arc::new(exchange_handler))?;
 	router.add_route("/v1/rest/recent", Arc::new(recent_handler))?;
-	router.add_route("/v1/rest/compact", Arc::new(compact_handler))?;
 	router.add_route("/v1/rest/aggregated", Arc::new(aggregated_handler))?;
+	router.add_route("/v1/rest/cross", Arc::new(cross_handler))?;
+	router.add_route("/v1/rest/candles", Arc::new(candles_handler))?;
+	router.add_route("/v1/rest/price_at", Arc::new(price_at_handler))?;
+	router.add_route("/v1/rest/validation", Arc::new(validation_handler))?;
+	router.add_route("/v1/rest/twap", Arc::new(twap_handler))?;
 
 	Ok(router)
 }
@@ -13,21 +13,32 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use crate::foreign::Foreign;
+use crate::metrics;
+use crate::rate_limit::{RateLimitMiddleware, RateLimitStats};
 use crate::rest::*;
 use crate::router::{Handler, ResponseFuture};
 use crate::web::*;
-use chrono::{DateTime, Duration, Utc};
-use hyper::{Body, Request};
+use chrono::Utc;
+use futures::future::ok;
+use hyper::header::CONTENT_TYPE;
+use hyper::{Body, Request, Response, StatusCode};
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
 use std::sync::Arc;
-use std::sync::Weak;
 
-use alphavantage::exchange_rate::{ExchangeRate, ExchangeRateResult};
+use alphavantage::exchange_rate::ExchangeRateResult;
 use gotts_oracle_alphavantage as alphavantage;
-use gotts_oracle_lib::OracleBackend;
+use gotts_oracle_lib::cross_rate::DEFAULT_MAX_LEG_AGE_SECS;
+use gotts_oracle_lib::twap::{self, DEFAULT_MAX_GAP_SECS};
+use gotts_oracle_lib::{
+	Candle, CrossRate, OracleBackend, QuoteValidator, Resolution, SignedPriceSet, Twap,
+	ValidationStats,
+};
 use gotts_oracle_util::Mutex;
 
 /// Gets API index
-/// GET /v1/
+/// GET /v1/rest
 ///
 pub struct IndexHandler {
 	pub list: Vec<String>,
@@ -42,25 +53,23 @@ impl Handler for IndexHandler {
 }
 
 /// Gets Exchange Rate
-/// GET /v1/exchange?from=USD&to=CNY
-/// POST /v1/exchange?from=USD&to=CNY
+/// GET /v1/rest/exchange?from=USD&to=CNY
+/// POST /v1/rest/exchange?from=USD&to=CNY
 ///
 pub struct ExchangeHandler<T: ?Sized>
 where
 	T: OracleBackend + Send + Sync + 'static,
 {
-	/// Oracle instance
-	pub oracle: Arc<Mutex<T>>,
-	/// Vendor API client
-	pub client: Weak<alphavantage::Client>,
+	/// Foreign API, the handler only parsing the query
+	pub foreign: Arc<Foreign<T>>,
 }
 
 impl<T: ?Sized> ExchangeHandler<T>
 where
 	T: OracleBackend + Send + Sync + 'static,
 {
-	pub fn new(oracle: Arc<Mutex<T>>, client: Weak<alphavantage::Client>) -> ExchangeHandler<T> {
-		ExchangeHandler { oracle, client }
+	pub fn new(foreign: Arc<Foreign<T>>) -> ExchangeHandler<T> {
+		ExchangeHandler { foreign }
 	}
 
 	fn get_rate(&self, req: Request<Body>) -> Result<ExchangeRateResult, Error> {
@@ -68,41 +77,7 @@ where
 		let params = QueryParams::from(query);
 		let from = parse_param_no_err!(params, "from", "USD".to_owned());
 		let to = parse_param_no_err!(params, "to", "CNY".to_owned());
-		let arc_client = w(&self.client)?;
-
-		let exchange_rate = crossbeam::scope(|scope| {
-			let handle = scope.spawn(move |_| -> Result<ExchangeRate, Error> {
-				let exchange_result = arc_client.get_exchange_rate(&from, &to);
-				let result = match exchange_result {
-					Ok(result) => Ok(result),
-					Err(_e) => Err(ErrorKind::RequestError(
-						"query alphavantage failed!".to_owned(),
-					))?,
-				};
-
-				result
-			});
-
-			handle.join().unwrap()
-		});
-
-		let result: ExchangeRate = exchange_rate.unwrap().unwrap();
-		let exchange_rate_result = ExchangeRateResult {
-			from: result.from.code.to_string(),
-			to: result.to.code.to_string(),
-			rate: result.rate,
-			date: result.date,
-		};
-
-		// save the query data into local database for aggregation
-		{
-			let mut oracle = self.oracle.lock();
-			let mut batch = oracle.batch()?;
-			batch.save(result.date, exchange_rate_result.clone())?;
-			batch.commit()?;
-		}
-
-		Ok(exchange_rate_result)
+		self.foreign.get_rate(from, to)
 	}
 }
 
@@ -120,24 +95,24 @@ where
 }
 
 /// Gets recent exchange rates
-/// GET /v1/recent?prefix=USD&items=16
-/// GET /v1/recent?prefix=USD2CNY&items=16
-/// POST /v1/recent?prefix=USD&items=16
+/// GET /v1/rest/recent?prefix=USD&items=16
+/// GET /v1/rest/recent?prefix=USD2CNY&items=16
+/// POST /v1/rest/recent?prefix=USD&items=16
 ///
 pub struct RecentHandler<T: ?Sized>
 where
 	T: OracleBackend + Send + Sync + 'static,
 {
-	/// Oracle instance
-	pub oracle: Arc<Mutex<T>>,
+	/// Foreign API, the handler only parsing the query
+	pub foreign: Arc<Foreign<T>>,
 }
 
 impl<T: ?Sized> RecentHandler<T>
 where
 	T: OracleBackend + Send + Sync + 'static,
 {
-	pub fn new(oracle: Arc<Mutex<T>>) -> RecentHandler<T> {
-		RecentHandler { oracle }
+	pub fn new(foreign: Arc<Foreign<T>>) -> RecentHandler<T> {
+		RecentHandler { foreign }
 	}
 
 	fn get_recent(&self, req: Request<Body>) -> Result<Vec<ExchangeRateResult>, Error> {
@@ -145,19 +120,8 @@ where
 		let params = QueryParams::from(query);
 		let items = parse_param_no_err!(params, "items", "8".to_owned());
 		let items: usize = items.parse().unwrap_or(8);
-		let from = parse_param!(params, "prefix", "".to_string());
-
-		// read the recent rate data from local database
-		let oracle = self.oracle.lock();
-		let mut rates: Vec<ExchangeRateResult> = if from.is_empty() {
-			oracle.iter().collect()
-		} else {
-			oracle.iter_id(&from).collect()
-		};
-		rates.sort_by_key(|rate| std::cmp::Reverse(rate.date.clone()));
-		rates.truncate(items);
-
-		Ok(rates)
+		let prefix = parse_param!(params, "prefix", "".to_string());
+		self.foreign.get_recent(prefix, items)
 	}
 }
 
@@ -174,71 +138,233 @@ where
 	}
 }
 
-/// Compact the exchange rate data, clean the data beyond x minutes.
-/// GET /v1/compact?mins=10
-/// POST /v1/compact?mins=10
+/// Gets aggregated exchange rates
+/// GET /v1/rest/aggregated
+/// POST /v1/rest/aggregated
 ///
-pub struct CompactHandler<T: ?Sized>
+pub struct AggregateHandler<T: ?Sized>
 where
 	T: OracleBackend + Send + Sync + 'static,
 {
-	/// Oracle instance
-	pub oracle: Arc<Mutex<T>>,
+	/// Foreign API, the handler only parsing the query
+	pub foreign: Arc<Foreign<T>>,
 }
 
-impl<T: ?Sized> CompactHandler<T>
+impl<T: ?Sized> AggregateHandler<T>
 where
 	T: OracleBackend + Send + Sync + 'static,
 {
-	pub fn new(oracle: Arc<Mutex<T>>) -> CompactHandler<T> {
-		CompactHandler { oracle }
+	pub fn new(foreign: Arc<Foreign<T>>) -> AggregateHandler<T> {
+		AggregateHandler { foreign }
+	}
+
+	fn get_aggregated(&self, _req: Request<Body>) -> Result<SignedPriceSet, Error> {
+		self.foreign.get_aggregated()
 	}
+}
 
-	fn compact(&self, req: Request<Body>) -> Result<usize, Error> {
+impl<T: ?Sized> Handler for AggregateHandler<T>
+where
+	T: OracleBackend + Send + Sync + 'static,
+{
+	fn get(&self, req: Request<Body>) -> ResponseFuture {
+		result_to_response(self.get_aggregated(req))
+	}
+
+	fn post(&self, req: Request<Body>) -> ResponseFuture {
+		result_to_response(self.get_aggregated(req))
+	}
+}
+
+/// Gets a cross exchange rate, derived from the stored quotes, the legs
+/// being no older than `max_age` seconds, at most a year.
+/// GET /v1/rest/cross?from=EUR&to=CNY
+/// GET /v1/rest/cross?from=EUR&to=CNY&max_age=600
+/// POST /v1/rest/cross?from=EUR&to=CNY
+///
+pub struct CrossHandler<T: ?Sized>
+where
+	T: OracleBackend + Send + Sync + 'static,
+{
+	/// Foreign API, the handler only parsing the query
+	pub foreign: Arc<Foreign<T>>,
+}
+
+impl<T: ?Sized> CrossHandler<T>
+where
+	T: OracleBackend + Send + Sync + 'static,
+{
+	pub fn new(foreign: Arc<Foreign<T>>) -> CrossHandler<T> {
+		CrossHandler { foreign }
+	}
+
+	fn get_cross_rate(&self, req: Request<Body>) -> Result<CrossRate, Error> {
 		let query = must_get_query!(req);
 		let params = QueryParams::from(query);
-		let minutes = parse_param_no_err!(params, "mins", "10".to_owned());
-		let minutes: u32 = minutes.parse().unwrap_or(10);
-
-		let cutoff_time: DateTime<Utc> = Utc::now() - Duration::minutes(minutes as i64);
-
-		// read all rate data from local database
-		let mut oracle = self.oracle.lock();
-		let mut batch = oracle.batch()?;
-		let mut total_cleaned = 0;
-		for rate in batch.iter() {
-			if rate.date < cutoff_time {
-				total_cleaned += 1;
-				let mut id = rate.from.clone();
-				id.push('2');
-				id.push_str(&rate.to);
-				batch.delete(&id, rate.date)?;
-			}
+		let from: String = parse_param!(params, "from", "".to_owned());
+		let to: String = parse_param!(params, "to", "".to_owned());
+		let max_age: i64 = parse_param!(params, "max_age", DEFAULT_MAX_LEG_AGE_SECS);
+		let max_age = duration_secs("max_age", max_age, MAX_DURATION_SECS)?;
+		self.foreign.cross_rate(&from, &to, max_age)
+	}
+}
+
+impl<T: ?Sized> Handler for CrossHandler<T>
+where
+	T: OracleBackend + Send + Sync + 'static,
+{
+	fn get(&self, req: Request<Body>) -> ResponseFuture {
+		result_to_response(self.get_cross_rate(req))
+	}
+
+	fn post(&self, req: Request<Body>) -> ResponseFuture {
+		result_to_response(self.get_cross_rate(req))
+	}
+}
+
+/// Gets the OHLC candles of a pair, rolled up from the compacted history
+/// GET /v1/rest/candles?pair=USD2CNY&resolution=1h
+/// GET /v1/rest/candles?pair=USD2CNY&resolution=1m&from=1562050200&to=1562053800
+/// POST /v1/rest/candles?pair=USD2CNY&resolution=1d
+///
+pub struct CandlesHandler<T: ?Sized>
+where
+	T: OracleBackend + Send + Sync + 'static,
+{
+	/// Foreign API, the handler only parsing the query
+	pub foreign: Arc<Foreign<T>>,
+}
+
+impl<T: ?Sized> CandlesHandler<T>
+where
+	T: OracleBackend + Send + Sync + 'static,
+{
+	pub fn new(foreign: Arc<Foreign<T>>) -> CandlesHandler<T> {
+		CandlesHandler { foreign }
+	}
+
+	fn get_candles(&self, req: Request<Body>) -> Result<Vec<Candle>, Error> {
+		let query = must_get_query!(req);
+		let params = QueryParams::from(query);
+		let pair: String = parse_param!(params, "pair", "".to_owned());
+		let resolution: Resolution = parse_param!(params, "resolution", Resolution::Hour);
+		let from: i64 = parse_param!(params, "from", 0);
+		let to: i64 = parse_param!(params, "to", Utc::now().timestamp());
+		if pair.is_empty() {
+			return Err(ErrorKind::Argument("pair is required".to_owned()))?;
 		}
-		batch.commit()?;
+		if from > to {
+			return Err(ErrorKind::Argument("from is later than to".to_owned()))?;
+		}
+		let from = timestamp("from", from)?;
+		let to = timestamp("to", to)?;
 
-		Ok(total_cleaned)
+		self.foreign.candles(&pair, resolution, from, to)
 	}
 }
 
-impl<T: ?Sized> Handler for CompactHandler<T>
+impl<T: ?Sized> Handler for CandlesHandler<T>
 where
 	T: OracleBackend + Send + Sync + 'static,
 {
 	fn get(&self, req: Request<Body>) -> ResponseFuture {
-		result_to_response(self.compact(req))
+		result_to_response(self.get_candles(req))
 	}
 
 	fn post(&self, req: Request<Body>) -> ResponseFuture {
-		result_to_response(self.compact(req))
+		result_to_response(self.get_candles(req))
 	}
 }
 
-/// Gets aggregated exchange rates
-/// GET /v1/aggregated
-/// POST /v1/aggregated
+/// Gets the exchange rate of a pair at a point in time, that is the latest
+/// observation at or before it, or the close of the candle of the compacted
+/// history, no older than the max staleness, given in seconds up to a year
+/// GET /v1/rest/price_at?pair=USD2CNY&time=1562050200
+/// GET /v1/rest/price_at?pair=USD2CNY&time=2019-07-02T12:00:00Z&max_staleness=60
+/// POST /v1/rest/price_at?pair=USD2CNY&time=1562050200
 ///
-pub struct AggregateHandler<T: ?Sized>
+pub struct PriceAtHandler<T: ?Sized>
+where
+	T: OracleBackend + Send + Sync + 'static,
+{
+	/// Foreign API, the handler only parsing the query
+	pub foreign: Arc<Foreign<T>>,
+}
+
+impl<T: ?Sized> PriceAtHandler<T>
+where
+	T: OracleBackend + Send + Sync + 'static,
+{
+	pub fn new(foreign: Arc<Foreign<T>>) -> PriceAtHandler<T> {
+		PriceAtHandler { foreign }
+	}
+
+	fn get_price_at(&self, req: Request<Body>) -> Result<ExchangeRateResult, Error> {
+		let query = must_get_query!(req);
+		let params = QueryParams::from(query);
+		let pair: String = parse_param!(params, "pair", "".to_owned());
+		let time: String = parse_param!(params, "time", "".to_owned());
+		let max_staleness: i64 = parse_param!(
+			params,
+			"max_staleness",
+			self.foreign.max_staleness.num_seconds()
+		);
+		if pair.is_empty() {
+			return Err(ErrorKind::Argument("pair is required".to_owned()))?;
+		}
+		let time = parse_datetime(&time).ok_or(ErrorKind::Argument(
+			"time is required, as a unix timestamp or in RFC 3339 format".to_owned(),
+		))?;
+		let max_staleness = duration_secs("max_staleness", max_staleness, MAX_DURATION_SECS)?;
+		self.foreign.rate_at(&pair, time, max_staleness)
+	}
+}
+
+impl<T: ?Sized> Handler for PriceAtHandler<T>
+where
+	T: OracleBackend + Send + Sync + 'static,
+{
+	fn get(&self, req: Request<Body>) -> ResponseFuture {
+		result_to_response(self.get_price_at(req))
+	}
+
+	fn post(&self, req: Request<Body>) -> ResponseFuture {
+		result_to_response(self.get_price_at(req))
+	}
+}
+
+/// Gets the counters of the quotes validation, along with the recently
+/// rejected quotes
+/// GET /v1/rest/validation
+///
+pub struct ValidationHandler {
+	/// Sanity filters on the incoming quotes
+	pub validator: Arc<QuoteValidator>,
+}
+
+impl ValidationHandler {
+	pub fn new(validator: Arc<QuoteValidator>) -> ValidationHandler {
+		ValidationHandler { validator }
+	}
+
+	fn get_stats(&self) -> Result<ValidationStats, Error> {
+		Ok(self.validator.stats())
+	}
+}
+
+impl Handler for ValidationHandler {
+	fn get(&self, _req: Request<Body>) -> ResponseFuture {
+		result_to_response(self.get_stats())
+	}
+}
+
+/// Gets the metrics of the oracle, in the prometheus text format: the calls
+/// of the price sources per pair, the age of the last update per pair, the
+/// size of the database, the compactions and the API calls per route.
+/// Requires the API secret, like the owner routes.
+/// GET /metrics
+///
+pub struct MetricsHandler<T: ?Sized>
 where
 	T: OracleBackend + Send + Sync + 'static,
 {
@@ -246,51 +372,172 @@ where
 	pub oracle: Arc<Mutex<T>>,
 }
 
-impl<T: ?Sized> AggregateHandler<T>
+impl<T: ?Sized> MetricsHandler<T>
 where
 	T: OracleBackend + Send + Sync + 'static,
 {
-	pub fn new(oracle: Arc<Mutex<T>>) -> AggregateHandler<T> {
-		AggregateHandler { oracle }
-	}
-
-	fn get_aggregated(&self, _req: Request<Body>) -> Result<Vec<ExchangeRateResult>, Error> {
-		let oracle = self.oracle.lock();
-		let mut rates: Vec<ExchangeRateResult> = oracle.iter().collect();
-		rates.sort_by_key(|rate| std::cmp::Reverse(rate.date.clone()));
-
-		let currencies_a = vec!["EUR", "GBP", "BTC", "ETH"];
-		let currencies_b = vec!["CNY", "JPY", "CAD"];
-		let mut aggregated: Vec<ExchangeRateResult> =
-			Vec::with_capacity(currencies_a.len() + currencies_b.len());
-		for from in currencies_a {
-			let index = rates
-				.iter()
-				.position(|r| r.from == from && r.to == "USD")
-				.ok_or(ErrorKind::NotFound)?;
-			aggregated.push(rates[index].clone());
+	pub fn new(oracle: Arc<Mutex<T>>) -> MetricsHandler<T> {
+		MetricsHandler { oracle }
+	}
+
+	fn get_metrics(&self) -> Result<(String, Vec<u8>), Error> {
+		match self.oracle.lock().stats() {
+			Ok(stats) => metrics::record_db_stats(&stats),
+			Err(e) => warn!("get_metrics: failed to get the database stats. {}", e),
 		}
-		for to in currencies_b {
-			let index = rates
-				.iter()
-				.position(|r| r.from == "USD" && r.to == to)
-				.ok_or(ErrorKind::NotFound)?;
-			aggregated.push(rates[index].clone());
+		metrics::encode().map_err(|e| ErrorKind::Internal(format!("metrics: {}", e)).into())
+	}
+}
+
+impl<T: ?Sized> Handler for MetricsHandler<T>
+where
+	T: OracleBackend + Send + Sync + 'static,
+{
+	fn get(&self, _req: Request<Body>) -> ResponseFuture {
+		match self.get_metrics() {
+			Ok((content_type, buffer)) => {
+				let res = Response::builder()
+					.status(StatusCode::OK)
+					.header(CONTENT_TYPE, content_type.as_str())
+					.body(Body::from(buffer))
+					.unwrap();
+				Box::new(ok(res))
+			}
+			Err(e) => response(StatusCode::INTERNAL_SERVER_ERROR, format!("{}", e)),
 		}
+	}
+}
+
+/// Gets the counters of the rate limits of the API calls, per route and for
+/// the clients limited.
+/// GET /v1/owner/rate_limits
+///
+pub struct RateLimitStatsHandler {
+	pub rate_limit: Arc<RateLimitMiddleware>,
+}
+
+impl RateLimitStatsHandler {
+	pub fn new(rate_limit: Arc<RateLimitMiddleware>) -> RateLimitStatsHandler {
+		RateLimitStatsHandler { rate_limit }
+	}
 
-		Ok(aggregated)
+	fn get_stats(&self) -> Result<RateLimitStats, Error> {
+		Ok(self.rate_limit.stats())
 	}
 }
 
-impl<T: ?Sized> Handler for AggregateHandler<T>
+impl Handler for RateLimitStatsHandler {
+	fn get(&self, _req: Request<Body>) -> ResponseFuture {
+		result_to_response(self.get_stats())
+	}
+}
+
+/// Gets the time-weighted average price of a pair over a window ending now,
+/// up to 7 days. An observation holds for at most `max_gap` seconds, the
+/// periods without any fresh observation are reported as gaps.
+/// GET /v1/rest/twap?pair=USD2CNY&window=15m
+/// GET /v1/rest/twap?pair=USD2CNY&window=1h&max_gap=600
+/// POST /v1/rest/twap?pair=USD2CNY&window=15m
+///
+pub struct TwapHandler<T: ?Sized>
+where
+	T: OracleBackend + Send + Sync + 'static,
+{
+	/// Foreign API, the handler only parsing the query
+	pub foreign: Arc<Foreign<T>>,
+}
+
+impl<T: ?Sized> TwapHandler<T>
+where
+	T: OracleBackend + Send + Sync + 'static,
+{
+	pub fn new(foreign: Arc<Foreign<T>>) -> TwapHandler<T> {
+		TwapHandler { foreign }
+	}
+
+	fn get_twap(&self, req: Request<Body>) -> Result<Twap, Error> {
+		let query = must_get_query!(req);
+		let params = QueryParams::from(query);
+		let pair: String = parse_param!(params, "pair", "".to_owned());
+		let window: String = parse_param!(params, "window", "15m".to_owned());
+		let max_gap: i64 = parse_param!(params, "max_gap", DEFAULT_MAX_GAP_SECS);
+		if pair.is_empty() {
+			return Err(ErrorKind::Argument("pair is required".to_owned()))?;
+		}
+		let window = twap::parse_window(&window).ok_or(ErrorKind::Argument(
+			"invalid window, i.e. 30s, 15m, 1h or 1d, up to 7d".to_owned(),
+		))?;
+		let max_gap = duration_secs("max_gap", max_gap, twap::MAX_WINDOW_SECS)?;
+		self.foreign.twap(&pair, window, max_gap)
+	}
+}
+
+impl<T: ?Sized> Handler for TwapHandler<T>
 where
 	T: OracleBackend + Send + Sync + 'static,
 {
 	fn get(&self, req: Request<Body>) -> ResponseFuture {
-		result_to_response(self.get_aggregated(req))
+		result_to_response(self.get_twap(req))
 	}
 
 	fn post(&self, req: Request<Body>) -> ResponseFuture {
-		result_to_response(self.get_aggregated(req))
+		result_to_response(self.get_twap(req))
+	}
+}
+
+/// A database snapshot taken through the owner API
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Snapshot {
+	/// Directory of the snapshot, on the oracle host
+	pub path: String,
+	/// Size of the snapshot in bytes
+	pub size: u64,
+}
+
+/// Takes a snapshot of the database while the oracle keeps running, into a
+/// new directory named after the current time. Owner only.
+/// POST /v1/owner/snapshot
+///
+pub struct SnapshotHandler<T: ?Sized>
+where
+	T: OracleBackend + Send + Sync + 'static,
+{
+	/// Oracle instance
+	pub oracle: Arc<Mutex<T>>,
+	/// Directory the snapshots are taken into
+	pub snapshot_dir: PathBuf,
+}
+
+impl<T: ?Sized> SnapshotHandler<T>
+where
+	T: OracleBackend + Send + Sync + 'static,
+{
+	pub fn new(oracle: Arc<Mutex<T>>, snapshot_dir: PathBuf) -> SnapshotHandler<T> {
+		SnapshotHandler {
+			oracle,
+			snapshot_dir,
+		}
+	}
+
+	fn snapshot(&self) -> Result<Snapshot, Error> {
+		let path = self
+			.snapshot_dir
+			.join(Utc::now().format("%Y%m%d-%H%M%S").to_string());
+		let path = path.to_string_lossy().to_string();
+
+		// the oracle isn't locked during the copy, only to get the snapshotter
+		let snapshotter = self.oracle.lock().snapshotter();
+		let size = snapshotter.snapshot(&path)?;
+
+		Ok(Snapshot { path, size })
+	}
+}
+
+impl<T: ?Sized> Handler for SnapshotHandler<T>
+where
+	T: OracleBackend + Send + Sync + 'static,
+{
+	fn post(&self, _req: Request<Body>) -> ResponseFuture {
+		result_to_response(self.snapshot())
 	}
 }
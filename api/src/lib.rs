@@ -21,17 +21,28 @@ extern crate lazy_static;
 
 #[macro_use]
 extern crate log;
+#[macro_use]
+extern crate prometheus;
 
 #[macro_use]
 mod web;
+mod auth;
+mod cache;
 pub mod client;
 mod foreign;
 mod foreign_rpc;
 mod handlers;
+pub mod metrics;
+mod owner;
+mod owner_rpc;
+mod rate_limit;
 mod rest;
 mod router;
 
-pub use crate::handlers::start_rest_apis;
+pub use crate::auth::*;
+pub use crate::cache::ExchangeCache;
+pub use crate::handlers::{start_rest_apis, ApiConfig, ApiContext};
+pub use crate::rate_limit::*;
 pub use crate::rest::*;
 pub use crate::router::*;
 pub use crate::web::*;
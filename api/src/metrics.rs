@@ -0,0 +1,105 @@
+// Copyright 2019 The Gotts Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Metrics of the oracle, exposed in the prometheus text format on the
+//! `/metrics` route, which requires the API secret. The metrics of the other
+//! crates, i.e. the price sources calls and the compactions, are registered in
+//! the same default registry.
+
+use chrono::{DateTime, Utc};
+use hyper::StatusCode;
+use prometheus::{Encoder, GaugeVec, IntCounterVec, IntGauge, TextEncoder};
+use std::collections::HashMap;
+
+use gotts_oracle_lib::DbStats;
+use gotts_oracle_util::Mutex;
+
+lazy_static! {
+	static ref HTTP_REQUESTS: IntCounterVec = register_int_counter_vec!(
+		"gotts_oracle_http_requests_total",
+		"API calls, by route and status",
+		&["route", "status"]
+	)
+	.unwrap();
+	static ref POLL_FAILURES: IntCounterVec = register_int_counter_vec!(
+		"gotts_oracle_poll_failures_total",
+		"Failed queries of the price sources for a pair",
+		&["pair"]
+	)
+	.unwrap();
+	static ref LAST_UPDATE: GaugeVec = register_gauge_vec!(
+		"gotts_oracle_last_update_timestamp_seconds",
+		"Time of the last exchange rate of a pair saved",
+		&["pair"]
+	)
+	.unwrap();
+	static ref LAST_UPDATE_AGE: GaugeVec = register_gauge_vec!(
+		"gotts_oracle_last_update_age_seconds",
+		"Age of the last exchange rate of a pair saved",
+		&["pair"]
+	)
+	.unwrap();
+	static ref DB_USED_SIZE: IntGauge = register_int_gauge!(
+		"gotts_oracle_db_used_size_bytes",
+		"Space used by the database"
+	)
+	.unwrap();
+	static ref DB_MAP_SIZE: IntGauge =
+		register_int_gauge!("gotts_oracle_db_map_size_bytes", "Map size of the database").unwrap();
+	static ref DB_ENTRIES: IntGauge =
+		register_int_gauge!("gotts_oracle_db_entries", "Entries of the database").unwrap();
+	static ref LAST_UPDATES: Mutex<HashMap<String, DateTime<Utc>>> = Mutex::new(HashMap::new());
+}
+
+/// Record an API call on `route`, answered with `status`.
+pub fn record_http(route: &str, status: StatusCode) {
+	HTTP_REQUESTS
+		.with_label_values(&[route, status.as_str()])
+		.inc();
+}
+
+/// Record an exchange rate of `pair` saved at `time`.
+pub fn record_update(pair: &str, time: DateTime<Utc>) {
+	LAST_UPDATES.lock().insert(pair.to_owned(), time);
+}
+
+/// Record a failed query of the price sources for `pair`.
+pub fn record_poll_failure(pair: &str) {
+	POLL_FAILURES.with_label_values(&[pair]).inc();
+}
+
+/// Record the size of the database.
+pub fn record_db_stats(stats: &DbStats) {
+	DB_USED_SIZE.set(stats.used_size as i64);
+	DB_MAP_SIZE.set(stats.map_size as i64);
+	DB_ENTRIES.set(stats.entries as i64);
+}
+
+/// Encode all the metrics in the prometheus text format, returns the content
+/// type with the text. The ages of the last updates are computed now.
+pub fn encode() -> Result<(String, Vec<u8>), prometheus::Error> {
+	let now = Utc::now();
+	for (pair, time) in LAST_UPDATES.lock().iter() {
+		let age = now.signed_duration_since(*time).num_milliseconds() as f64 / 1000.0;
+		LAST_UPDATE
+			.with_label_values(&[pair])
+			.set(time.timestamp() as f64);
+		LAST_UPDATE_AGE.with_label_values(&[pair]).set(age);
+	}
+
+	let encoder = TextEncoder::new();
+	let mut buffer = vec![];
+	encoder.encode(&prometheus::gather(), &mut buffer)?;
+	Ok((encoder.format_type().to_owned(), buffer))
+}
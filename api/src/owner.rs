@@ -0,0 +1,273 @@
+// Copyright 2019 The Gotts Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Owner API External Definition
+
+use crate::rest::*;
+use gotts_oracle_config::{CandlesConfig, GlobalConfig};
+use gotts_oracle_lib::candles;
+use gotts_oracle_lib::{DbStats, OracleBackend};
+use gotts_oracle_sources::PollControl;
+use gotts_oracle_util::{Mutex, RwLock};
+
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use std::sync::Arc;
+
+/// Polling state of the currency pairs
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Polling {
+	/// Polled pairs, i.e. "EUR2USD", the paused ones included
+	pub pairs: Vec<String>,
+	/// Paused pairs
+	pub paused: Vec<String>,
+}
+
+/// Settings applied by a reload of the configuration file
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ReloadedConfig {
+	/// Polled pairs
+	pub pairs: Vec<String>,
+	/// Candles retention
+	pub candles: CandlesConfig,
+}
+
+/// Main interface into the administrative functions of the oracle, which are
+/// only served to the holder of the API secret. The public reads are in the
+/// ['Foreign'](struct.Foreign.html) API.
+///
+/// Methods in this API are intended to be 'single use'.
+///
+
+pub struct Owner<T: ?Sized>
+where
+	T: OracleBackend + Send + Sync + 'static,
+{
+	/// Oracle instance
+	pub oracle: Arc<Mutex<T>>,
+	/// Candles retention, shared with the compaction of the daemon
+	pub candles: Arc<RwLock<CandlesConfig>>,
+	/// Control of the polling daemon
+	pub control: Arc<PollControl>,
+	/// Configuration file the oracle was started with, if any
+	pub config_file: Option<PathBuf>,
+}
+
+impl<T: ?Sized> Owner<T>
+where
+	T: OracleBackend + Send + Sync + 'static,
+{
+	/// Create a new owner API instance.
+	///
+	/// # Arguments
+	/// * `oracle` - The oracle instance.
+	/// * `candles` - The candles retention, shared with the daemon.
+	/// * `control` - The control of the polling daemon.
+	/// * `config_file` - The configuration file, reloaded by `reload_config`.
+	///
+	/// # Returns
+	/// * An instance of the Owner API.
+	///
+
+	pub fn new(
+		oracle: Arc<Mutex<T>>,
+		candles: Arc<RwLock<CandlesConfig>>,
+		control: Arc<PollControl>,
+		config_file: Option<PathBuf>,
+	) -> Self {
+		Owner {
+			oracle,
+			candles,
+			control,
+			config_file,
+		}
+	}
+
+	/// Compact the exchange rate data, clean the data beyond x minutes after
+	/// rolling it up into candles.
+	///
+	/// # Arguments
+	/// * `minutes` - compact data which beyond specified minutes.
+	///
+	/// # Returns
+	/// * Result Containing:
+	/// * An usize of how many items has been cleaned.
+	/// * or [`Error`](struct.Error.html) if an error is encountered.
+	///
+	pub fn compact(&self, minutes: u32) -> Result<usize, Error> {
+		let cutoff_time: DateTime<Utc> = Utc::now() - Duration::minutes(minutes as i64);
+		let retention = self.candles.read().clone();
+
+		let mut oracle = self.oracle.lock();
+		let total_cleaned = candles::compact(&mut *oracle, cutoff_time, &retention)?;
+
+		Ok(total_cleaned)
+	}
+
+	/// Returns the retention of the candles rolled up by the compactions.
+	///
+	/// # Returns
+	/// * Result Containing:
+	/// * A [`CandlesConfig`](../gotts_oracle_config/struct.CandlesConfig.html)
+	/// * or [`Error`](struct.Error.html) if an error is encountered.
+	///
+	pub fn get_retention(&self) -> Result<CandlesConfig, Error> {
+		Ok(self.candles.read().clone())
+	}
+
+	/// Sets the retention of the candles, applied from the next compaction on.
+	/// The configuration file is left unchanged.
+	///
+	/// # Arguments
+	/// * `retention` - retention of the candles, in days, 0 keeping them forever.
+	///
+	/// # Returns
+	/// * Result Containing:
+	/// * `Ok(())` if successful
+	/// * or [`Error`](struct.Error.html) if an error is encountered.
+	///
+	pub fn set_retention(&self, retention: CandlesConfig) -> Result<(), Error> {
+		info!("candles retention set to {:?}", retention);
+		*self.candles.write() = retention;
+		Ok(())
+	}
+
+	/// Returns the polled pairs, and the paused ones.
+	///
+	/// # Returns
+	/// * Result Containing:
+	/// * A [`Polling`](struct.Polling.html)
+	/// * or [`Error`](struct.Error.html) if an error is encountered.
+	///
+	pub fn get_polling(&self) -> Result<Polling, Error> {
+		Ok(Polling {
+			pairs: pair_ids(self.control.pairs()),
+			paused: pair_ids(self.control.paused()),
+		})
+	}
+
+	/// Triggers a poll of the price sources for a pair, done by the daemon
+	/// right away, within the rate limits of the price sources.
+	///
+	/// # Arguments
+	/// * `from` - exchange rate from
+	/// * `to` - exchange rate to.
+	///
+	/// # Returns
+	/// * Result Containing:
+	/// * `Ok(())` if the poll is scheduled
+	/// * or an Argument [`Error`](struct.Error.html) if the pair isn't polled.
+	///
+	pub fn poll(&self, from: String, to: String) -> Result<(), Error> {
+		if !self.control.poll(&from, &to) {
+			return Err(not_polled(&from, &to))?;
+		}
+		Ok(())
+	}
+
+	/// Pauses the polling of a pair, until it's resumed.
+	///
+	/// # Arguments
+	/// * `from` - exchange rate from
+	/// * `to` - exchange rate to.
+	///
+	/// # Returns
+	/// * Result Containing:
+	/// * `Ok(())` if successful
+	/// * or an Argument [`Error`](struct.Error.html) if the pair isn't polled.
+	///
+	pub fn pause_pair(&self, from: String, to: String) -> Result<(), Error> {
+		if !self.control.pause(&from, &to) {
+			return Err(not_polled(&from, &to))?;
+		}
+		info!("polling of {}2{} paused", from, to);
+		Ok(())
+	}
+
+	/// Resumes the polling of a paused pair.
+	///
+	/// # Arguments
+	/// * `from` - exchange rate from
+	/// * `to` - exchange rate to.
+	///
+	/// # Returns
+	/// * Result Containing:
+	/// * `Ok(())` if successful
+	/// * or an Argument [`Error`](struct.Error.html) if the pair isn't polled.
+	///
+	pub fn resume_pair(&self, from: String, to: String) -> Result<(), Error> {
+		if !self.control.resume(&from, &to) {
+			return Err(not_polled(&from, &to))?;
+		}
+		info!("polling of {}2{} resumed", from, to);
+		Ok(())
+	}
+
+	/// Reloads the configuration file, applying the polled pairs and the
+	/// candles retention. The other settings, and the pairs served by the
+	/// foreign API, take a restart.
+	///
+	/// # Returns
+	/// * Result Containing:
+	/// * A [`ReloadedConfig`](struct.ReloadedConfig.html) with the applied settings
+	/// * or [`Error`](struct.Error.html) if the configuration can't be read.
+	///
+	pub fn reload_config(&self) -> Result<ReloadedConfig, Error> {
+		let config_file = self.config_file.as_ref().ok_or(ErrorKind::Internal(
+			"the oracle runs without a configuration file".to_owned(),
+		))?;
+		let config = GlobalConfig::new(&config_file.to_string_lossy())
+			.map_err(|e| ErrorKind::Internal(format!("{}", e)))?;
+		let server = config
+			.members
+			.ok_or(ErrorKind::Internal("empty configuration".to_owned()))?
+			.server;
+
+		let pairs = server.pairs.pairs();
+		self.control.set_pairs(pairs.clone());
+		*self.candles.write() = server.candles.clone();
+		info!("configuration reloaded from {}", config_file.display());
+
+		Ok(ReloadedConfig {
+			pairs: pair_ids(pairs),
+			candles: server.candles,
+		})
+	}
+
+	/// Returns the statistics of the database.
+	///
+	/// # Returns
+	/// * Result Containing:
+	/// * A [`DbStats`](../gotts_oracle_lib/types/struct.DbStats.html)
+	/// * or [`Error`](struct.Error.html) if an error is encountered.
+	///
+	pub fn get_db_stats(&self) -> Result<DbStats, Error> {
+		let oracle = self.oracle.lock();
+		let stats = oracle.stats()?;
+
+		Ok(stats)
+	}
+}
+
+fn pair_ids(pairs: Vec<(String, String)>) -> Vec<String> {
+	pairs
+		.into_iter()
+		.map(|(from, to)| format!("{}2{}", from, to))
+		.collect()
+}
+
+fn not_polled(from: &str, to: &str) -> ErrorKind {
+	ErrorKind::Argument(format!("pair {}2{} isn't polled", from, to))
+}
@@ -0,0 +1,79 @@
+// Copyright 2019 The Gotts Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! JSON-RPC Stub generation for the Owner API
+
+use crate::owner::{Owner, Polling, ReloadedConfig};
+use crate::rest::ErrorKind;
+use gotts_oracle_config::CandlesConfig;
+use gotts_oracle_lib::{DbStats, OracleBackend};
+
+/// Public definition used to generate Oracle owner jsonrpc api.
+/// * When running `gotts_oracle` with defaults, the V1 owner json api is available at
+/// `localhost:3518/v1/owner`, provided an API secret is configured
+/// * The endpoint only supports POST operations, with the json-rpc request as the body,
+/// and the API secret as the basic auth password of the `gotts` user
+#[easy_jsonrpc_mw::rpc]
+pub trait OwnerRpc: Sync + Send {
+	fn compact(&self, minutes: u32) -> Result<usize, ErrorKind>;
+	fn get_retention(&self) -> Result<CandlesConfig, ErrorKind>;
+	fn set_retention(&self, retention: CandlesConfig) -> Result<(), ErrorKind>;
+	fn get_polling(&self) -> Result<Polling, ErrorKind>;
+	fn poll(&self, from: String, to: String) -> Result<(), ErrorKind>;
+	fn pause_pair(&self, from: String, to: String) -> Result<(), ErrorKind>;
+	fn resume_pair(&self, from: String, to: String) -> Result<(), ErrorKind>;
+	fn reload_config(&self) -> Result<ReloadedConfig, ErrorKind>;
+	fn get_db_stats(&self) -> Result<DbStats, ErrorKind>;
+}
+
+impl<T: ?Sized> OwnerRpc for Owner<T>
+where
+	T: OracleBackend + Send + Sync + 'static,
+{
+	fn compact(&self, minutes: u32) -> Result<usize, ErrorKind> {
+		Owner::compact(self, minutes).map_err(|e| e.kind().clone())
+	}
+
+	fn get_retention(&self) -> Result<CandlesConfig, ErrorKind> {
+		Owner::get_retention(self).map_err(|e| e.kind().clone())
+	}
+
+	fn set_retention(&self, retention: CandlesConfig) -> Result<(), ErrorKind> {
+		Owner::set_retention(self, retention).map_err(|e| e.kind().clone())
+	}
+
+	fn get_polling(&self) -> Result<Polling, ErrorKind> {
+		Owner::get_polling(self).map_err(|e| e.kind().clone())
+	}
+
+	fn poll(&self, from: String, to: String) -> Result<(), ErrorKind> {
+		Owner::poll(self, from, to).map_err(|e| e.kind().clone())
+	}
+
+	fn pause_pair(&self, from: String, to: String) -> Result<(), ErrorKind> {
+		Owner::pause_pair(self, from, to).map_err(|e| e.kind().clone())
+	}
+
+	fn resume_pair(&self, from: String, to: String) -> Result<(), ErrorKind> {
+		Owner::resume_pair(self, from, to).map_err(|e| e.kind().clone())
+	}
+
+	fn reload_config(&self) -> Result<ReloadedConfig, ErrorKind> {
+		Owner::reload_config(self).map_err(|e| e.kind().clone())
+	}
+
+	fn get_db_stats(&self) -> Result<DbStats, ErrorKind> {
+		Owner::get_db_stats(self).map_err(|e| e.kind().clone())
+	}
+}
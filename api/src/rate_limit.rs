@@ -0,0 +1,193 @@
+// Copyright 2019 The Gotts Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Rate limits of the API calls of each client, so that no client can
+//! exhaust the quota of the price sources keys on its own.
+
+use crate::auth::ApiSecretAuth;
+use crate::router::{Handler, HandlerObj, RemoteAddr, ResponseFuture};
+use crate::web::response;
+use futures::future::ok;
+use hyper::header::RETRY_AFTER;
+use hyper::{Body, Request, Response, StatusCode};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::time::Instant;
+
+use gotts_oracle_config::RateLimitConfig;
+use gotts_oracle_sources::TokenBucket;
+use gotts_oracle_util::Mutex;
+
+/// Route of the limit shared by all the routes not configured
+pub const OTHER_ROUTES: &'static str = "*";
+
+/// Client of the calls carrying the API secret
+pub const API_SECRET_CLIENT: &'static str = "api_secret";
+
+/// Above this count of tracked clients, the ones with a full bucket are
+/// forgotten, their calls being all left anyway.
+const MAX_TRACKED_CLIENTS: usize = 10_000;
+
+/// Maximum count of the limited clients reported in the stats
+const MAX_REPORTED_CLIENTS: usize = 100;
+
+/// Calls of a route, allowed or limited, since the start of the server
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct RouteStats {
+	pub allowed: u64,
+	pub limited: u64,
+}
+
+/// Counters of the rate limits
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct RateLimitStats {
+	/// Calls per route, the routes not configured being counted under "*"
+	pub routes: BTreeMap<String, RouteStats>,
+	/// Limited calls per client, for the first clients limited
+	pub limited_clients: BTreeMap<String, u64>,
+	/// Count of the clients currently tracked
+	pub clients: usize,
+}
+
+/// Rate limiting middleware, keeping a token bucket per client and route.
+/// A client is identified by the API secret, when it gives it, or else by
+/// its IP address. The calls beyond the limits get a 429 response, with the
+/// seconds to wait in the Retry-After header.
+pub struct RateLimitMiddleware {
+	config: RateLimitConfig,
+	secret: Option<ApiSecretAuth>,
+	buckets: Mutex<HashMap<(String, String), TokenBucket>>,
+	stats: Mutex<RateLimitStats>,
+}
+
+impl RateLimitMiddleware {
+	pub fn new(config: RateLimitConfig, api_secret: Option<&str>) -> RateLimitMiddleware {
+		RateLimitMiddleware {
+			config,
+			secret: api_secret.map(ApiSecretAuth::new),
+			buckets: Mutex::new(HashMap::new()),
+			stats: Mutex::new(RateLimitStats::default()),
+		}
+	}
+
+	/// The counters of the calls since the start of the server.
+	pub fn stats(&self) -> RateLimitStats {
+		let mut stats = self.stats.lock().clone();
+		stats.clients = self.buckets.lock().len();
+		stats
+	}
+
+	/// The client of a request.
+	fn client(&self, req: &Request<Body>) -> String {
+		if let Some(ref secret) = self.secret {
+			if secret.is_authorized(req) {
+				return API_SECRET_CLIENT.to_string();
+			}
+		}
+		match req.extensions().get::<RemoteAddr>() {
+			Some(RemoteAddr(addr)) => format!("ip:{}", addr.ip()),
+			None => "unknown".to_string(),
+		}
+	}
+
+	/// The route of the limit of a path, with the calls per minute.
+	fn limit(&self, path: &str) -> (String, u32) {
+		match self.config.routes.get(path) {
+			Some(per_minute) => (path.to_string(), *per_minute),
+			None => (OTHER_ROUTES.to_string(), self.config.calls_per_minute),
+		}
+	}
+
+	/// Take a call of the client on the route, returns the seconds to wait
+	/// before the next call if it's limited.
+	fn take(&self, client: String, route: String, per_minute: u32, now: Instant) -> Option<u64> {
+		let mut buckets = self.buckets.lock();
+		if buckets.len() >= MAX_TRACKED_CLIENTS {
+			buckets.retain(|_, bucket| !bucket.is_full(now));
+		}
+		let capacity = self.config.burst.min(per_minute).max(1);
+		let bucket = buckets
+			.entry((client, route))
+			.or_insert_with(|| TokenBucket::new(capacity, per_minute, now));
+		if bucket.try_take(now) {
+			None
+		} else {
+			let wait = bucket
+				.next_available(now)
+				.map(|d| d.as_secs() + if d.subsec_nanos() > 0 { 1 } else { 0 })
+				.unwrap_or(60);
+			Some(wait.max(1))
+		}
+	}
+
+	fn count(&self, client: &str, route: &str, limited: bool) {
+		let mut stats = self.stats.lock();
+		let route_stats = stats.routes.entry(route.to_string()).or_default();
+		if limited {
+			route_stats.limited += 1;
+			if stats.limited_clients.len() < MAX_REPORTED_CLIENTS
+				|| stats.limited_clients.contains_key(client)
+			{
+				*stats.limited_clients.entry(client.to_string()).or_insert(0) += 1;
+			}
+		} else {
+			route_stats.allowed += 1;
+		}
+	}
+}
+
+impl Handler for RateLimitMiddleware {
+	fn call(
+		&self,
+		req: Request<Body>,
+		mut handlers: Box<dyn Iterator<Item = HandlerObj>>,
+	) -> ResponseFuture {
+		let next_handler = match handlers.next() {
+			Some(h) => h,
+			None => return response(StatusCode::INTERNAL_SERVER_ERROR, "no handler found"),
+		};
+
+		let (route, per_minute) = self.limit(req.uri().path());
+		if per_minute == 0 {
+			self.count("", &route, false);
+			return next_handler.call(req, handlers);
+		}
+		let client = self.client(&req);
+		match self.take(client.clone(), route.clone(), per_minute, Instant::now()) {
+			None => {
+				self.count(&client, &route, false);
+				next_handler.call(req, handlers)
+			}
+			Some(retry_after) => {
+				self.count(&client, &route, true);
+				debug!(
+					"Rate limited API call of {}: {} {}",
+					client,
+					req.method(),
+					req.uri().path()
+				);
+				too_many_requests_response(retry_after)
+			}
+		}
+	}
+}
+
+fn too_many_requests_response(retry_after: u64) -> ResponseFuture {
+	let response = Response::builder()
+		.status(StatusCode::TOO_MANY_REQUESTS)
+		.header(RETRY_AFTER, retry_after.to_string().as_str())
+		.body(Body::from("too many requests"))
+		.unwrap();
+	Box::new(ok(response))
+}
@@ -19,13 +19,17 @@
 //! To use it, just have your service(s) implement the ApiEndpoint trait and
 //! register them on a ApiServer.
 
-use crate::router::{Handler, HandlerObj, ResponseFuture, Router, RouterError};
+use crate::metrics;
+use crate::router::{Handler, HandlerObj, MatchedRoute, ResponseFuture, Router, RouterError};
 use crate::web::response;
 use failure::{Backtrace, Context, Fail, ResultExt};
 use futures::sync::oneshot;
 use futures::Stream;
 use gotts_oracle_lib;
+use gotts_oracle_util::RwLock;
 use hyper::rt::Future;
+use hyper::server::conn::AddrStream;
+use hyper::service::{make_service_fn, service_fn};
 use hyper::{rt, Body, Request, Server, StatusCode};
 use rustls;
 use rustls::internal::pemfile;
@@ -35,8 +39,8 @@ use std::fs::File;
 use std::net::SocketAddr;
 use std::sync::{Arc, Weak};
 use std::{io, thread};
-use tokio_rustls::ServerConfigExt;
-use tokio_tcp;
+use tokio_rustls::{ServerConfigExt, TlsStream};
+use tokio_tcp::{self, TcpStream};
 
 /// Errors that can be returned by an ApiEndpoint implementation.
 #[derive(Debug)]
@@ -52,6 +56,10 @@ pub enum ErrorKind {
 	Argument(String),
 	#[fail(display = "Not found.")]
 	NotFound,
+	#[fail(display = "Stale: {}", _0)]
+	Stale(String),
+	#[fail(display = "Price sources error: {}", _0)]
+	Vendor(String),
 	#[fail(display = "Request error: {}", _0)]
 	RequestError(String),
 	#[fail(display = "ResponseError error: {}", _0)]
@@ -100,8 +108,13 @@ impl From<Context<ErrorKind>> for Error {
 
 impl From<gotts_oracle_lib::Error> for Error {
 	fn from(error: gotts_oracle_lib::Error) -> Error {
+		let kind = match error.kind() {
+			gotts_oracle_lib::ErrorKind::NotFound(_) => ErrorKind::NotFound,
+			gotts_oracle_lib::ErrorKind::Stale(msg) => ErrorKind::Stale(msg),
+			_ => ErrorKind::LibOracle(error.to_string()),
+		};
 		Error {
-			inner: Context::new(ErrorKind::LibOracle(error.to_string())),
+			inner: Context::new(kind),
 		}
 	}
 }
@@ -114,11 +127,13 @@ impl From<RouterError> for Error {
 	}
 }
 
-/// TLS config
+/// TLS config. With a client CA, the clients must authenticate with a
+/// certificate signed by it.
 #[derive(Clone)]
 pub struct TLSConfig {
 	pub certificate: String,
 	pub private_key: String,
+	pub client_ca: Option<String>,
 }
 
 impl TLSConfig {
@@ -126,9 +141,17 @@ impl TLSConfig {
 		TLSConfig {
 			certificate,
 			private_key,
+			client_ca: None,
 		}
 	}
 
+	/// Require the clients to authenticate with a certificate signed by one of
+	/// the CA certificates of the `client_ca` file
+	pub fn with_client_ca(mut self, client_ca: String) -> TLSConfig {
+		self.client_ca = Some(client_ca);
+		self
+	}
+
 	fn load_certs(&self) -> Result<Vec<rustls::Certificate>, Error> {
 		let certfile = File::open(&self.certificate).context(ErrorKind::Internal(format!(
 			"failed to open file {}",
@@ -136,42 +159,137 @@ impl TLSConfig {
 		)))?;
 		let mut reader = io::BufReader::new(certfile);
 
-		pemfile::certs(&mut reader)
-			.map_err(|_| ErrorKind::Internal("failed to load certificate".to_string()).into())
+		let certs = pemfile::certs(&mut reader).map_err(|_| {
+			ErrorKind::Internal(format!("failed to load certificate {}", self.certificate))
+		})?;
+		if certs.is_empty() {
+			return Err(ErrorKind::Internal(format!(
+				"no certificate found in {}",
+				self.certificate
+			)))?;
+		}
+		Ok(certs)
 	}
 
 	fn load_private_key(&self) -> Result<rustls::PrivateKey, Error> {
-		let keyfile = File::open(&self.private_key).context(ErrorKind::Internal(format!(
+		let read_keys = |pkcs8: bool| -> Result<Vec<rustls::PrivateKey>, Error> {
+			let keyfile = File::open(&self.private_key).context(ErrorKind::Internal(format!(
+				"failed to open file {}",
+				self.private_key
+			)))?;
+			let mut reader = io::BufReader::new(keyfile);
+			let keys = if pkcs8 {
+				pemfile::pkcs8_private_keys(&mut reader)
+			} else {
+				pemfile::rsa_private_keys(&mut reader)
+			};
+			keys.map_err(|_| {
+				ErrorKind::Internal(format!("failed to load private key {}", self.private_key))
+					.into()
+			})
+		};
+
+		let mut keys = read_keys(true)?;
+		if keys.is_empty() {
+			keys = read_keys(false)?;
+		}
+		match keys.len() {
+			0 => Err(ErrorKind::Internal(format!(
+				"no PKCS8 or RSA private key found in {}",
+				self.private_key
+			))
+			.into()),
+			1 => Ok(keys[0].clone()),
+			_ => Err(ErrorKind::Internal(format!(
+				"expected a single private key in {}",
+				self.private_key
+			))
+			.into()),
+		}
+	}
+
+	fn load_client_roots(&self) -> Result<Option<rustls::RootCertStore>, Error> {
+		let client_ca = match &self.client_ca {
+			Some(client_ca) => client_ca,
+			None => return Ok(None),
+		};
+		let cafile = File::open(client_ca).context(ErrorKind::Internal(format!(
 			"failed to open file {}",
-			self.private_key
+			client_ca
 		)))?;
-		let mut reader = io::BufReader::new(keyfile);
-
-		let keys = pemfile::pkcs8_private_keys(&mut reader)
-			.map_err(|_| ErrorKind::Internal("failed to load private key".to_string()))?;
-		if keys.len() != 1 {
-			return Err(ErrorKind::Internal(
-				"expected a single private key".to_string(),
-			))?;
+		let mut reader = io::BufReader::new(cafile);
+
+		let mut roots = rustls::RootCertStore::empty();
+		match roots.add_pem_file(&mut reader) {
+			Ok((valid, _)) if valid > 0 => Ok(Some(roots)),
+			_ => Err(ErrorKind::Internal(format!(
+				"no valid CA certificate found in {}",
+				client_ca
+			))
+			.into()),
 		}
-		Ok(keys[0].clone())
 	}
 
 	pub fn build_server_config(&self) -> Result<Arc<rustls::ServerConfig>, Error> {
 		let certs = self.load_certs()?;
 		let key = self.load_private_key()?;
-		let mut cfg = rustls::ServerConfig::new(rustls::NoClientAuth::new());
+		let mut cfg = match self.load_client_roots()? {
+			Some(roots) => {
+				rustls::ServerConfig::new(rustls::AllowAnyAuthenticatedClient::new(roots))
+			}
+			None => rustls::ServerConfig::new(rustls::NoClientAuth::new()),
+		};
 		cfg.set_single_cert(certs, key)
-			.context(ErrorKind::Internal(
-				"set single certificate failed".to_string(),
-			))?;
+			.context(ErrorKind::Internal(format!(
+				"invalid certificate {} or private key {}",
+				self.certificate, self.private_key
+			)))?;
 		Ok(Arc::new(cfg))
 	}
+
+	/// Check the certificate, the private key and the client CA certificates
+	/// can be loaded
+	pub fn validate(&self) -> Result<(), Error> {
+		self.build_server_config().map(|_| ())
+	}
+}
+
+/// The TLS configuration of a running server, which can be reloaded from its
+/// files, i.e. once the certificate is renewed. The connections already
+/// established keep the configuration they were accepted with.
+#[derive(Clone)]
+pub struct ReloadableTLSConfig {
+	conf: TLSConfig,
+	server_config: Arc<RwLock<Arc<rustls::ServerConfig>>>,
+}
+
+impl ReloadableTLSConfig {
+	fn new(conf: TLSConfig) -> Result<ReloadableTLSConfig, Error> {
+		let server_config = conf.build_server_config()?;
+		Ok(ReloadableTLSConfig {
+			conf,
+			server_config: Arc::new(RwLock::new(server_config)),
+		})
+	}
+
+	/// Reload the configuration from its files, the current one being kept if
+	/// they are invalid
+	pub fn reload(&self) -> Result<(), Error> {
+		let server_config = self.conf.build_server_config()?;
+		*self.server_config.write() = server_config;
+		info!("TLS configuration reloaded from {}", self.conf.certificate);
+		Ok(())
+	}
+
+	fn current(&self) -> Arc<rustls::ServerConfig> {
+		self.server_config.read().clone()
+	}
 }
 
 /// HTTP server allowing the registration of ApiEndpoint implementations.
 pub struct ApiServer {
 	shutdown_sender: Option<oneshot::Sender<()>>,
+	tls_config: Option<ReloadableTLSConfig>,
 }
 
 impl ApiServer {
@@ -180,11 +298,16 @@ impl ApiServer {
 	pub fn new() -> ApiServer {
 		ApiServer {
 			shutdown_sender: None,
+			tls_config: None,
 		}
 	}
 
+	/// The TLS configuration of the server, if it's serving HTTPS.
+	pub fn tls_config(&self) -> Option<ReloadableTLSConfig> {
+		self.tls_config.clone()
+	}
+
 	/// Starts ApiServer at the provided address.
-	/// TODO support stop operation
 	pub fn start(
 		&mut self,
 		addr: SocketAddr,
@@ -208,15 +331,24 @@ impl ApiServer {
 				"Can't start HTTP API server, it's running already".to_string(),
 			))?;
 		}
-		let (tx, _rx) = oneshot::channel::<()>();
+		let server = Server::try_bind(&addr).map_err(|e| {
+			ErrorKind::Internal(format!("failed to bind API server on {}: {}", addr, e))
+		})?;
+		let (tx, rx) = oneshot::channel::<()>();
 		self.shutdown_sender = Some(tx);
 		thread::Builder::new()
 			.name("apis".to_string())
 			.spawn(move || {
-				let server = Server::bind(&addr)
-					.serve(router)
-					// TODO graceful shutdown is unstable, investigate
-					//.with_graceful_shutdown(rx)
+				// the router is shared by the connections, each one routing its
+				// requests with the address of its client
+				let router = Arc::new(router);
+				let server = server
+					.serve(make_service_fn(move |conn: &AddrStream| {
+						let remote_addr = conn.remote_addr();
+						let router = router.clone();
+						service_fn(move |req| router.handle(req, Some(remote_addr)))
+					}))
+					.with_graceful_shutdown(rx)
 					.map_err(|e| eprintln!("HTTP API server error: {}", e));
 
 				rt::run(server);
@@ -225,7 +357,6 @@ impl ApiServer {
 	}
 
 	/// Starts the TLS ApiServer at the provided address.
-	/// TODO support stop operation
 	fn start_tls(
 		&mut self,
 		addr: SocketAddr,
@@ -238,15 +369,20 @@ impl ApiServer {
 			))?;
 		}
 
-		let tls_conf = conf.build_server_config()?;
+		let tls_conf = ReloadableTLSConfig::new(conf)?;
+		let listener = tokio_tcp::TcpListener::bind(&addr).map_err(|e| {
+			ErrorKind::Internal(format!("failed to bind API server on {}: {}", addr, e))
+		})?;
+		let (tx, rx) = oneshot::channel::<()>();
+		self.shutdown_sender = Some(tx);
+		self.tls_config = Some(tls_conf.clone());
 
 		thread::Builder::new()
 			.name("apis".to_string())
 			.spawn(move || {
-				let listener = tokio_tcp::TcpListener::bind(&addr).expect("failed to bind");
 				let tls = listener
 					.incoming()
-					.and_then(move |s| tls_conf.accept_async(s))
+					.and_then(move |s| tls_conf.current().accept_async(s))
 					.then(|r| match r {
 						Ok(x) => Ok::<_, io::Error>(Some(x)),
 						Err(e) => {
@@ -255,8 +391,16 @@ impl ApiServer {
 						}
 					})
 					.filter_map(|x| x);
+				let router = Arc::new(router);
 				let server = Server::builder(tls)
-					.serve(router)
+					.serve(make_service_fn(
+						move |conn: &TlsStream<TcpStream, rustls::ServerSession>| {
+							let remote_addr = conn.get_ref().0.peer_addr().ok();
+							let router = router.clone();
+							service_fn(move |req| router.handle(req, remote_addr))
+						},
+					))
+					.with_graceful_shutdown(rx)
 					.map_err(|e| eprintln!("HTTP API server error: {}", e));
 
 				rt::run(server);
@@ -264,21 +408,28 @@ impl ApiServer {
 			.map_err(|_| ErrorKind::Internal("failed to spawn API thread".to_string()).into())
 	}
 
-	/// Stops the API server, it panics in case of error
+	/// Stops the API server. It stops accepting new connections, the
+	/// requests in flight are completed before the server thread exits.
 	pub fn stop(&mut self) -> bool {
-		if self.shutdown_sender.is_some() {
-			// TODO re-enable stop after investigation
-			//let tx = mem::replace(&mut self.shutdown_sender, None).unwrap();
-			//tx.send(()).expect("Failed to stop API server");
-			info!("API server has been stoped");
-			true
-		} else {
-			error!("Can't stop API server, it's not running or doesn't spport stop operation");
-			false
+		match self.shutdown_sender.take() {
+			Some(tx) => {
+				self.tls_config = None;
+				if tx.send(()).is_err() {
+					error!("Can't stop API server, its thread has already exited");
+					return false;
+				}
+				info!("API server has been stopped");
+				true
+			}
+			None => {
+				error!("Can't stop API server, it's not running");
+				false
+			}
 		}
 	}
 }
 
+/// Logging middleware, counting the API calls by route and status too.
 pub struct LoggingMiddleware {}
 
 impl Handler for LoggingMiddleware {
@@ -288,8 +439,17 @@ impl Handler for LoggingMiddleware {
 		mut handlers: Box<dyn Iterator<Item = HandlerObj>>,
 	) -> ResponseFuture {
 		debug!("REST call: {} {}", req.method(), req.uri().path());
+		// counted by route pattern, the paths of the wildcard routes being
+		// unbounded
+		let route = req
+			.extensions()
+			.get::<MatchedRoute>()
+			.map_or("unmatched", |r| r.0);
 		match handlers.next() {
-			Some(handler) => handler.call(req, handlers),
+			Some(handler) => Box::new(handler.call(req, handlers).map(move |res| {
+				metrics::record_http(route, res.status());
+				res
+			})),
 			None => response(StatusCode::INTERNAL_SERVER_ERROR, "no handler found"),
 		}
 	}
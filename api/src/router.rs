@@ -21,6 +21,7 @@ use hyper::{Body, Method, Request, Response, StatusCode};
 use serde::{Deserialize, Serialize};
 use std::collections::hash_map::DefaultHasher;
 use std::hash::{Hash, Hasher};
+use std::net::SocketAddr;
 use std::sync::Arc;
 
 lazy_static! {
@@ -109,9 +110,20 @@ const MAX_CHILDREN: usize = 16;
 
 pub type HandlerObj = Arc<dyn Handler + Send + Sync>;
 
+/// Address of the client of a request, set by the server in the extensions
+/// of the request before routing it.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RemoteAddr(pub SocketAddr);
+
+/// Route matched by a request, i.e. `/v1/zzz/*` for `/v1/zzz/1`, set by the
+/// router in the extensions of the request before calling its handlers.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct MatchedRoute(pub &'static str);
+
 #[derive(Clone)]
 pub struct Node {
 	key: u64,
+	route: Option<&'static str>,
 	value: Option<HandlerObj>,
 	children: [NodeId; MAX_CHILDREN],
 	children_count: usize,
@@ -175,14 +187,46 @@ impl Router {
 		match self.node(node_id).value() {
 			None => {
 				let node = self.node_mut(node_id);
-				node.set_value(value);
+				node.set_value(route, value);
 				Ok(node)
 			}
 			Some(_) => Err(RouterError::RouteAlreadyExists),
 		}
 	}
 
+	/// Route a request from the client at `remote_addr`, to the handlers of
+	/// its path.
+	pub fn handle(
+		&self,
+		mut req: Request<Body>,
+		remote_addr: Option<SocketAddr>,
+	) -> ResponseFuture {
+		if let Some(addr) = remote_addr {
+			req.extensions_mut().insert(RemoteAddr(addr));
+		}
+		self.route(req)
+	}
+
+	fn route(&self, mut req: Request<Body>) -> ResponseFuture {
+		match self.lookup(req.uri().path()) {
+			Err(_) => not_found(),
+			Ok((route, handlers)) => {
+				req.extensions_mut().insert(MatchedRoute(route));
+				let mut handlers = handlers.into_iter();
+				match handlers.next() {
+					None => not_found(),
+					Some(h) => h.call(req, Box::new(handlers)),
+				}
+			}
+		}
+	}
+
 	pub fn get(&self, path: &str) -> Result<impl Iterator<Item = HandlerObj>, RouterError> {
+		self.lookup(path).map(|(_, handlers)| handlers.into_iter())
+	}
+
+	/// The route matching a path and its handlers, the middlewares first
+	fn lookup(&self, path: &str) -> Result<(&'static str, Vec<HandlerObj>), RouterError> {
 		let keys = generate_path(path);
 		let mut handlers = vec![];
 		let mut node_id = self.root();
@@ -196,11 +240,13 @@ impl Router {
 			}
 		}
 
-		if let Some(h) = self.node(node_id).value() {
-			handlers.push(h);
-			Ok(handlers.into_iter())
-		} else {
-			Err(RouterError::NoValue)
+		let node = self.node(node_id);
+		match (node.route, node.value()) {
+			(Some(route), Some(h)) => {
+				handlers.push(h);
+				Ok((route, handlers))
+			}
+			_ => Err(RouterError::NoValue),
 		}
 	}
 }
@@ -212,13 +258,7 @@ impl Service for Router {
 	type Future = ResponseFuture;
 
 	fn call(&mut self, req: Request<Self::ReqBody>) -> Self::Future {
-		match self.get(req.uri().path()) {
-			Err(_) => not_found(),
-			Ok(mut handlers) => match handlers.next() {
-				None => not_found(),
-				Some(h) => h.call(req, Box::new(handlers)),
-			},
-		}
+		self.route(req)
 	}
 }
 
@@ -238,6 +278,7 @@ impl Node {
 	fn new(key: u64, value: Option<HandlerObj>) -> Node {
 		Node {
 			key,
+			route: None,
 			value,
 			children: [NodeId(0); MAX_CHILDREN],
 			children_count: 0,
@@ -262,7 +303,8 @@ impl Node {
 		}
 	}
 
-	fn set_value(&mut self, value: HandlerObj) {
+	fn set_value(&mut self, route: &'static str, value: HandlerObj) {
+		self.route = Some(route);
 		self.value = Some(value);
 	}
 
@@ -379,5 +421,9 @@ mod tests {
 		assert_eq!(call_handler("/v1/zzz/1"), 103);
 		assert_eq!(call_handler("/v1/zzz/2"), 103);
 		assert_eq!(call_handler("/v1/zzz/2/zzz"), 106);
+
+		assert_eq!(routes.lookup("/v1/users/xxx").unwrap().0, "/v1/users/xxx");
+		assert_eq!(routes.lookup("/v1/zzz/1").unwrap().0, "/v1/zzz/*");
+		assert_eq!(routes.lookup("/v1/zzz/2/zzz").unwrap().0, "/v1/zzz/*/zzz");
 	}
 }
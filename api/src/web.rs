@@ -15,6 +15,7 @@
 
 use crate::rest::*;
 use crate::router::ResponseFuture;
+use chrono::{DateTime, Duration, TimeZone, Utc};
 use futures::future::{err, ok};
 use futures::{Future, Stream};
 use hyper::{Body, Request, Response, StatusCode};
@@ -53,6 +54,8 @@ where
 			ErrorKind::Argument(msg) => response(StatusCode::BAD_REQUEST, msg.clone()),
 			ErrorKind::RequestError(msg) => response(StatusCode::BAD_REQUEST, msg.clone()),
 			ErrorKind::NotFound => response(StatusCode::NOT_FOUND, ""),
+			ErrorKind::Stale(msg) => response(StatusCode::UNPROCESSABLE_ENTITY, msg.clone()),
+			ErrorKind::Vendor(msg) => response(StatusCode::BAD_GATEWAY, msg.clone()),
 			ErrorKind::Internal(msg) => response(StatusCode::INTERNAL_SERVER_ERROR, msg.clone()),
 			ErrorKind::ResponseError(msg) => {
 				response(StatusCode::INTERNAL_SERVER_ERROR, msg.clone())
@@ -101,6 +104,37 @@ pub fn response<T: Into<Body> + Debug>(status: StatusCode, text: T) -> ResponseF
 	Box::new(ok(just_response(status, text)))
 }
 
+/// Parse a date given either as a unix timestamp in seconds, or in RFC 3339 format
+pub fn parse_datetime(value: &str) -> Option<DateTime<Utc>> {
+	if let Ok(timestamp) = value.parse::<i64>() {
+		return Utc.timestamp_opt(timestamp, 0).single();
+	}
+	DateTime::parse_from_rfc3339(value)
+		.ok()
+		.map(|date| date.with_timezone(&Utc))
+}
+
+/// A date parameter given as a unix timestamp in seconds
+pub fn timestamp(name: &str, secs: i64) -> Result<DateTime<Utc>, Error> {
+	Utc.timestamp_opt(secs, 0)
+		.single()
+		.ok_or_else(|| ErrorKind::Argument(format!("{} is out of range", name)).into())
+}
+
+/// Longest duration given in seconds in the query parameters, a year
+pub const MAX_DURATION_SECS: i64 = 366 * 24 * 3600;
+
+/// A duration parameter given in seconds, which must be in `0..=max_secs`
+pub fn duration_secs(name: &str, secs: i64, max_secs: i64) -> Result<Duration, Error> {
+	if secs < 0 || secs > max_secs {
+		return Err(ErrorKind::Argument(format!(
+			"{} must be between 0 and {} seconds",
+			name, max_secs
+		)))?;
+	}
+	Ok(Duration::seconds(secs))
+}
+
 pub struct QueryParams {
 	params: HashMap<String, Vec<String>>,
 }
@@ -0,0 +1,353 @@
+// Copyright 2019 The Gotts Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use gotts_oracle_api as api;
+
+use self::api::*;
+use gotts_oracle_config::RateLimitConfig;
+use hyper::{Body, Request, StatusCode};
+use rustls::internal::pemfile;
+use std::fs::{self, File};
+use std::io::{self, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::{thread, time};
+
+struct IndexHandler {
+	list: Vec<String>,
+}
+
+impl Handler for IndexHandler {
+	fn get(&self, _req: Request<Body>) -> ResponseFuture {
+		json_response_pretty(&self.list)
+	}
+}
+
+pub struct CounterMiddleware {
+	counter: AtomicUsize,
+}
+
+impl CounterMiddleware {
+	fn new() -> CounterMiddleware {
+		CounterMiddleware {
+			counter: AtomicUsize::new(0),
+		}
+	}
+
+	fn value(&self) -> usize {
+		self.counter.load(Ordering::SeqCst)
+	}
+}
+
+impl Handler for CounterMiddleware {
+	fn call(
+		&self,
+		req: Request<Body>,
+		mut handlers: Box<dyn Iterator<Item = HandlerObj>>,
+	) -> ResponseFuture {
+		self.counter.fetch_add(1, Ordering::SeqCst);
+		match handlers.next() {
+			Some(h) => h.call(req, handlers),
+			None => response(StatusCode::INTERNAL_SERVER_ERROR, "no handler found"),
+		}
+	}
+}
+
+fn build_router() -> Router {
+	let route_list = vec!["get blocks".to_string(), "get chain".to_string()];
+	let index_handler = IndexHandler { list: route_list };
+	let mut router = Router::new();
+	router
+		.add_route("/v1/*", Arc::new(index_handler))
+		.expect("add_route failed");
+	router
+}
+
+fn request_with_retry(url: &str) -> Result<Vec<String>, api::Error> {
+	let mut tries = 0;
+	loop {
+		let res = api::client::get::<Vec<String>>(url, None);
+		if res.is_ok() {
+			return res;
+		}
+		if tries > 5 {
+			return res;
+		}
+		tries += 1;
+		thread::sleep(time::Duration::from_millis(500));
+	}
+}
+
+#[test]
+fn test_start_stop_api() {
+	let mut server = ApiServer::new();
+	let mut router = build_router();
+	let counter = Arc::new(CounterMiddleware::new());
+	router.add_middleware(counter.clone());
+	let server_addr = "127.0.0.1:14434";
+	let addr: SocketAddr = server_addr.parse().expect("unable to parse server address");
+	let handle = server.start(addr, router, None).expect("start failed");
+
+	// a running server can't be started twice
+	assert!(server.start(addr, build_router(), None).is_err());
+
+	let url = format!("http://{}/v1/", server_addr);
+	let index = request_with_retry(url.as_str()).unwrap();
+	assert_eq!(index.len(), 2);
+	assert_eq!(counter.value(), 1);
+
+	// the server thread exits once stopped, releasing the address
+	assert!(server.stop());
+	handle.join().expect("API server thread panicked");
+	assert!(!server.stop());
+	assert!(TcpListener::bind(addr).is_ok());
+	assert!(api::client::get::<Vec<String>>(url.as_str(), None).is_err());
+}
+
+#[test]
+fn test_api_secret() {
+	let mut server = ApiServer::new();
+	let mut router = Router::new();
+	router
+		.add_route("/v1/public", Arc::new(IndexHandler { list: vec![] }))
+		.expect("add_route failed");
+	router
+		.add_route("/v1/owner", Arc::new(IndexHandler { list: vec![] }))
+		.expect("add_route failed");
+	let auth = BasicAuthMiddleware::new("secret", vec!["/v1/owner".to_string()]);
+	assert_eq!(auth.access("/v1/public"), Access::Public);
+	assert_eq!(auth.access("/v1/owner"), Access::Owner);
+	router.add_middleware(Arc::new(auth));
+	let server_addr = "127.0.0.1:14435";
+	let addr: SocketAddr = server_addr.parse().expect("unable to parse server address");
+	let handle = server.start(addr, router, None).expect("start failed");
+
+	let public_url = format!("http://{}/v1/public", server_addr);
+	let owner_url = format!("http://{}/v1/owner", server_addr);
+	assert!(request_with_retry(public_url.as_str()).is_ok());
+	assert!(api::client::get::<Vec<String>>(owner_url.as_str(), None).is_err());
+	assert!(api::client::get::<Vec<String>>(owner_url.as_str(), Some("wrong".to_owned())).is_err());
+	assert!(api::client::get::<Vec<String>>(owner_url.as_str(), Some("secret".to_owned())).is_ok());
+
+	assert!(server.stop());
+	handle.join().expect("API server thread panicked");
+}
+
+#[test]
+fn test_rate_limit() {
+	let mut server = ApiServer::new();
+	let mut router = Router::new();
+	router
+		.add_route("/v1/limited", Arc::new(IndexHandler { list: vec![] }))
+		.expect("add_route failed");
+	router
+		.add_route("/v1/other", Arc::new(IndexHandler { list: vec![] }))
+		.expect("add_route failed");
+	let mut config = RateLimitConfig::default();
+	config.routes.insert("/v1/limited".to_string(), 1);
+	let rate_limit = Arc::new(RateLimitMiddleware::new(config, Some("secret")));
+	router.add_middleware(rate_limit.clone());
+	let server_addr = "127.0.0.1:14437";
+	let addr: SocketAddr = server_addr.parse().expect("unable to parse server address");
+	let handle = server.start(addr, router, None).expect("start failed");
+
+	// the limited route allows a single call per minute to each client
+	let limited_url = format!("http://{}/v1/limited", server_addr);
+	let other_url = format!("http://{}/v1/other", server_addr);
+	assert!(request_with_retry(limited_url.as_str()).is_ok());
+	assert!(api::client::get::<Vec<String>>(limited_url.as_str(), None).is_err());
+	assert!(api::client::get::<Vec<String>>(other_url.as_str(), None).is_ok());
+	// the API secret is another client
+	assert!(
+		api::client::get::<Vec<String>>(limited_url.as_str(), Some("secret".to_owned())).is_ok()
+	);
+
+	let stats = rate_limit.stats();
+	assert_eq!(stats.routes["/v1/limited"].allowed, 2);
+	assert_eq!(stats.routes["/v1/limited"].limited, 1);
+	assert_eq!(stats.routes[OTHER_ROUTES].allowed, 1);
+	assert_eq!(stats.limited_clients["ip:127.0.0.1"], 1);
+	assert_eq!(stats.clients, 3);
+
+	assert!(server.stop());
+	handle.join().expect("API server thread panicked");
+}
+
+#[test]
+fn test_http_metrics() {
+	let mut server = ApiServer::new();
+	let mut router = Router::new();
+	router
+		.add_route("/v1/counted", Arc::new(IndexHandler { list: vec![] }))
+		.expect("add_route failed");
+	router
+		.add_route("/v1/items/*", Arc::new(IndexHandler { list: vec![] }))
+		.expect("add_route failed");
+	router.add_middleware(Arc::new(LoggingMiddleware {}));
+	let server_addr = "127.0.0.1:14438";
+	let addr: SocketAddr = server_addr.parse().expect("unable to parse server address");
+	let handle = server.start(addr, router, None).expect("start failed");
+
+	let url = format!("http://{}/v1/counted", server_addr);
+	assert!(request_with_retry(url.as_str()).is_ok());
+	for item in &["a", "b"] {
+		let url = format!("http://{}/v1/items/{}", server_addr, item);
+		assert!(api::client::get::<Vec<String>>(url.as_str(), None).is_ok());
+	}
+
+	let (content_type, text) = api::metrics::encode().expect("encode failed");
+	assert!(content_type.starts_with("text/plain"));
+	let text = String::from_utf8(text).unwrap();
+	assert!(
+		text.contains("gotts_oracle_http_requests_total{route=\"/v1/counted\",status=\"200\"} 1")
+	);
+	// the calls of a wildcard route are counted by its pattern
+	assert!(
+		text.contains("gotts_oracle_http_requests_total{route=\"/v1/items/*\",status=\"200\"} 2")
+	);
+	assert!(!text.contains("/v1/items/a"));
+
+	assert!(server.stop());
+	handle.join().expect("API server thread panicked");
+}
+
+#[test]
+fn test_invalid_tls_config() {
+	let conf = TLSConfig::new("missing_cert.pem".to_owned(), "missing_key.pem".to_owned());
+	let err = conf.validate().unwrap_err();
+	assert!(format!("{}", err).contains("missing_cert.pem"));
+
+	// the server doesn't start with an invalid TLS configuration
+	let mut server = ApiServer::new();
+	let addr: SocketAddr = "127.0.0.1:14436".parse().unwrap();
+	assert!(server.start(addr, build_router(), Some(conf)).is_err());
+	assert!(server.tls_config().is_none());
+}
+
+/// The certificates generated by tests/tls/gen.sh
+fn tls_file(name: &str) -> PathBuf {
+	Path::new(env!("CARGO_MANIFEST_DIR"))
+		.join("tests")
+		.join("tls")
+		.join(name)
+}
+
+fn tls_path(name: &str) -> String {
+	tls_file(name).to_str().unwrap().to_owned()
+}
+
+/// GET the index over HTTPS on localhost, trusting the `ca` certificate and
+/// authenticating with the `client` certificate if any
+fn tls_get(port: u16, ca: &str, client: Option<&str>) -> Result<String, io::Error> {
+	let mut config = rustls::ClientConfig::new();
+	let mut reader = BufReader::new(File::open(tls_file(ca))?);
+	config.root_store.add_pem_file(&mut reader).unwrap();
+	if let Some(client) = client {
+		let mut reader = BufReader::new(File::open(tls_file(&format!("{}.pem", client)))?);
+		let certs = pemfile::certs(&mut reader).unwrap();
+		let mut reader = BufReader::new(File::open(tls_file(&format!("{}.key", client)))?);
+		let key = pemfile::pkcs8_private_keys(&mut reader).unwrap().remove(0);
+		config.set_single_client_cert(certs, key);
+	}
+	let dns_name = webpki::DNSNameRef::try_from_ascii_str("localhost").unwrap();
+	let mut session = rustls::ClientSession::new(&Arc::new(config), dns_name);
+
+	let mut sock = TcpStream::connect(("127.0.0.1", port))?;
+	sock.set_read_timeout(Some(time::Duration::from_secs(5)))?;
+	let mut stream = rustls::Stream::new(&mut session, &mut sock);
+	stream.write_all(b"GET /v1/ HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")?;
+	stream.flush()?;
+
+	// the server may close the connection without a close_notify, what's
+	// read until then is the response
+	let mut buf = vec![];
+	if let Err(e) = stream.read_to_end(&mut buf) {
+		if buf.is_empty() {
+			return Err(e);
+		}
+	}
+	let res = String::from_utf8_lossy(&buf).into_owned();
+	if !res.starts_with("HTTP/1.1 200") {
+		return Err(io::Error::new(io::ErrorKind::Other, res));
+	}
+	Ok(res)
+}
+
+#[test]
+fn test_tls_reload() {
+	// the server reads its certificate from files it's safe to overwrite
+	let dir = std::env::temp_dir().join("gotts_oracle_test_tls_reload");
+	let _ = fs::remove_dir_all(&dir);
+	fs::create_dir_all(&dir).unwrap();
+	let certificate = dir.join("cert.pem");
+	let private_key = dir.join("key.pem");
+	fs::copy(tls_file("server.pem"), &certificate).unwrap();
+	fs::copy(tls_file("server.key"), &private_key).unwrap();
+	let conf = TLSConfig::new(
+		certificate.to_str().unwrap().to_owned(),
+		private_key.to_str().unwrap().to_owned(),
+	);
+	assert!(conf.validate().is_ok());
+
+	let mut server = ApiServer::new();
+	let addr: SocketAddr = "127.0.0.1:14439".parse().unwrap();
+	let handle = server
+		.start(addr, build_router(), Some(conf))
+		.expect("start failed");
+	let tls_config = server.tls_config().expect("no TLS configuration");
+
+	let res = tls_get(14439, "ca.pem", None).expect("HTTPS request failed");
+	assert!(res.contains("get blocks"));
+	// the server certificate isn't signed by the other CA
+	assert!(tls_get(14439, "ca2.pem", None).is_err());
+
+	// the renewed certificate is served once reloaded
+	fs::copy(tls_file("server2.pem"), &certificate).unwrap();
+	fs::copy(tls_file("server2.key"), &private_key).unwrap();
+	assert!(tls_get(14439, "ca.pem", None).is_ok());
+	tls_config.reload().expect("reload failed");
+	assert!(tls_get(14439, "ca2.pem", None).is_ok());
+	assert!(tls_get(14439, "ca.pem", None).is_err());
+
+	// an invalid certificate isn't loaded, the current one is kept
+	fs::write(&certificate, "not a certificate").unwrap();
+	assert!(tls_config.reload().is_err());
+	assert!(tls_get(14439, "ca2.pem", None).is_ok());
+
+	assert!(server.stop());
+	handle.join().expect("API server thread panicked");
+	fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_tls_client_auth() {
+	let conf = TLSConfig::new(tls_path("server.pem"), tls_path("server.key"))
+		.with_client_ca(tls_path("ca.pem"));
+	let mut server = ApiServer::new();
+	let addr: SocketAddr = "127.0.0.1:14440".parse().unwrap();
+	let handle = server
+		.start(addr, build_router(), Some(conf))
+		.expect("start failed");
+
+	// the clients must authenticate with a certificate signed by the client CA
+	assert!(tls_get(14440, "ca.pem", None).is_err());
+	assert!(tls_get(14440, "ca.pem", Some("client2")).is_err());
+	let res = tls_get(14440, "ca.pem", Some("client")).expect("HTTPS request failed");
+	assert!(res.contains("get blocks"));
+
+	assert!(server.stop());
+	handle.join().expect("API server thread panicked");
+}
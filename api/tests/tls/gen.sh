@@ -0,0 +1,36 @@
+#!/bin/sh
+# Generates the certificates of the TLS tests: two CAs, a server certificate
+# for localhost signed by each of them and a client certificate signed by each
+# of them. The CA keys aren't kept.
+set -e
+cd "$(dirname "$0")"
+TMP=$(mktemp -d)
+trap 'rm -rf "$TMP"' EXIT
+DAYS=7300
+
+for ca in ca ca2; do
+	openssl req -x509 -newkey rsa:2048 -nodes -days $DAYS -sha256 \
+		-subj "/CN=gotts oracle test $ca" \
+		-addext "basicConstraints=critical,CA:TRUE" \
+		-addext "keyUsage=critical,keyCertSign,cRLSign" \
+		-keyout "$TMP/$ca.key" -out "$ca.pem"
+done
+
+cert() {
+	name=$1
+	ca=$2
+	ext=$3
+	openssl req -newkey rsa:2048 -nodes -sha256 -subj "/CN=$name" \
+		-keyout "$name.key" -out "$TMP/$name.csr"
+	printf "basicConstraints=critical,CA:FALSE\n%s\n" "$ext" >"$TMP/$name.ext"
+	openssl x509 -req -in "$TMP/$name.csr" -CA "$ca.pem" -CAkey "$TMP/$ca.key" \
+		-CAcreateserial -CAserial "$TMP/$ca.srl" -days $DAYS -sha256 \
+		-extfile "$TMP/$name.ext" -out "$name.pem"
+}
+
+cert server ca "subjectAltName=DNS:localhost
+extendedKeyUsage=serverAuth"
+cert server2 ca2 "subjectAltName=DNS:localhost
+extendedKeyUsage=serverAuth"
+cert client ca "extendedKeyUsage=clientAuth"
+cert client2 ca2 "extendedKeyUsage=clientAuth"
@@ -49,12 +49,19 @@ fn comments() -> HashMap<String, String> {
 #tls_certificate_file = \"\"
 #private key for the TLS certificate
 #tls_certificate_key = \"\"
+#CA certificates of the Gotts nodes allowed to call the API, which then must
+# present a client certificate signed by one of them
+#tls_client_ca_file = \"\"
+#the TLS certificate, key and CA certificates are reloaded on SIGHUP
 
 #Alpha Vantage API Key
 #Note: the api key integrated in the source is just for demo, with very limited access,
 # please claim your own api key and set it here.
 # the free api key can be requested here: https://www.alphavantage.co/support/#api-key
 #alpha_vantage_api_key = \"\"
+#Alpha Vantage API endpoint, to query a staging proxy or a mock instead,
+# i.e. the one of: cargo run --features mock --example mock_server
+#alpha_vantage_endpoint = \"http://127.0.0.1:8080/query\"
 
 #the address on which services will listen
 "
@@ -65,7 +72,16 @@ fn comments() -> HashMap<String, String> {
 		"api_secret_path".to_string(),
 		"
 #path of the secret token used by the API to authenticate the calls
-#comment the it to disable basic auth
+#comment the it to disable basic auth, and the owner routes and /metrics with it
+"
+		.to_string(),
+	);
+
+	retval.insert(
+		"oracle_key_path".to_string(),
+		"
+#path of the seed of the ed25519 key signing the aggregated prices
+#keep it private, and publish the public key printed at server start
 "
 		.to_string(),
 	);
@@ -79,6 +95,251 @@ fn comments() -> HashMap<String, String> {
 		.to_string(),
 	);
 
+	retval.insert(
+		"vendor_timeout_secs".to_string(),
+		"
+#timeout of a request to a data vendor, in seconds
+"
+		.to_string(),
+	);
+
+	retval.insert(
+		"max_staleness_secs".to_string(),
+		"
+#maximum age, in seconds, of the observation returned by a point-in-time
+#price lookup, i.e. /v1/rest/price_at, before it's reported as stale
+"
+		.to_string(),
+	);
+
+	retval.insert(
+		"exchange_max_age_secs".to_string(),
+		"
+#maximum age, in seconds, of the stored rate served by /v1/rest/exchange,
+#beyond which it's fetched live from the price sources. 0 always fetches live.
+"
+		.to_string(),
+	);
+
+	retval.insert(
+		"[server.pairs]".to_string(),
+		"
+#########################################
+### CURRENCY PAIRS CONFIGURATION      ###
+#########################################
+
+#Currency pairs polled from the data vendors and served by the aggregated API.
+#All pairs are quoted against the base currency.
+"
+		.to_string(),
+	);
+
+	retval.insert(
+		"base_currency".to_string(),
+		"
+#the base currency all the pairs are quoted against
+"
+		.to_string(),
+	);
+
+	retval.insert(
+		"to_base_currencies".to_string(),
+		"
+#currencies quoted in the base currency, i.e. \"EUR\" for the pair EUR2USD
+"
+		.to_string(),
+	);
+
+	retval.insert(
+		"from_base_currencies".to_string(),
+		"
+#currencies the base currency is quoted in, i.e. \"CNY\" for the pair USD2CNY
+"
+		.to_string(),
+	);
+
+	retval.insert(
+		"[server.candles]".to_string(),
+		"
+#########################################
+### CANDLES CONFIGURATION             ###
+#########################################
+
+#Before the history older than the compaction cutoff is deleted, it is rolled
+#up into 1 minute, 1 hour and 1 day OHLC candles, served by /v1/rest/candles.
+#Retention periods are in days, 0 keeps the candles forever.
+"
+		.to_string(),
+	);
+
+	retval.insert(
+		"minute_retention_days".to_string(),
+		"
+#retention of the 1 minute candles
+"
+		.to_string(),
+	);
+
+	retval.insert(
+		"hour_retention_days".to_string(),
+		"
+#retention of the 1 hour candles
+"
+		.to_string(),
+	);
+
+	retval.insert(
+		"day_retention_days".to_string(),
+		"
+#retention of the 1 day candles
+"
+		.to_string(),
+	);
+
+	retval.insert(
+		"[server.validation]".to_string(),
+		"
+#########################################
+### QUOTES VALIDATION CONFIGURATION   ###
+#########################################
+
+#Sanity filters on the quotes returned by the price sources. Rejected quotes
+#are logged and counted, see /v1/rest/validation, and never stored.
+"
+		.to_string(),
+	);
+
+	retval.insert(
+		"max_quote_age_secs".to_string(),
+		"
+#maximum age of a quote, in seconds. The forex markets are closed on week-ends,
+#so that the default is 3 days
+"
+		.to_string(),
+	);
+
+	retval.insert(
+		"max_jump_percent".to_string(),
+		"
+#maximum deviation of a quote from the recent median of its pair, in percent,
+#0 disables the check. Only the stored quotes younger than max_quote_age_secs
+#count toward the median.
+"
+		.to_string(),
+	);
+
+	retval.insert(
+		"median_window".to_string(),
+		"
+#number of recent quotes of the pair the median is computed over
+"
+		.to_string(),
+	);
+
+	retval.insert(
+		"rebaseline_after".to_string(),
+		"
+#number of consecutive quotes rejected for a jump, within max_jump_percent of
+#each other, after which the last one is accepted as the new baseline of the
+#pair, so that a real move doesn't leave the pair stale. 0 disables it.
+"
+		.to_string(),
+	);
+
+	retval.insert(
+		"[server.scheduler]".to_string(),
+		"
+#########################################
+### POLLING SCHEDULER CONFIGURATION   ###
+#########################################
+
+#Polling schedule of the currency pairs. When the vendor keys can't take all
+#the pairs, the stalest ones are polled first.
+"
+		.to_string(),
+	);
+
+	retval.insert(
+		"poll_interval_secs".to_string(),
+		"
+#interval between two polls of a pair, in seconds
+"
+		.to_string(),
+	);
+
+	retval.insert(
+		"alpha_vantage_calls_per_minute".to_string(),
+		"
+#calls per minute allowed by the Alpha Vantage key, 5 for a free key
+"
+		.to_string(),
+	);
+
+	retval.insert(
+		"alpha_vantage_burst".to_string(),
+		"
+#calls the Alpha Vantage key can make in a burst
+"
+		.to_string(),
+	);
+
+	retval.insert(
+		"backoff_initial_secs".to_string(),
+		"
+#delay before a failing pair is retried, in seconds, doubled on every
+#consecutive failure and randomized by up to a half
+"
+		.to_string(),
+	);
+
+	retval.insert(
+		"backoff_max_secs".to_string(),
+		"
+#maximum delay before a failing pair is retried, in seconds
+"
+		.to_string(),
+	);
+
+	retval.insert(
+		"[server.rate_limit]".to_string(),
+		"
+#########################################
+### API RATE LIMITS CONFIGURATION     ###
+#########################################
+
+#Rate limits of the API calls of each client, identified by the API secret or
+#else by its IP address. The calls beyond the limits are answered with a 429
+#status and a Retry-After header, and counted, see /v1/owner/rate_limits.
+"
+		.to_string(),
+	);
+
+	retval.insert(
+		"calls_per_minute".to_string(),
+		"
+#calls per minute allowed to a client over all the routes not listed below,
+#0 disables the limit
+"
+		.to_string(),
+	);
+
+	retval.insert(
+		"burst".to_string(),
+		"
+#calls a client can make in a burst, capped to the limit of the route
+"
+		.to_string(),
+	);
+
+	retval.insert(
+		"[server.rate_limit.routes]".to_string(),
+		"
+#calls per minute allowed to a client on specific routes, i.e. the ones which
+#may call the price sources, 0 disables the limit
+"
+		.to_string(),
+	);
+
 	retval.insert(
 		"[logging]".to_string(),
 		"
@@ -158,7 +419,7 @@ fn comments() -> HashMap<String, String> {
 }
 
 fn get_key(line: &str) -> String {
-	if line.contains("[") && line.contains("]") {
+	if line.starts_with("[") && line.contains("]") {
 		return line.to_owned();
 	} else if line.contains("=") {
 		return line.split("=").collect::<Vec<&str>>()[0].trim().to_owned();
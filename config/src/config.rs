@@ -17,7 +17,7 @@
 
 use dirs;
 use rand::distributions::{Alphanumeric, Distribution};
-use rand::thread_rng;
+use rand::{thread_rng, Rng};
 use std::env;
 use std::fs::{self, File};
 use std::io::prelude::*;
@@ -28,7 +28,7 @@ use toml;
 
 use crate::comments::insert_comments;
 use crate::types::{ConfigError, ConfigMembers, GlobalConfig, ServerConfig};
-use crate::util::LoggingConfig;
+use crate::util::{from_hex, to_hex, LoggingConfig};
 
 /// The default file name to use when trying to derive
 /// the oracle config file location
@@ -38,6 +38,10 @@ const GOTTS_HOME: &'static str = ".gotts";
 const GOTTS_CHAIN_DIR: &'static str = "oracle_data";
 /// Node API secret
 pub const API_SECRET_FILE_NAME: &'static str = ".oracle_api_secret";
+/// Oracle signing key seed
+pub const ORACLE_KEY_FILE_NAME: &'static str = ".oracle_key";
+/// Length in bytes of the oracle signing key seed
+pub const ORACLE_KEY_SEED_LEN: usize = 32;
 
 fn get_gotts_path() -> Result<PathBuf, ConfigError> {
 	// Check if gotts dir exists
@@ -104,12 +108,66 @@ fn check_api_secret_file() -> Result<(), ConfigError> {
 	}
 }
 
+/// Create file with a random oracle signing key seed, readable by the owner only
+fn init_oracle_key(oracle_key_path: &PathBuf) -> Result<(), ConfigError> {
+	if let Some(parent) = oracle_key_path.parent() {
+		fs::create_dir_all(parent)?;
+	}
+	let mut oracle_key_file = File::create(oracle_key_path)?;
+	#[cfg(unix)]
+	{
+		use std::os::unix::fs::PermissionsExt;
+		oracle_key_file.set_permissions(fs::Permissions::from_mode(0o600))?;
+	}
+	let mut seed = [0u8; ORACLE_KEY_SEED_LEN];
+	thread_rng().fill(&mut seed);
+	oracle_key_file.write_all(to_hex(seed.to_vec()).as_bytes())?;
+	Ok(())
+}
+
+/// Check if file contains a valid key seed and nothing else
+fn check_oracle_key(oracle_key_path: &PathBuf) -> Result<(), ConfigError> {
+	let contents = fs::read_to_string(oracle_key_path)?;
+	match from_hex(contents) {
+		Ok(ref seed) if seed.len() == ORACLE_KEY_SEED_LEN => Ok(()),
+		_ => Err(ConfigError::ParseError(
+			oracle_key_path.to_str().unwrap().to_owned(),
+			"invalid oracle key, remove it to generate a new one".to_owned(),
+		)),
+	}
+}
+
+/// Check that the oracle key file, at the configured path if any, exists and
+/// is valid. An invalid key is never replaced, since the public key may have
+/// been published already.
+fn check_oracle_key_file(configured: Option<&str>) -> Result<(), ConfigError> {
+	let oracle_key_path = get_oracle_key_path(configured)?;
+	if !oracle_key_path.exists() {
+		init_oracle_key(&oracle_key_path)
+	} else {
+		check_oracle_key(&oracle_key_path)
+	}
+}
+
+/// Path of the oracle signing key, the configured one if any, or the default
+/// one under the gotts home directory.
+pub fn get_oracle_key_path(configured: Option<&str>) -> Result<PathBuf, ConfigError> {
+	match configured {
+		Some(p) => Ok(PathBuf::from(p)),
+		None => {
+			let mut oracle_key_path = get_gotts_path()?;
+			oracle_key_path.push(ORACLE_KEY_FILE_NAME);
+			Ok(oracle_key_path)
+		}
+	}
+}
+
 /// Handles setup and detection of paths for node
 pub fn initial_setup_server() -> Result<GlobalConfig, ConfigError> {
 	check_api_secret_file()?;
 	// Use config file if current directory if it exists, .gotts home otherwise
-	if let Some(p) = check_config_current_dir(SERVER_CONFIG_FILE_NAME) {
-		GlobalConfig::new(p.to_str().unwrap())
+	let global_config = if let Some(p) = check_config_current_dir(SERVER_CONFIG_FILE_NAME) {
+		GlobalConfig::new(p.to_str().unwrap())?
 	} else {
 		// Check if gotts dir exists
 		let gotts_path = get_gotts_path()?;
@@ -126,8 +184,16 @@ pub fn initial_setup_server() -> Result<GlobalConfig, ConfigError> {
 			default_config.write_to_file(config_path.to_str().unwrap())?;
 		}
 
-		GlobalConfig::new(config_path.to_str().unwrap())
-	}
+		GlobalConfig::new(config_path.to_str().unwrap())?
+	};
+
+	// the oracle key is checked where it's configured, once the config loaded
+	let oracle_key_path = global_config
+		.members
+		.as_ref()
+		.and_then(|m| m.server.oracle_key_path.clone());
+	check_oracle_key_file(oracle_key_path.as_ref().map(|p| p.as_str()))?;
+	Ok(global_config)
 }
 
 /// Returns the defaults, as strewn throughout the code
@@ -205,6 +271,10 @@ impl GlobalConfig {
 		secret_path.push(API_SECRET_FILE_NAME);
 		self.members.as_mut().unwrap().server.api_secret_path =
 			Some(secret_path.to_str().unwrap().to_owned());
+		let mut oracle_key_path = gotts_home.clone();
+		oracle_key_path.push(ORACLE_KEY_FILE_NAME);
+		self.members.as_mut().unwrap().server.oracle_key_path =
+			Some(oracle_key_path.to_str().unwrap().to_owned());
 		let mut log_path = gotts_home.clone();
 		log_path.push(SERVER_LOG_FILE_NAME);
 		self.members
@@ -31,4 +31,7 @@ pub mod config;
 pub mod types;
 
 pub use crate::config::initial_setup_server;
-pub use crate::types::{ConfigError, ConfigMembers, GlobalConfig, ServerConfig};
+pub use crate::types::{
+	CandlesConfig, ConfigError, ConfigMembers, GlobalConfig, PairsConfig, RateLimitConfig,
+	SchedulerConfig, ServerConfig, ValidationConfig,
+};
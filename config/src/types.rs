@@ -15,6 +15,7 @@
 
 //! Public types for config modules
 
+use std::collections::BTreeMap;
 use std::fmt;
 use std::io;
 use std::path::PathBuf;
@@ -106,14 +107,57 @@ pub struct ServerConfig {
 	pub api_http_addr: String,
 	/// Location of secret for basic auth on Rest API HTTP server.
 	pub api_secret_path: Option<String>,
+	/// Location of the seed of the key signing the aggregated prices.
+	pub oracle_key_path: Option<String>,
 
 	/// TLS certificate file
 	pub tls_certificate_file: Option<String>,
 	/// TLS certificate private key file
 	pub tls_certificate_key: Option<String>,
+	/// CA certificates file of the clients allowed to call the API, enabling
+	/// the TLS client authentication
+	pub tls_client_ca_file: Option<String>,
 
 	/// Alpha Vantage API Key
 	pub alpha_vantage_api_key: Option<String>,
+
+	/// Alpha Vantage API endpoint, i.e. a staging proxy or a mock, instead of
+	/// the Alpha Vantage one
+	pub alpha_vantage_endpoint: Option<String>,
+
+	/// Timeout of a request to a data vendor, in seconds
+	#[serde(default = "default_vendor_timeout_secs")]
+	pub vendor_timeout_secs: u64,
+
+	/// Maximum age, in seconds, of the observation returned by a point-in-time
+	/// price lookup
+	#[serde(default = "default_max_staleness_secs")]
+	pub max_staleness_secs: u64,
+
+	/// Maximum age, in seconds, of the stored rate served by the exchange API
+	/// before it's fetched live from the price sources
+	#[serde(default = "default_exchange_max_age_secs")]
+	pub exchange_max_age_secs: u64,
+
+	/// Currency pairs polled and aggregated by the oracle
+	#[serde(default)]
+	pub pairs: PairsConfig,
+
+	/// Retention of the OHLC candles rolled up from the compacted history
+	#[serde(default)]
+	pub candles: CandlesConfig,
+
+	/// Sanity filters on the incoming quotes
+	#[serde(default)]
+	pub validation: ValidationConfig,
+
+	/// Polling schedule, within the rate limits of the vendor keys
+	#[serde(default)]
+	pub scheduler: SchedulerConfig,
+
+	/// Rate limits of the API calls of each client
+	#[serde(default)]
+	pub rate_limit: RateLimitConfig,
 }
 
 impl Default for ServerConfig {
@@ -122,9 +166,211 @@ impl Default for ServerConfig {
 			db_root: "gotts_oracle".to_string(),
 			api_http_addr: "127.0.0.1:3518".to_string(),
 			api_secret_path: None,
+			oracle_key_path: None,
 			tls_certificate_file: None,
 			tls_certificate_key: None,
+			tls_client_ca_file: None,
 			alpha_vantage_api_key: None,
+			alpha_vantage_endpoint: None,
+			vendor_timeout_secs: default_vendor_timeout_secs(),
+			max_staleness_secs: default_max_staleness_secs(),
+			exchange_max_age_secs: default_exchange_max_age_secs(),
+			pairs: PairsConfig::default(),
+			candles: CandlesConfig::default(),
+			validation: ValidationConfig::default(),
+			scheduler: SchedulerConfig::default(),
+			rate_limit: RateLimitConfig::default(),
+		}
+	}
+}
+
+fn default_rebaseline_after() -> usize {
+	3
+}
+
+fn default_vendor_timeout_secs() -> u64 {
+	10
+}
+
+fn default_max_staleness_secs() -> u64 {
+	600
+}
+
+fn default_exchange_max_age_secs() -> u64 {
+	60
+}
+
+/// Currency pairs configuration. All pairs are quoted against a single base
+/// currency, either as `XXX2BASE` or as `BASE2XXX`.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct PairsConfig {
+	/// The base currency, i.e. "USD"
+	pub base_currency: String,
+	/// Currencies quoted in the base currency, i.e. "EUR" for EUR2USD
+	pub to_base_currencies: Vec<String>,
+	/// Currencies the base currency is quoted in, i.e. "CNY" for USD2CNY
+	pub from_base_currencies: Vec<String>,
+}
+
+impl Default for PairsConfig {
+	fn default() -> PairsConfig {
+		PairsConfig {
+			base_currency: "USD".to_string(),
+			to_base_currencies: vec![
+				"EUR".to_string(),
+				"GBP".to_string(),
+				"BTC".to_string(),
+				"ETH".to_string(),
+			],
+			from_base_currencies: vec!["CNY".to_string(), "JPY".to_string(), "CAD".to_string()],
+		}
+	}
+}
+
+impl PairsConfig {
+	/// All the configured pairs as (from, to) currency codes, the `XXX2BASE`
+	/// pairs first.
+	pub fn pairs(&self) -> Vec<(String, String)> {
+		let mut pairs =
+			Vec::with_capacity(self.to_base_currencies.len() + self.from_base_currencies.len());
+		for from in &self.to_base_currencies {
+			pairs.push((from.clone(), self.base_currency.clone()));
+		}
+		for to in &self.from_base_currencies {
+			pairs.push((self.base_currency.clone(), to.clone()));
+		}
+		pairs
+	}
+
+	/// Whether the pair from `from` to `to` is configured, ignoring case.
+	pub fn contains(&self, from: &str, to: &str) -> bool {
+		self.find(from, to).is_some()
+	}
+
+	/// The configured pair from `from` to `to`, ignoring case, with the
+	/// currency codes as configured.
+	pub fn find(&self, from: &str, to: &str) -> Option<(String, String)> {
+		self.pairs()
+			.into_iter()
+			.find(|(f, t)| f.eq_ignore_ascii_case(from) && t.eq_ignore_ascii_case(to))
+	}
+
+	/// The configured pair of id `id`, i.e. "USD2CNY", ignoring case, with
+	/// the currency codes as configured.
+	pub fn find_id(&self, id: &str) -> Option<(String, String)> {
+		self.pairs()
+			.into_iter()
+			.find(|(f, t)| format!("{}2{}", f, t).eq_ignore_ascii_case(id))
+	}
+}
+
+/// Candles configuration. Before the raw history is compacted, it is rolled
+/// up into 1 minute, 1 hour and 1 day OHLC candles, each kept for its own
+/// retention period. A retention of 0 keeps the candles forever.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct CandlesConfig {
+	/// Retention of the 1 minute candles, in days
+	pub minute_retention_days: u32,
+	/// Retention of the 1 hour candles, in days
+	pub hour_retention_days: u32,
+	/// Retention of the 1 day candles, in days
+	pub day_retention_days: u32,
+}
+
+impl Default for CandlesConfig {
+	fn default() -> CandlesConfig {
+		CandlesConfig {
+			minute_retention_days: 7,
+			hour_retention_days: 365,
+			day_retention_days: 0,
+		}
+	}
+}
+
+/// Validation configuration. Incoming quotes are rejected, instead of being
+/// stored, when their rate isn't a positive number, when they are dated too
+/// far in the past, or when they jump too far from the recent median of the
+/// pair.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct ValidationConfig {
+	/// Maximum age of a quote, in seconds
+	pub max_quote_age_secs: u64,
+	/// Maximum deviation from the recent median of the pair, in percent.
+	/// 0 disables the check.
+	pub max_jump_percent: f64,
+	/// Number of recent quotes the median is computed over
+	pub median_window: usize,
+	/// Number of consecutive quotes rejected for a jump, consistent with each
+	/// other, after which the pair is re-baselined on them. 0 disables it.
+	#[serde(default = "default_rebaseline_after")]
+	pub rebaseline_after: usize,
+}
+
+impl Default for ValidationConfig {
+	fn default() -> ValidationConfig {
+		ValidationConfig {
+			max_quote_age_secs: 3 * 24 * 3600,
+			max_jump_percent: 10.0,
+			median_window: 16,
+			rebaseline_after: 3,
+		}
+	}
+}
+
+/// Polling schedule of the currency pairs. The calls made with a vendor key
+/// are limited by a token bucket, and the failing pairs are retried after an
+/// exponential backoff.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct SchedulerConfig {
+	/// Interval between two polls of a pair, in seconds
+	pub poll_interval_secs: u64,
+	/// Calls per minute allowed by the Alpha Vantage key
+	pub alpha_vantage_calls_per_minute: u32,
+	/// Calls the Alpha Vantage key can make in a burst
+	pub alpha_vantage_burst: u32,
+	/// Delay before a failing pair is retried, in seconds, doubled on every
+	/// consecutive failure
+	pub backoff_initial_secs: u64,
+	/// Maximum delay before a failing pair is retried, in seconds
+	pub backoff_max_secs: u64,
+}
+
+impl Default for SchedulerConfig {
+	fn default() -> SchedulerConfig {
+		SchedulerConfig {
+			poll_interval_secs: 60,
+			alpha_vantage_calls_per_minute: 5,
+			alpha_vantage_burst: 5,
+			backoff_initial_secs: 15,
+			backoff_max_secs: 900,
+		}
+	}
+}
+
+/// Rate limits of the API calls of each client, identified by the API secret
+/// or else by its IP address. A client gets a limit on every configured route,
+/// and a limit shared by all the other routes.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct RateLimitConfig {
+	/// Calls per minute allowed to a client over the routes not configured,
+	/// 0 disabling the limit
+	pub calls_per_minute: u32,
+	/// Calls a client can make in a burst, capped to the limit of the route
+	pub burst: u32,
+	/// Calls per minute allowed to a client on specific routes, i.e. the ones
+	/// calling the price sources, 0 disabling the limit
+	pub routes: BTreeMap<String, u32>,
+}
+
+impl Default for RateLimitConfig {
+	fn default() -> RateLimitConfig {
+		let mut routes = BTreeMap::new();
+		routes.insert("/v1/rest/exchange".to_string(), 12);
+		routes.insert("/v1/json".to_string(), 30);
+		RateLimitConfig {
+			calls_per_minute: 120,
+			burst: 20,
+			routes,
 		}
 	}
 }
@@ -0,0 +1,187 @@
+// Copyright 2019 The Gotts Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Signed price attestations. The aggregated prices are signed by the oracle
+//! ed25519 key over their canonical binary encoding, so that any Gotts node
+//! knowing the oracle public key can check where a price came from.
+
+use ring::signature::{self, Ed25519KeyPair};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::Path;
+
+use crate::alphavantage::ExchangeRateResult;
+use crate::error::{Error, ErrorKind};
+use crate::oracle_ser::{self as ser, Writeable, Writer};
+use gotts_oracle_util::{from_hex, to_hex};
+
+/// Domain separation tag, prefixed to the encoding of every signed price set
+pub const PRICE_SET_TAG: &[u8] = b"gotts_oracle_price_set";
+
+/// A set of aggregated prices, as covered by a signature
+#[derive(Debug, Clone, PartialEq)]
+pub struct PriceSet<'a> {
+	/// The prices, in the order they are served
+	pub rates: &'a [ExchangeRateResult],
+}
+
+impl<'a> Writeable for PriceSet<'a> {
+	fn write<W: Writer>(&self, writer: &mut W) -> Result<(), ser::Error> {
+		writer.write_fixed_bytes(&PRICE_SET_TAG)?;
+		writer.write_u64(self.rates.len() as u64)?;
+		for rate in self.rates {
+			rate.write(writer)?;
+		}
+		Ok(())
+	}
+}
+
+/// Canonical binary encoding of a set of prices, which is the signed message
+pub fn price_set_message(rates: &[ExchangeRateResult]) -> Result<Vec<u8>, Error> {
+	ser::ser_vec(&PriceSet { rates })
+		.map_err(|e| ErrorKind::Format(format!("price set encoding failed: {}", e)).into())
+}
+
+/// Aggregated prices along with the oracle signature
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SignedPriceSet {
+	/// The aggregated prices
+	pub rates: Vec<ExchangeRateResult>,
+	/// Hex encoded ed25519 public key of the oracle
+	pub public_key: String,
+	/// Hex encoded ed25519 signature of the canonical encoding of `rates`
+	pub signature: String,
+}
+
+/// The oracle signing key
+pub struct OracleKeypair {
+	keypair: Ed25519KeyPair,
+}
+
+impl OracleKeypair {
+	/// Build the key pair from its 32 bytes seed
+	pub fn from_seed(seed: &[u8]) -> Result<OracleKeypair, Error> {
+		let keypair = Ed25519KeyPair::from_seed_unchecked(untrusted::Input::from(seed))
+			.map_err(|_| ErrorKind::Signature("invalid oracle key seed".to_owned()))?;
+		Ok(OracleKeypair { keypair })
+	}
+
+	/// Load the key pair from a file holding the hex encoded seed, as
+	/// generated at first start
+	pub fn from_seed_file<P: AsRef<Path>>(path: P) -> Result<OracleKeypair, Error> {
+		let contents = fs::read_to_string(path.as_ref()).map_err(|e| {
+			ErrorKind::Signature(format!(
+				"can't read oracle key {}: {}",
+				path.as_ref().display(),
+				e
+			))
+		})?;
+		let seed = from_hex(contents).map_err(ErrorKind::Signature)?;
+		OracleKeypair::from_seed(&seed)
+	}
+
+	/// Hex encoded public key
+	pub fn public_key(&self) -> String {
+		to_hex(self.keypair.public_key_bytes().to_vec())
+	}
+
+	/// Sign a set of aggregated prices
+	pub fn sign(&self, rates: Vec<ExchangeRateResult>) -> Result<SignedPriceSet, Error> {
+		let msg = price_set_message(&rates)?;
+		let signature = self.keypair.sign(&msg);
+		Ok(SignedPriceSet {
+			rates,
+			public_key: self.public_key(),
+			signature: to_hex(signature.as_ref().to_vec()),
+		})
+	}
+}
+
+/// Verify a signed price set against the trusted, hex encoded, oracle public
+/// key. The public key carried by the price set itself is not trusted.
+pub fn verify_price_set(signed: &SignedPriceSet, public_key: &str) -> Result<(), Error> {
+	let public_key = from_hex(public_key.to_owned()).map_err(ErrorKind::Signature)?;
+	let sig = from_hex(signed.signature.clone()).map_err(ErrorKind::Signature)?;
+	let msg = price_set_message(&signed.rates)?;
+	signature::verify(
+		&signature::ED25519,
+		untrusted::Input::from(&public_key),
+		untrusted::Input::from(&msg),
+		untrusted::Input::from(&sig),
+	)
+	.map_err(|_| ErrorKind::Signature("invalid price set signature".to_owned()).into())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use chrono::{TimeZone, Utc};
+
+	fn rates() -> Vec<ExchangeRateResult> {
+		vec![
+			ExchangeRateResult {
+				from: "EUR".to_owned(),
+				to: "USD".to_owned(),
+				rate: 1.1012,
+				date: Utc.timestamp(1_562_050_220, 0),
+			},
+			ExchangeRateResult {
+				from: "USD".to_owned(),
+				to: "CNY".to_owned(),
+				rate: 6.8619,
+				date: Utc.timestamp(1_562_050_280, 0),
+			},
+		]
+	}
+
+	#[test]
+	fn sign_and_verify() {
+		let keypair = OracleKeypair::from_seed(&[7u8; 32]).unwrap();
+		let signed = keypair.sign(rates()).unwrap();
+		assert_eq!(signed.public_key.len(), 64);
+		verify_price_set(&signed, &keypair.public_key()).unwrap();
+
+		// survives a round trip through the json api
+		let json = serde_json::to_string(&signed).unwrap();
+		let decoded: SignedPriceSet = serde_json::from_str(&json).unwrap();
+		verify_price_set(&decoded, &keypair.public_key()).unwrap();
+	}
+
+	#[test]
+	fn tampered_or_foreign_key() {
+		let keypair = OracleKeypair::from_seed(&[7u8; 32]).unwrap();
+		let other = OracleKeypair::from_seed(&[8u8; 32]).unwrap();
+
+		let mut signed = keypair.sign(rates()).unwrap();
+		assert!(verify_price_set(&signed, &other.public_key()).is_err());
+
+		signed.rates[1].rate = 6.8620;
+		assert!(verify_price_set(&signed, &keypair.public_key()).is_err());
+	}
+
+	#[test]
+	fn canonical_encoding() {
+		let msg = price_set_message(&rates()).unwrap();
+		assert_eq!(&msg[..PRICE_SET_TAG.len()], PRICE_SET_TAG);
+		assert_eq!(
+			&msg[PRICE_SET_TAG.len()..PRICE_SET_TAG.len() + 8],
+			&[0, 0, 0, 0, 0, 0, 0, 2]
+		);
+		// tag, count, then per rate: from, to (len prefixed), rate and timestamp
+		assert_eq!(
+			msg.len(),
+			PRICE_SET_TAG.len() + 8 + 2 * (8 + 3 + 8 + 3 + 8 + 8)
+		);
+	}
+}
@@ -0,0 +1,133 @@
+// Copyright 2019 The Gotts Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Backfill of the exchange rate history from the historical series of the
+//! vendors, so that a new oracle instance doesn't start empty. The history
+//! already held, as raw rates or rolled up into candles, is never written
+//! over: a backfill can be run again, or restarted after an interruption,
+//! without duplicating any observation.
+
+use chrono::{DateTime, Utc};
+use std::collections::BTreeSet;
+
+use crate::alphavantage::ExchangeRateResult;
+use crate::candles::Resolution;
+use crate::error::Error;
+use crate::types::OracleBackend;
+
+/// Outcome of the backfill of a pair
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct BackfillStats {
+	/// Number of observations stored
+	pub stored: usize,
+	/// Number of observations skipped, the oracle already holding their date
+	pub skipped: usize,
+}
+
+/// The dates of a pair the oracle already holds an observation for
+pub struct Coverage {
+	/// Dates of the raw rates, as timestamps
+	dates: BTreeSet<i64>,
+	/// Spans from the first to the last tick of the candles
+	spans: Vec<(DateTime<Utc>, DateTime<Utc>)>,
+}
+
+impl Coverage {
+	/// The coverage of the pair `id` from `from` to `to` included
+	pub fn of<T: ?Sized>(oracle: &T, id: &str, from: DateTime<Utc>, to: DateTime<Utc>) -> Coverage
+	where
+		T: OracleBackend,
+	{
+		let dates = oracle
+			.range(id, from, to)
+			.map(|r| r.date.timestamp())
+			.collect();
+		let mut spans = vec![];
+		for resolution in Resolution::ALL.iter().cloned() {
+			spans.extend(
+				oracle
+					.iter_candles(id, resolution)
+					.filter(|c| c.first_tick <= to && c.last_tick >= from)
+					.map(|c| (c.first_tick, c.last_tick)),
+			);
+		}
+		Coverage { dates, spans }
+	}
+
+	/// Whether the oracle holds an observation at `date`, or candles whose
+	/// ticks span over it.
+	pub fn covers(&self, date: DateTime<Utc>) -> bool {
+		self.dates.contains(&date.timestamp())
+			|| self
+				.spans
+				.iter()
+				.any(|(first, last)| *first <= date && date <= *last)
+	}
+}
+
+/// Store the historical observations of the pair `id` the oracle doesn't
+/// hold yet, in a single batch.
+pub fn backfill<T: ?Sized>(
+	oracle: &mut T,
+	id: &str,
+	observations: Vec<ExchangeRateResult>,
+) -> Result<BackfillStats, Error>
+where
+	T: OracleBackend,
+{
+	let mut stats = BackfillStats::default();
+	let (from, to) = match (
+		observations.iter().map(|r| r.date).min(),
+		observations.iter().map(|r| r.date).max(),
+	) {
+		(Some(from), Some(to)) => (from, to),
+		_ => return Ok(stats),
+	};
+
+	let coverage = Coverage::of(&*oracle, id, from, to);
+	let mut batch = oracle.batch()?;
+	for observation in observations {
+		if coverage.covers(observation.date) {
+			stats.skipped += 1;
+			continue;
+		}
+		batch.save(observation.date, observation)?;
+		stats.stored += 1;
+	}
+	batch.commit()?;
+
+	Ok(stats)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use chrono::TimeZone;
+
+	#[test]
+	fn coverage() {
+		let coverage = Coverage {
+			dates: vec![1_562_050_200].into_iter().collect(),
+			spans: vec![(
+				Utc.timestamp(1_562_100_000, 0),
+				Utc.timestamp(1_562_103_600, 0),
+			)],
+		};
+		assert!(coverage.covers(Utc.timestamp(1_562_050_200, 0)));
+		assert!(!coverage.covers(Utc.timestamp(1_562_050_201, 0)));
+		assert!(coverage.covers(Utc.timestamp(1_562_100_000, 0)));
+		assert!(coverage.covers(Utc.timestamp(1_562_103_600, 0)));
+		assert!(!coverage.covers(Utc.timestamp(1_562_103_601, 0)));
+	}
+}
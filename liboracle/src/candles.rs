@@ -0,0 +1,356 @@
+// Copyright 2019 The Gotts Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! OHLC candles, rolled up from the raw exchange rate history before it is
+//! compacted, so that the history is kept at a lower resolution instead of
+//! being lost.
+
+use chrono::{DateTime, Duration, NaiveDateTime, Utc};
+use prometheus::IntCounter;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::str::FromStr;
+
+use crate::alphavantage::ExchangeRateResult;
+use crate::error::{Error, ErrorKind};
+use crate::types::OracleBackend;
+use gotts_oracle_config::CandlesConfig;
+
+lazy_static! {
+	static ref COMPACTIONS: IntCounter = register_int_counter!(
+		"gotts_oracle_compactions_total",
+		"Compactions of the exchange rate history"
+	)
+	.unwrap();
+	static ref COMPACTED: IntCounter = register_int_counter!(
+		"gotts_oracle_compacted_rates_total",
+		"Exchange rates cleaned by the compactions, rolled up into candles"
+	)
+	.unwrap();
+}
+
+/// Time span covered by a candle
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum Resolution {
+	/// 1 minute candles
+	#[serde(rename = "1m")]
+	Minute,
+	/// 1 hour candles
+	#[serde(rename = "1h")]
+	Hour,
+	/// 1 day candles
+	#[serde(rename = "1d")]
+	Day,
+}
+
+impl Resolution {
+	/// All the resolutions candles are rolled up into
+	pub const ALL: [Resolution; 3] = [Resolution::Minute, Resolution::Hour, Resolution::Day];
+
+	/// Length of a candle, in seconds
+	pub fn seconds(self) -> i64 {
+		match self {
+			Resolution::Minute => 60,
+			Resolution::Hour => 60 * 60,
+			Resolution::Day => 24 * 60 * 60,
+		}
+	}
+
+	/// Start of the candle the `date` falls in
+	pub fn start_of(self, date: DateTime<Utc>) -> DateTime<Utc> {
+		let timestamp = date.timestamp();
+		let start = timestamp - timestamp.rem_euclid(self.seconds());
+		DateTime::<Utc>::from_utc(NaiveDateTime::from_timestamp(start, 0), Utc)
+	}
+
+	/// Single byte code, used by the binary encoding
+	pub fn to_u8(self) -> u8 {
+		match self {
+			Resolution::Minute => 0,
+			Resolution::Hour => 1,
+			Resolution::Day => 2,
+		}
+	}
+
+	/// Resolution from its single byte code
+	pub fn from_u8(code: u8) -> Option<Resolution> {
+		match code {
+			0 => Some(Resolution::Minute),
+			1 => Some(Resolution::Hour),
+			2 => Some(Resolution::Day),
+			_ => None,
+		}
+	}
+
+	/// Configured retention of the candles, `None` if they are kept forever
+	pub fn retention(self, config: &CandlesConfig) -> Option<Duration> {
+		let days = match self {
+			Resolution::Minute => config.minute_retention_days,
+			Resolution::Hour => config.hour_retention_days,
+			Resolution::Day => config.day_retention_days,
+		};
+		if days == 0 {
+			None
+		} else {
+			Some(Duration::days(days as i64))
+		}
+	}
+}
+
+impl fmt::Display for Resolution {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			Resolution::Minute => write!(f, "1m"),
+			Resolution::Hour => write!(f, "1h"),
+			Resolution::Day => write!(f, "1d"),
+		}
+	}
+}
+
+impl FromStr for Resolution {
+	type Err = Error;
+
+	fn from_str(s: &str) -> Result<Resolution, Error> {
+		match s {
+			"1m" => Ok(Resolution::Minute),
+			"1h" => Ok(Resolution::Hour),
+			"1d" => Ok(Resolution::Day),
+			_ => Err(ErrorKind::GenericError(format!("invalid candle resolution: {}", s)).into()),
+		}
+	}
+}
+
+/// An OHLC candle of a currency pair
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Candle {
+	/// The currency the rate is quoted from
+	pub from: String,
+	/// The currency the rate is quoted to
+	pub to: String,
+	/// Time span covered by the candle
+	pub resolution: Resolution,
+	/// Start of the time span covered by the candle
+	pub start: DateTime<Utc>,
+	/// Rate of the first tick
+	pub open: f64,
+	/// Highest rate
+	pub high: f64,
+	/// Lowest rate
+	pub low: f64,
+	/// Rate of the last tick
+	pub close: f64,
+	/// Number of ticks rolled up into the candle
+	pub ticks: u64,
+	/// Date of the first tick
+	pub first_tick: DateTime<Utc>,
+	/// Date of the last tick
+	pub last_tick: DateTime<Utc>,
+}
+
+impl Candle {
+	/// A new candle holding a single tick
+	pub fn from_tick(rate: &ExchangeRateResult, resolution: Resolution) -> Candle {
+		Candle {
+			from: rate.from.clone(),
+			to: rate.to.clone(),
+			resolution,
+			start: resolution.start_of(rate.date),
+			open: rate.rate,
+			high: rate.rate,
+			low: rate.rate,
+			close: rate.rate,
+			ticks: 1,
+			first_tick: rate.date,
+			last_tick: rate.date,
+		}
+	}
+
+	/// The pair id, i.e. "USD2CNY"
+	pub fn id(&self) -> String {
+		format!("{}2{}", self.from, self.to)
+	}
+
+	/// Merge another candle, of the same pair and time span, into this one.
+	/// Ticks can be merged in any order, open and close follow the tick dates.
+	pub fn merge(&mut self, other: &Candle) {
+		if other.first_tick < self.first_tick {
+			self.open = other.open;
+			self.first_tick = other.first_tick;
+		}
+		if other.last_tick >= self.last_tick {
+			self.close = other.close;
+			self.last_tick = other.last_tick;
+		}
+		self.high = self.high.max(other.high);
+		self.low = self.low.min(other.low);
+		self.ticks += other.ticks;
+	}
+}
+
+/// Roll up raw ticks into candles of the given resolution, ordered by pair
+/// and start date.
+pub fn rollup(ticks: &[ExchangeRateResult], resolution: Resolution) -> Vec<Candle> {
+	let mut candles: BTreeMap<(String, String, i64), Candle> = BTreeMap::new();
+	for tick in ticks {
+		let candle = Candle::from_tick(tick, resolution);
+		let key = (
+			candle.from.clone(),
+			candle.to.clone(),
+			candle.start.timestamp(),
+		);
+		match candles.get_mut(&key) {
+			Some(existing) => existing.merge(&candle),
+			None => {
+				candles.insert(key, candle);
+			}
+		}
+	}
+	candles.into_iter().map(|(_, candle)| candle).collect()
+}
+
+/// Compact the exchange rate history older than the `cutoff` date. The
+/// compacted ticks are first rolled up into candles, merged with the stored
+/// ones, then the candles beyond their retention period are pruned.
+/// Returns the number of ticks cleaned.
+pub fn compact<T: ?Sized>(
+	oracle: &mut T,
+	cutoff: DateTime<Utc>,
+	config: &CandlesConfig,
+) -> Result<usize, Error>
+where
+	T: OracleBackend,
+{
+	let now = Utc::now();
+	let mut batch = oracle.batch()?;
+	let expired: Vec<ExchangeRateResult> = batch.iter().filter(|r| r.date < cutoff).collect();
+
+	for resolution in Resolution::ALL.iter().cloned() {
+		for candle in rollup(&expired, resolution) {
+			let candle = match batch.get_candle(&candle.id(), resolution, candle.start)? {
+				Some(mut stored) => {
+					stored.merge(&candle);
+					stored
+				}
+				None => candle,
+			};
+			batch.save_candle(&candle)?;
+		}
+
+		if let Some(retention) = resolution.retention(config) {
+			let horizon = now - retention;
+			let pruned: Vec<Candle> = batch
+				.iter_candles(resolution)
+				.filter(|c| c.start + Duration::seconds(resolution.seconds()) <= horizon)
+				.collect();
+			for candle in pruned {
+				batch.delete_candle(&candle.id(), resolution, candle.start)?;
+			}
+		}
+	}
+
+	for rate in &expired {
+		let mut id = rate.from.clone();
+		id.push('2');
+		id.push_str(&rate.to);
+		batch.delete(&id, rate.date)?;
+	}
+	batch.commit()?;
+
+	COMPACTIONS.inc();
+	COMPACTED.inc_by(expired.len() as i64);
+	Ok(expired.len())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use chrono::TimeZone;
+
+	fn tick(from: &str, to: &str, rate: f64, timestamp: i64) -> ExchangeRateResult {
+		ExchangeRateResult {
+			from: from.to_owned(),
+			to: to.to_owned(),
+			rate,
+			date: Utc.timestamp(timestamp, 0),
+		}
+	}
+
+	#[test]
+	fn resolution_start_of() {
+		let date = Utc.timestamp(1_562_050_283, 0);
+		assert_eq!(Resolution::Minute.start_of(date).timestamp(), 1_562_050_260);
+		assert_eq!(Resolution::Hour.start_of(date).timestamp(), 1_562_047_200);
+		assert_eq!(Resolution::Day.start_of(date).timestamp(), 1_562_025_600);
+		assert_eq!("1h".parse::<Resolution>().unwrap(), Resolution::Hour);
+		assert!("5m".parse::<Resolution>().is_err());
+	}
+
+	#[test]
+	fn rollup_ohlc() {
+		// unordered ticks, over two minutes of USD2CNY and one of EUR2USD
+		let ticks = vec![
+			tick("USD", "CNY", 6.86, 1_562_050_230),
+			tick("USD", "CNY", 6.85, 1_562_050_200),
+			tick("USD", "CNY", 6.88, 1_562_050_210),
+			tick("EUR", "USD", 1.10, 1_562_050_220),
+			tick("USD", "CNY", 6.87, 1_562_050_270),
+		];
+
+		let candles = rollup(&ticks, Resolution::Minute);
+		assert_eq!(candles.len(), 3);
+		assert_eq!(candles[0].id(), "EUR2USD");
+		let c = &candles[1];
+		assert_eq!(c.id(), "USD2CNY");
+		assert_eq!(c.start.timestamp(), 1_562_050_200);
+		assert_eq!((c.open, c.high, c.low, c.close), (6.85, 6.88, 6.85, 6.86));
+		assert_eq!(c.ticks, 3);
+		assert_eq!(candles[2].start.timestamp(), 1_562_050_260);
+		assert_eq!(candles[2].ticks, 1);
+
+		let candles = rollup(&ticks, Resolution::Hour);
+		assert_eq!(candles.len(), 2);
+		let c = &candles[1];
+		assert_eq!((c.open, c.high, c.low, c.close), (6.85, 6.88, 6.85, 6.87));
+		assert_eq!(c.ticks, 4);
+	}
+
+	#[test]
+	fn merge_candles() {
+		let early = rollup(
+			&[
+				tick("USD", "CNY", 6.85, 1_562_050_200),
+				tick("USD", "CNY", 6.80, 1_562_050_300),
+			],
+			Resolution::Hour,
+		);
+		let late = rollup(
+			&[
+				tick("USD", "CNY", 6.90, 1_562_050_400),
+				tick("USD", "CNY", 6.87, 1_562_050_500),
+			],
+			Resolution::Hour,
+		);
+
+		let mut merged = late[0].clone();
+		merged.merge(&early[0]);
+		assert_eq!(
+			(merged.open, merged.high, merged.low, merged.close),
+			(6.85, 6.90, 6.80, 6.87)
+		);
+		assert_eq!(merged.ticks, 4);
+		assert_eq!(merged.first_tick.timestamp(), 1_562_050_200);
+		assert_eq!(merged.last_tick.timestamp(), 1_562_050_500);
+	}
+}
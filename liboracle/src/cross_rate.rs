@@ -0,0 +1,291 @@
+// Copyright 2019 The Gotts Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Cross rates derivation, by triangulation over the stored direct quotes.
+
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet, VecDeque};
+
+use crate::alphavantage::ExchangeRateResult;
+use crate::error::Error;
+use crate::types::OracleBackend;
+
+/// Legs older than this are not used to derive a cross rate by default.
+pub const DEFAULT_MAX_LEG_AGE_SECS: i64 = 3600;
+
+/// An exchange rate derived from one or more stored quotes.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CrossRate {
+	/// Currency to get the exchange rate for.
+	pub from: String,
+	/// Destination currency for the exchange rate.
+	pub to: String,
+	/// Value of the exchange rate.
+	pub rate: f64,
+	/// Currencies traversed to derive the rate, from `from` to `to` inclusive.
+	pub path: Vec<String>,
+	/// Date of the oldest leg used.
+	pub date: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone)]
+struct Edge {
+	to: String,
+	rate: f64,
+	date: DateTime<Utc>,
+}
+
+/// Graph of currencies, with an edge in both directions for each quoted pair.
+pub struct CurrencyGraph {
+	edges: HashMap<String, Vec<Edge>>,
+}
+
+impl CurrencyGraph {
+	/// Build the graph from a set of quotes, keeping only the latest quote of each pair.
+	pub fn from_rates<I>(rates: I) -> CurrencyGraph
+	where
+		I: IntoIterator<Item = ExchangeRateResult>,
+	{
+		let mut latest: HashMap<(String, String), ExchangeRateResult> = HashMap::new();
+		for rate in rates {
+			let key = (rate.from.clone(), rate.to.clone());
+			let is_newer = match latest.get(&key) {
+				Some(r) => rate.date > r.date,
+				None => true,
+			};
+			if is_newer {
+				latest.insert(key, rate);
+			}
+		}
+
+		let mut graph = CurrencyGraph {
+			edges: HashMap::new(),
+		};
+		for rate in latest.values() {
+			if !rate.rate.is_finite() || rate.rate <= 0.0 {
+				continue;
+			}
+			graph.add_edge(&rate.from, &rate.to, rate.rate, rate.date);
+			graph.add_edge(&rate.to, &rate.from, 1.0 / rate.rate, rate.date);
+		}
+		graph
+	}
+
+	/// Build the graph from the latest rates of the pairs stored by an oracle
+	/// backend.
+	pub fn from_backend<T: ?Sized>(oracle: &T) -> Result<CurrencyGraph, Error>
+	where
+		T: OracleBackend,
+	{
+		let mut rates: Vec<ExchangeRateResult> = vec![];
+		for id in oracle.ids("") {
+			if let Some(rate) = oracle.latest(&id)? {
+				rates.push(rate);
+			}
+		}
+		Ok(CurrencyGraph::from_rates(rates))
+	}
+
+	/// A direct quote takes precedence over an inverted one only if it is
+	/// at least as fresh.
+	fn add_edge(&mut self, from: &str, to: &str, rate: f64, date: DateTime<Utc>) {
+		let edges = self.edges.entry(from.to_owned()).or_insert(vec![]);
+		match edges.iter_mut().find(|e| e.to == to) {
+			Some(edge) => {
+				if date > edge.date {
+					edge.rate = rate;
+					edge.date = date;
+				}
+			}
+			None => edges.push(Edge {
+				to: to.to_owned(),
+				rate,
+				date,
+			}),
+		}
+	}
+
+	/// Derive the exchange rate from `from` to `to`, ignoring legs older than
+	/// `max_age`. A direct quote is used if any, then a path through the
+	/// `pivot` currency, and the shortest path otherwise.
+	pub fn cross_rate(
+		&self,
+		from: &str,
+		to: &str,
+		pivot: &str,
+		max_age: Duration,
+	) -> Option<CrossRate> {
+		let cutoff = Utc::now() - max_age;
+		let is_fresh = |e: &Edge| e.date >= cutoff;
+
+		let legs = match self.edge(from, to).filter(|e| is_fresh(*e)) {
+			Some(direct) => vec![direct],
+			None => match (self.edge(from, pivot), self.edge(pivot, to)) {
+				(Some(a), Some(b)) if is_fresh(a) && is_fresh(b) => vec![a, b],
+				_ => self.shortest_path(from, to, &is_fresh)?,
+			},
+		};
+		if legs.is_empty() {
+			return None;
+		}
+
+		let mut path = vec![from.to_owned()];
+		path.extend(legs.iter().map(|e| e.to.clone()));
+		Some(CrossRate {
+			from: from.to_owned(),
+			to: to.to_owned(),
+			rate: legs.iter().fold(1.0, |rate, e| rate * e.rate),
+			path,
+			date: legs.iter().map(|e| e.date).min().unwrap(),
+		})
+	}
+
+	fn edge(&self, from: &str, to: &str) -> Option<&Edge> {
+		self.edges.get(from)?.iter().find(|e| e.to == to)
+	}
+
+	/// Breadth first search of the path with the fewest legs.
+	fn shortest_path<'a, F>(
+		&'a self,
+		from: &'a str,
+		to: &str,
+		is_fresh: &F,
+	) -> Option<Vec<&'a Edge>>
+	where
+		F: Fn(&Edge) -> bool,
+	{
+		let mut visited: HashSet<&str> = HashSet::new();
+		let mut prev: HashMap<&str, (&str, &Edge)> = HashMap::new();
+		let mut queue: VecDeque<&str> = VecDeque::new();
+		visited.insert(from);
+		queue.push_back(from);
+		while let Some(node) = queue.pop_front() {
+			if node == to {
+				break;
+			}
+			if let Some(edges) = self.edges.get(node) {
+				for edge in edges {
+					if !is_fresh(edge) || visited.contains(edge.to.as_str()) {
+						continue;
+					}
+					visited.insert(&edge.to);
+					prev.insert(&edge.to, (node, edge));
+					queue.push_back(&edge.to);
+				}
+			}
+		}
+
+		if !visited.contains(to) {
+			return None;
+		}
+		let mut legs = vec![];
+		let mut node = to;
+		while node != from {
+			let (p, edge) = prev.get(node)?;
+			legs.push(*edge);
+			node = *p;
+		}
+		legs.reverse();
+		Some(legs)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn quote(from: &str, to: &str, rate: f64, age_mins: i64) -> ExchangeRateResult {
+		ExchangeRateResult {
+			from: from.to_owned(),
+			to: to.to_owned(),
+			rate,
+			date: Utc::now() - Duration::minutes(age_mins),
+		}
+	}
+
+	fn graph() -> CurrencyGraph {
+		CurrencyGraph::from_rates(vec![
+			quote("EUR", "USD", 1.10, 5),
+			quote("GBP", "USD", 1.25, 2),
+			quote("USD", "CNY", 7.00, 1),
+			quote("USD", "JPY", 110.0, 90),
+			quote("USD", "CNY", 6.00, 30),
+			quote("KRW", "JPY", 0.1, 3),
+		])
+	}
+
+	#[test]
+	fn direct_and_inverse() {
+		let g = graph();
+		let max_age = Duration::minutes(60);
+		let direct = g.cross_rate("USD", "CNY", "USD", max_age).unwrap();
+		assert_eq!(direct.rate, 7.00);
+		assert_eq!(direct.path, vec!["USD", "CNY"]);
+
+		let inverse = g.cross_rate("USD", "GBP", "USD", max_age).unwrap();
+		assert_eq!(inverse.rate, 1.0 / 1.25);
+		assert_eq!(inverse.path, vec!["USD", "GBP"]);
+	}
+
+	#[test]
+	fn via_pivot() {
+		let g = graph();
+		let cross = g
+			.cross_rate("EUR", "CNY", "USD", Duration::minutes(60))
+			.unwrap();
+		assert!((cross.rate - 1.10 * 7.00).abs() < 1e-9);
+		assert_eq!(cross.path, vec!["EUR", "USD", "CNY"]);
+		assert!(cross.date < Utc::now() - Duration::minutes(4));
+	}
+
+	#[test]
+	fn stale_legs_ignored() {
+		let g = graph();
+		assert!(g
+			.cross_rate("EUR", "JPY", "USD", Duration::minutes(60))
+			.is_none());
+		let cross = g
+			.cross_rate("EUR", "JPY", "USD", Duration::minutes(120))
+			.unwrap();
+		assert_eq!(cross.path, vec!["EUR", "USD", "JPY"]);
+	}
+
+	#[test]
+	fn shortest_path() {
+		let g = graph();
+		let cross = g
+			.cross_rate("KRW", "GBP", "USD", Duration::minutes(120))
+			.unwrap();
+		assert_eq!(cross.path, vec!["KRW", "JPY", "USD", "GBP"]);
+		assert!((cross.rate - 0.1 / 110.0 / 1.25).abs() < 1e-12);
+		assert!(g
+			.cross_rate("KRW", "SGD", "USD", Duration::minutes(120))
+			.is_none());
+	}
+
+	#[test]
+	fn invalid_rates_ignored() {
+		let g = CurrencyGraph::from_rates(vec![
+			quote("EUR", "USD", std::f64::INFINITY, 1),
+			quote("GBP", "USD", std::f64::NAN, 1),
+			quote("USD", "CNY", 0.0, 1),
+		]);
+		let max_age = Duration::minutes(60);
+		assert!(g.cross_rate("USD", "EUR", "USD", max_age).is_none());
+		assert!(g.cross_rate("EUR", "USD", "USD", max_age).is_none());
+		assert!(g.cross_rate("GBP", "USD", "USD", max_age).is_none());
+		assert!(g.cross_rate("CNY", "USD", "USD", max_age).is_none());
+	}
+}
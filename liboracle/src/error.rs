@@ -47,6 +47,22 @@ pub enum ErrorKind {
 	#[fail(display = "Vendor API error")]
 	Vendor,
 
+	/// No exchange rate found
+	#[fail(display = "Not found: {}", _0)]
+	NotFound(String),
+
+	/// The exchange rate found is older than the maximum staleness allowed
+	#[fail(display = "Stale exchange rate: {}", _0)]
+	Stale(String),
+
+	/// A quote rejected by the sanity filters
+	#[fail(display = "Quote rejected: {}", _0)]
+	Rejected(String),
+
+	/// Error signing or verifying a price attestation
+	#[fail(display = "Signature error: {}", _0)]
+	Signature(String),
+
 	/// Other
 	#[fail(display = "Generic error: {}", _0)]
 	GenericError(String),
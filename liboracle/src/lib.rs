@@ -15,19 +15,37 @@
 
 //! Concrete implementations of Gotts Oracle
 
+#[macro_use]
+extern crate lazy_static;
 #[macro_use]
 extern crate log;
+#[macro_use]
+extern crate prometheus;
 use failure;
 extern crate failure_derive;
 
 use gotts_oracle_alphavantage as alphavantage;
+use gotts_oracle_sources as sources;
 
+pub mod attestation;
+pub mod backfill;
+pub mod candles;
+pub mod cross_rate;
 pub mod error;
 pub mod lmdb;
 pub mod oracle_ser;
 pub mod oracle_store;
+pub mod schema;
+pub mod twap;
 pub mod types;
+pub mod validation;
 
-pub use self::error::Error;
-pub use self::lmdb::{oracle_db_exists, LMDBBackend};
-pub use self::types::{OracleBackend, OracleInst};
+pub use self::attestation::{verify_price_set, OracleKeypair, SignedPriceSet};
+pub use self::backfill::{BackfillStats, Coverage};
+pub use self::candles::{Candle, Resolution};
+pub use self::cross_rate::{CrossRate, CurrencyGraph};
+pub use self::error::{Error, ErrorKind};
+pub use self::lmdb::{oracle_db_exists, restore_snapshot, CheckReport, LMDBBackend, SNAPSHOT_DIR};
+pub use self::twap::{Gap, Twap};
+pub use self::types::{DbStats, OracleBackend, OracleInst, Snapshotter};
+pub use self::validation::{QuoteValidator, ValidationStats};
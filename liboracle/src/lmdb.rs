@@ -17,17 +17,51 @@ use chrono::{DateTime, Utc};
 use std::cell::RefCell;
 use std::{fs, path};
 
-use super::error::Error;
-use super::oracle_store::{self, option_to_not_found, Store};
-use super::types::{ExchangePriceBatch, OracleBackend};
+use super::candles::{Candle, Resolution};
+use super::error::{Error, ErrorKind};
+use super::oracle_store::{self, Store, StoreSnapshotter};
+use super::schema;
+use super::types::{DbStats, ExchangePriceBatch, OracleBackend, Snapshotter};
 use crate::alphavantage::ExchangeRateResult;
+use crate::sources::SourceReading;
 use byteorder::{BigEndian, WriteBytesExt};
 use gotts_oracle_config::ServerConfig;
+use gotts_oracle_util::to_hex;
 
 const SEP: u8 = b':';
 pub const DB_DIR: &'static str = "db";
+/// Directory of the snapshots taken through the API, under the db root
+pub const SNAPSHOT_DIR: &'static str = "snapshots";
+
+/// Directory of the LMDB environment of the store, and of a snapshot being
+/// restored, under the db directory
+const ENV_DIR: &'static str = "lmdb";
+const RESTORE_ENV_DIR: &'static str = "lmdb.restore";
+/// LMDB data file, in the environment directory and in the snapshots
+const DATA_FILE: &'static str = "data.mdb";
+/// LMDB lock file, in the environment directory
+const LOCK_FILE: &'static str = "lock.mdb";
 
 const EXCHANGE_RATE_PREFIX: u8 = 'e' as u8;
+const SOURCE_READING_PREFIX: u8 = 'r' as u8;
+const CANDLE_1M_PREFIX: u8 = 'm' as u8;
+const CANDLE_1H_PREFIX: u8 = 'h' as u8;
+const CANDLE_1D_PREFIX: u8 = 'd' as u8;
+
+/// Latest timestamp a key is looked up from. The timestamps of the dates
+/// chrono handles start with a zero byte, so that the keys of an id up to it
+/// are all lower than the ones of the longer ids it's a prefix of, i.e.
+/// ETH2USD and ETH2USDT.
+const MAX_TIMESTAMP: i64 = (1 << 56) - 1;
+
+/// The db key prefix of the candles of a resolution
+fn candle_prefix(resolution: Resolution) -> u8 {
+	match resolution {
+		Resolution::Minute => CANDLE_1M_PREFIX,
+		Resolution::Hour => CANDLE_1H_PREFIX,
+		Resolution::Day => CANDLE_1D_PREFIX,
+	}
+}
 
 /// Build a db key from a prefix and a byte vector identifier.
 pub fn to_key(prefix: u8, k: &mut Vec<u8>) -> Vec<u8> {
@@ -48,6 +82,109 @@ pub fn to_key_i64(prefix: u8, k: &mut Vec<u8>, val: i64) -> Vec<u8> {
 	res
 }
 
+/// Build a source reading db key, the reading source name appended after the
+/// timestamp so that all readings of an aggregated rate share a common prefix
+fn to_reading_key(id: &str, date: DateTime<Utc>, source: &str) -> Vec<u8> {
+	let mut key = to_key_i64(
+		SOURCE_READING_PREFIX,
+		&mut id.as_bytes().to_vec(),
+		date.timestamp(),
+	);
+	key.extend_from_slice(source.as_bytes());
+	key
+}
+
+/// Whether another process has the LMDB environment in the `env` directory
+/// open. LMDB holds a shared lock on the first byte of the lock file while
+/// the environment is open, which conflicts with a write lock. The locks of
+/// this process aren't reported, and are released once the lock file closed,
+/// so that the environment must not be open in this process.
+#[cfg(unix)]
+fn env_in_use(env: &path::Path) -> Result<bool, Error> {
+	use std::os::unix::io::AsRawFd;
+
+	let lock_file = match fs::OpenOptions::new()
+		.read(true)
+		.write(true)
+		.open(env.join(LOCK_FILE))
+	{
+		Ok(file) => file,
+		Err(ref e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(false),
+		Err(e) => return Err(ErrorKind::Backend(format!("{}", e)).into()),
+	};
+	let mut lock: libc::flock = unsafe { std::mem::zeroed() };
+	lock.l_type = libc::F_WRLCK as libc::c_short;
+	lock.l_whence = libc::SEEK_SET as libc::c_short;
+	lock.l_start = 0;
+	lock.l_len = 1;
+	if unsafe { libc::fcntl(lock_file.as_raw_fd(), libc::F_GETLK, &mut lock) } != 0 {
+		let e = std::io::Error::last_os_error();
+		return Err(ErrorKind::Backend(format!("{}", e)).into());
+	}
+	Ok(lock.l_type != libc::F_UNLCK as libc::c_short)
+}
+
+/// Whether another process has the LMDB environment open, which can't be
+/// told but for the unix systems, on which LMDB locks its lock file.
+#[cfg(not(unix))]
+fn env_in_use(_env: &path::Path) -> Result<bool, Error> {
+	Ok(false)
+}
+
+/// Replace the oracle database by a snapshot, keeping the replaced database
+/// aside. The snapshot is checked to open as a database before anything is
+/// replaced, and the database to be closed, the oracle must not be running
+/// meanwhile. Returns the path the replaced database is kept at, if there was
+/// one.
+pub fn restore_snapshot(
+	config: &ServerConfig,
+	snapshot: &str,
+) -> Result<Option<path::PathBuf>, Error> {
+	let backend_error = |e: std::io::Error| ErrorKind::Backend(format!("{}", e));
+	let snapshot_file = path::Path::new(snapshot).join(DATA_FILE);
+	if !snapshot_file.is_file() {
+		return Err(ErrorKind::NotFound(format!("no database snapshot in {}", snapshot)).into());
+	}
+	let db_path = path::Path::new(&config.db_root).join(DB_DIR);
+	let env = db_path.join(ENV_DIR);
+	if env_in_use(&env)? {
+		return Err(ErrorKind::Backend(format!(
+			"the database in {} is open, stop the oracle before restoring it",
+			env.display()
+		))
+		.into());
+	}
+
+	// stage a copy of the snapshot next to the database
+	let staging = db_path.join(RESTORE_ENV_DIR);
+	if staging.exists() {
+		fs::remove_dir_all(&staging).map_err(backend_error)?;
+	}
+	fs::create_dir_all(&staging).map_err(backend_error)?;
+	fs::copy(&snapshot_file, staging.join(DATA_FILE)).map_err(backend_error)?;
+	Store::new(
+		db_path.to_str().unwrap(),
+		Some(RESTORE_ENV_DIR),
+		Some(DB_DIR),
+		None,
+	)?;
+
+	let replaced = if env.exists() {
+		let replaced = db_path.join(format!(
+			"{}.{}.bak",
+			ENV_DIR,
+			Utc::now().format("%Y%m%d%H%M%S")
+		));
+		fs::rename(&env, &replaced).map_err(backend_error)?;
+		Some(replaced)
+	} else {
+		None
+	};
+	fs::rename(&staging, &env).map_err(backend_error)?;
+	info!("database restored from snapshot {}", snapshot);
+	Ok(replaced)
+}
+
 /// test to see if database files exist in the current directory. If so,
 /// use a DB backend for all operations
 pub fn oracle_db_exists(config: ServerConfig) -> bool {
@@ -55,6 +192,36 @@ pub fn oracle_db_exists(config: ServerConfig) -> bool {
 	db_path.exists()
 }
 
+/// Snapshotter of the LMDB backend, holding the environment of the store
+struct LMDBSnapshotter {
+	store: StoreSnapshotter,
+}
+
+impl Snapshotter for LMDBSnapshotter {
+	fn snapshot(&self, path: &str) -> Result<u64, Error> {
+		let dir = path::Path::new(path);
+		let is_empty = dir
+			.read_dir()
+			.map(|mut d| d.next().is_none())
+			.unwrap_or(true);
+		if !is_empty {
+			return Err(
+				ErrorKind::Backend(format!("snapshot directory {} is not empty", path)).into(),
+			);
+		}
+		fs::create_dir_all(dir).map_err(|e| {
+			ErrorKind::Backend(format!("can't create snapshot directory {}: {}", path, e))
+		})?;
+
+		self.store.snapshot(path)?;
+		let size = fs::metadata(dir.join(DATA_FILE))
+			.map_err(|e| ErrorKind::Backend(format!("snapshot {} missing: {}", path, e)))?
+			.len();
+		info!("database snapshot written to {}, {} bytes", path, size);
+		Ok(size)
+	}
+}
+
 pub struct LMDBBackend {
 	db: Store,
 	config: ServerConfig,
@@ -62,11 +229,18 @@ pub struct LMDBBackend {
 
 impl LMDBBackend {
 	pub fn new(config: ServerConfig) -> Result<Self, Error> {
+		let res = LMDBBackend::open_unmigrated(config)?;
+		schema::migrate(&res.db)?;
+		Ok(res)
+	}
+
+	/// Open the database as it is, without migrating it to the current schema
+	/// version, i.e. to check it.
+	pub fn open_unmigrated(config: ServerConfig) -> Result<Self, Error> {
 		let db_path = path::Path::new(&config.db_root).join(DB_DIR);
 		fs::create_dir_all(&db_path).expect("Couldn't create Oracle backend directory!");
 
 		let store = Store::new(db_path.to_str().unwrap(), None, Some(DB_DIR), None)?;
-
 		let res = LMDBBackend {
 			db: store,
 			config: config.clone(),
@@ -80,6 +254,47 @@ impl LMDBBackend {
 		let db_path = path::Path::new(&config.db_root).join(DB_DIR);
 		db_path.exists()
 	}
+
+	/// Decode every entry of the database, reporting the ones which can't be,
+	/// that the iterators skip.
+	pub fn check(&self) -> Result<CheckReport, Error> {
+		let mut report = CheckReport {
+			schema_version: schema::schema_version(&self.db)?,
+			..CheckReport::default()
+		};
+		for (key, value) in self.db.iter_raw(&[])? {
+			let decoded = match key.first().cloned() {
+				Some(EXCHANGE_RATE_PREFIX) => {
+					oracle_store::decode::<ExchangeRateResult>(&value).map(|_| ())
+				}
+				Some(SOURCE_READING_PREFIX) => {
+					oracle_store::decode::<SourceReading>(&value).map(|_| ())
+				}
+				Some(CANDLE_1M_PREFIX) | Some(CANDLE_1H_PREFIX) | Some(CANDLE_1D_PREFIX) => {
+					oracle_store::decode::<Candle>(&value).map(|_| ())
+				}
+				Some(schema::SCHEMA_PREFIX) => continue,
+				_ => Err(oracle_store::Error::SerErr("unknown key prefix".to_owned())),
+			};
+			match decoded {
+				Ok(()) => report.decoded += 1,
+				Err(e) => report.undecodable.push((to_hex(key), e.to_string())),
+			}
+		}
+		Ok(report)
+	}
+}
+
+/// Outcome of the check of a database
+#[derive(Debug, Clone, Default)]
+pub struct CheckReport {
+	/// Schema version of the database
+	pub schema_version: u32,
+	/// Number of entries decoded
+	pub decoded: usize,
+	/// Hex encoded keys of the entries which can't be decoded, along with the
+	/// decoding error
+	pub undecodable: Vec<(String, String)>,
 }
 
 impl OracleBackend for LMDBBackend {
@@ -93,8 +308,131 @@ impl OracleBackend for LMDBBackend {
 	}
 
 	fn get(&self, id: &str) -> Result<ExchangeRateResult, Error> {
-		let key = to_key(EXCHANGE_RATE_PREFIX, &mut id.as_bytes().to_vec());
-		option_to_not_found(self.db.get_ser(&key), &format!("Key Id: {}", id)).map_err(|e| e.into())
+		self.latest(id)?
+			.ok_or_else(|| ErrorKind::NotFound(format!("Key Id: {}", id)).into())
+	}
+
+	fn ids(&self, prefix: &str) -> Vec<String> {
+		let prefix_key = to_key(EXCHANGE_RATE_PREFIX, &mut prefix.as_bytes().to_vec());
+		let mut end = prefix_key.clone();
+		end.push(0xff);
+
+		// skip from one pair to the next one, positive timestamps always starting
+		// with a zero byte
+		let mut ids = vec![];
+		let mut seek = prefix_key.clone();
+		loop {
+			let first = match self
+				.db
+				.iter_range::<ExchangeRateResult>(&prefix_key, &seek, &end)
+			{
+				Ok(mut iter) => iter.next(),
+				Err(_) => None,
+			};
+			match first {
+				Some((key, _)) if key.len() > 10 => {
+					seek = key[..key.len() - 8].to_vec();
+					seek.push(0x01);
+					ids.push(String::from_utf8_lossy(&key[2..key.len() - 8]).to_string());
+				}
+				_ => break,
+			}
+		}
+		ids
+	}
+
+	fn range<'a>(
+		&'a self,
+		id: &str,
+		from: DateTime<Utc>,
+		to: DateTime<Utc>,
+	) -> Box<dyn Iterator<Item = ExchangeRateResult> + 'a> {
+		let prefix = to_key(EXCHANGE_RATE_PREFIX, &mut id.as_bytes().to_vec());
+		let from = to_key_i64(
+			EXCHANGE_RATE_PREFIX,
+			&mut id.as_bytes().to_vec(),
+			from.timestamp(),
+		);
+		let to = to_key_i64(
+			EXCHANGE_RATE_PREFIX,
+			&mut id.as_bytes().to_vec(),
+			to.timestamp(),
+		);
+		Box::new(
+			self.db
+				.iter_range(&prefix, &from, &to)
+				.unwrap()
+				.map(|o| o.1),
+		)
+	}
+
+	fn latest(&self, id: &str) -> Result<Option<ExchangeRateResult>, Error> {
+		let prefix = to_key(EXCHANGE_RATE_PREFIX, &mut id.as_bytes().to_vec());
+		let last = to_key_i64(
+			EXCHANGE_RATE_PREFIX,
+			&mut id.as_bytes().to_vec(),
+			MAX_TIMESTAMP,
+		);
+		Ok(self.db.iter_rev(&prefix, &last)?.next().map(|o| o.1))
+	}
+
+	fn at_or_before(
+		&self,
+		id: &str,
+		time: DateTime<Utc>,
+	) -> Result<Option<ExchangeRateResult>, Error> {
+		let prefix = to_key(EXCHANGE_RATE_PREFIX, &mut id.as_bytes().to_vec());
+		let key = to_key_i64(
+			EXCHANGE_RATE_PREFIX,
+			&mut id.as_bytes().to_vec(),
+			time.timestamp(),
+		);
+		Ok(self.db.iter_rev(&prefix, &key)?.next().map(|o| o.1))
+	}
+
+	fn iter_readings<'a>(&'a self, id: &str) -> Box<dyn Iterator<Item = SourceReading> + 'a> {
+		let key = to_key(SOURCE_READING_PREFIX, &mut id.as_bytes().to_vec());
+		Box::new(self.db.iter(&key).unwrap().map(|o| o.1))
+	}
+
+	fn iter_candles<'a>(
+		&'a self,
+		id: &str,
+		resolution: Resolution,
+	) -> Box<dyn Iterator<Item = Candle> + 'a> {
+		let key = to_key(candle_prefix(resolution), &mut id.as_bytes().to_vec());
+		Box::new(self.db.iter(&key).unwrap().map(|o| o.1))
+	}
+
+	fn candle_at_or_before(
+		&self,
+		id: &str,
+		resolution: Resolution,
+		time: DateTime<Utc>,
+	) -> Result<Option<Candle>, Error> {
+		let prefix = candle_prefix(resolution);
+		let id_prefix = to_key(prefix, &mut id.as_bytes().to_vec());
+		let key = to_key_i64(prefix, &mut id.as_bytes().to_vec(), time.timestamp());
+		Ok(self.db.iter_rev(&id_prefix, &key)?.next().map(|o| o.1))
+	}
+
+	fn candles_range<'a>(
+		&'a self,
+		id: &str,
+		resolution: Resolution,
+		from: DateTime<Utc>,
+		to: DateTime<Utc>,
+	) -> Box<dyn Iterator<Item = Candle> + 'a> {
+		let prefix = candle_prefix(resolution);
+		let id_prefix = to_key(prefix, &mut id.as_bytes().to_vec());
+		let from = to_key_i64(prefix, &mut id.as_bytes().to_vec(), from.timestamp());
+		let to = to_key_i64(prefix, &mut id.as_bytes().to_vec(), to.timestamp());
+		Box::new(
+			self.db
+				.iter_range(&id_prefix, &from, &to)
+				.unwrap()
+				.map(|o| o.1),
+		)
 	}
 
 	fn batch<'a>(&'a mut self) -> Result<Box<dyn ExchangePriceBatch + 'a>, Error> {
@@ -103,6 +441,19 @@ impl OracleBackend for LMDBBackend {
 			db: RefCell::new(Some(self.db.batch()?)),
 		}))
 	}
+
+	fn snapshotter(&self) -> Box<dyn Snapshotter> {
+		Box::new(LMDBSnapshotter {
+			store: self.db.snapshotter(),
+		})
+	}
+
+	fn stats(&self) -> Result<DbStats, Error> {
+		Ok(DbStats {
+			schema_version: schema::schema_version(&self.db)?,
+			..self.db.stats()?
+		})
+	}
 }
 
 /// An atomic batch in which all changes can be committed all at once or
@@ -139,13 +490,31 @@ impl<'a> ExchangePriceBatch for Batch<'a> {
 		Ok(())
 	}
 
+	fn save_reading(&mut self, date: DateTime<Utc>, reading: SourceReading) -> Result<(), Error> {
+		let mut fromto = reading.rate.from.clone();
+		fromto.push('2');
+		fromto.push_str(&reading.rate.to);
+		let key = to_reading_key(&fromto, date, &reading.source);
+		self.db.borrow().as_ref().unwrap().put_ser(&key, &reading)?;
+		Ok(())
+	}
+
 	fn get(&self, id: &str) -> Result<ExchangeRateResult, Error> {
-		let key = to_key(EXCHANGE_RATE_PREFIX, &mut id.as_bytes().to_vec());
-		option_to_not_found(
-			self.db.borrow().as_ref().unwrap().get_ser(&key),
-			&format!("Key ID: {}", id),
-		)
-		.map_err(|e| e.into())
+		let prefix = to_key(EXCHANGE_RATE_PREFIX, &mut id.as_bytes().to_vec());
+		let last = to_key_i64(
+			EXCHANGE_RATE_PREFIX,
+			&mut id.as_bytes().to_vec(),
+			MAX_TIMESTAMP,
+		);
+		let latest: Option<ExchangeRateResult> = self
+			.db
+			.borrow()
+			.as_ref()
+			.unwrap()
+			.iter_rev(&prefix, &last)?
+			.next()
+			.map(|o| o.1);
+		latest.ok_or_else(|| ErrorKind::NotFound(format!("Key ID: {}", id)).into())
 	}
 
 	fn iter(&self) -> Box<dyn Iterator<Item = ExchangeRateResult>> {
@@ -184,6 +553,72 @@ impl<'a> ExchangePriceBatch for Batch<'a> {
 			let _ = self.db.borrow().as_ref().unwrap().delete(&key);
 		}
 
+		// And all the source readings it was aggregated from.
+		{
+			let prefix = to_key_i64(
+				SOURCE_READING_PREFIX,
+				&mut id.as_bytes().to_vec(),
+				date.timestamp(),
+			);
+			let db = self.db.borrow();
+			let db = db.as_ref().unwrap();
+			let keys: Vec<Vec<u8>> = db.iter::<SourceReading>(&prefix)?.map(|o| o.0).collect();
+			for key in keys {
+				let _ = db.delete(&key);
+			}
+		}
+
+		Ok(())
+	}
+
+	fn get_candle(
+		&self,
+		id: &str,
+		resolution: Resolution,
+		start: DateTime<Utc>,
+	) -> Result<Option<Candle>, Error> {
+		let key = to_key_i64(
+			candle_prefix(resolution),
+			&mut id.as_bytes().to_vec(),
+			start.timestamp(),
+		);
+		Ok(self.db.borrow().as_ref().unwrap().get_ser(&key)?)
+	}
+
+	fn save_candle(&mut self, candle: &Candle) -> Result<(), Error> {
+		let key = to_key_i64(
+			candle_prefix(candle.resolution),
+			&mut candle.id().as_bytes().to_vec(),
+			candle.start.timestamp(),
+		);
+		self.db.borrow().as_ref().unwrap().put_ser(&key, candle)?;
+		Ok(())
+	}
+
+	fn iter_candles(&self, resolution: Resolution) -> Box<dyn Iterator<Item = Candle>> {
+		Box::new(
+			self.db
+				.borrow()
+				.as_ref()
+				.unwrap()
+				.iter(&[candle_prefix(resolution)])
+				.unwrap()
+				.map(|o| o.1),
+		)
+	}
+
+	fn delete_candle(
+		&mut self,
+		id: &str,
+		resolution: Resolution,
+		start: DateTime<Utc>,
+	) -> Result<(), Error> {
+		let key = to_key_i64(
+			candle_prefix(resolution),
+			&mut id.as_bytes().to_vec(),
+			start.timestamp(),
+		);
+		let _ = self.db.borrow().as_ref().unwrap().delete(&key);
 		Ok(())
 	}
 
@@ -193,3 +628,333 @@ impl<'a> ExchangePriceBatch for Batch<'a> {
 		Ok(())
 	}
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::{candles, twap};
+	use chrono::{Duration, TimeZone};
+	use gotts_oracle_config::CandlesConfig;
+
+	fn rate(timestamp: i64, rate: f64) -> ExchangeRateResult {
+		pair_rate("USD", "CNY", timestamp, rate)
+	}
+
+	fn pair_rate(from: &str, to: &str, timestamp: i64, rate: f64) -> ExchangeRateResult {
+		ExchangeRateResult {
+			from: from.to_owned(),
+			to: to.to_owned(),
+			rate,
+			date: Utc.timestamp(timestamp, 0),
+		}
+	}
+
+	fn save(oracle: &mut LMDBBackend, rate: ExchangeRateResult) {
+		let mut batch = oracle.batch().unwrap();
+		batch.save(rate.date, rate).unwrap();
+		batch.commit().unwrap();
+	}
+
+	/// A fresh backend in a temporary directory, removed once dropped
+	struct TempBackend {
+		root: path::PathBuf,
+		oracle: Option<LMDBBackend>,
+	}
+
+	impl TempBackend {
+		fn new(name: &str) -> TempBackend {
+			let root = std::env::temp_dir().join(name);
+			let _ = fs::remove_dir_all(&root);
+			let config = ServerConfig {
+				db_root: root.to_string_lossy().into_owned(),
+				..ServerConfig::default()
+			};
+			TempBackend {
+				root,
+				oracle: Some(LMDBBackend::new(config).unwrap()),
+			}
+		}
+
+		fn oracle(&mut self) -> &mut LMDBBackend {
+			self.oracle.as_mut().unwrap()
+		}
+	}
+
+	impl Drop for TempBackend {
+		fn drop(&mut self) {
+			self.oracle = None;
+			let _ = fs::remove_dir_all(&self.root);
+		}
+	}
+
+	#[test]
+	fn ids_sharing_a_prefix() {
+		let mut db = TempBackend::new("gotts_oracle_ids_sharing_a_prefix");
+		let oracle = db.oracle();
+		for timestamp in &[1_562_050_200, 1_562_050_260, 1_562_050_320] {
+			save(oracle, rate(*timestamp, 6.87));
+			save(oracle, pair_rate("ETH", "USDT", *timestamp, 290.0));
+		}
+		save(oracle, pair_rate("ETH", "USD", 1_562_050_200, 289.0));
+		save(oracle, pair_rate("ETH", "USD", 1_562_050_260, 289.5));
+
+		// each pair is listed once, whatever its number of rates
+		assert_eq!(oracle.ids(""), vec!["ETH2USD", "ETH2USDT", "USD2CNY"]);
+		assert_eq!(oracle.ids("ETH2USD"), vec!["ETH2USD", "ETH2USDT"]);
+		assert_eq!(oracle.ids("USD"), vec!["USD2CNY"]);
+		assert!(oracle.ids("CNY").is_empty());
+
+		// the rates of ETH2USDT, keyed after the ones of ETH2USD, aren't read as
+		// ETH2USD ones
+		let latest = oracle.latest("ETH2USD").unwrap().unwrap();
+		assert_eq!(latest, pair_rate("ETH", "USD", 1_562_050_260, 289.5));
+		assert_eq!(oracle.get("ETH2USD").unwrap(), latest);
+		let at = oracle
+			.at_or_before("ETH2USD", Utc.timestamp(1_562_050_400, 0))
+			.unwrap();
+		assert_eq!(at, Some(latest));
+		let all: Vec<ExchangeRateResult> = oracle
+			.range(
+				"ETH2USD",
+				Utc.timestamp(0, 0),
+				Utc.timestamp(1_562_050_400, 0),
+			)
+			.collect();
+		assert_eq!(all.len(), 2);
+		assert!(all.iter().all(|r| r.to == "USD"));
+		let batch = oracle.batch().unwrap();
+		assert_eq!(
+			batch.get("ETH2USD").unwrap().date.timestamp(),
+			1_562_050_260
+		);
+	}
+
+	#[test]
+	fn at_or_before_and_range() {
+		let mut db = TempBackend::new("gotts_oracle_at_or_before_and_range");
+		let oracle = db.oracle();
+		save(oracle, rate(1_562_050_200, 6.85));
+		save(oracle, rate(1_562_050_260, 6.86));
+		save(oracle, rate(1_562_050_320, 6.87));
+		let at = |timestamp: i64| -> Option<i64> {
+			oracle
+				.at_or_before("USD2CNY", Utc.timestamp(timestamp, 0))
+				.unwrap()
+				.map(|r| r.date.timestamp())
+		};
+
+		// an exact match, between two rates, before the first and past the last
+		assert_eq!(at(1_562_050_260), Some(1_562_050_260));
+		assert_eq!(at(1_562_050_300), Some(1_562_050_260));
+		assert_eq!(at(1_562_050_199), None);
+		assert_eq!(at(1_562_099_999), Some(1_562_050_320));
+		assert_eq!(
+			oracle.latest("USD2CNY").unwrap().unwrap().date.timestamp(),
+			1_562_050_320
+		);
+		assert!(oracle.latest("EUR2USD").unwrap().is_none());
+
+		// both bounds of a range are included
+		let range = |from: i64, to: i64| -> Vec<i64> {
+			oracle
+				.range("USD2CNY", Utc.timestamp(from, 0), Utc.timestamp(to, 0))
+				.map(|r| r.date.timestamp())
+				.collect()
+		};
+		assert_eq!(
+			range(1_562_050_200, 1_562_050_260),
+			vec![1_562_050_200, 1_562_050_260]
+		);
+		assert_eq!(range(1_562_050_201, 1_562_050_319), vec![1_562_050_260]);
+		assert_eq!(range(1_562_050_320, 1_562_050_320), vec![1_562_050_320]);
+		assert!(range(1_562_050_321, 1_562_099_999).is_empty());
+	}
+
+	#[test]
+	fn rate_at_compacted() {
+		let mut db = TempBackend::new("gotts_oracle_rate_at_compacted");
+		let oracle = db.oracle();
+		save(oracle, rate(1_562_050_200, 6.85));
+		save(oracle, rate(1_562_050_210, 6.86));
+		save(oracle, rate(1_562_050_320, 6.87));
+		let kept = CandlesConfig {
+			minute_retention_days: 0,
+			hour_retention_days: 0,
+			day_retention_days: 0,
+		};
+		candles::compact(oracle, Utc.timestamp(1_562_050_400, 0), &kept).unwrap();
+		assert!(oracle.latest("USD2CNY").unwrap().is_none());
+		save(oracle, rate(1_562_057_400, 6.90));
+
+		let rate_at = |oracle: &LMDBBackend, timestamp: i64, max_staleness: i64| {
+			oracle.rate_at(
+				"USD2CNY",
+				Utc.timestamp(timestamp, 0),
+				Duration::seconds(max_staleness),
+			)
+		};
+		// the raw rates are read first, then the candles they're compacted into
+		assert_eq!(rate_at(oracle, 1_562_057_410, 60).unwrap().rate, 6.90);
+		assert_eq!(
+			rate_at(oracle, 1_562_050_300, 300).unwrap(),
+			rate(1_562_050_210, 6.86)
+		);
+		assert_eq!(
+			rate_at(oracle, 1_562_050_330, 300).unwrap(),
+			rate(1_562_050_320, 6.87)
+		);
+
+		// the minute candles being pruned, the close of the hour candle spanning
+		// the time is read, dated later within its span
+		{
+			let mut batch = oracle.batch().unwrap();
+			for start in &[1_562_050_200, 1_562_050_320] {
+				batch
+					.delete_candle("USD2CNY", Resolution::Minute, Utc.timestamp(*start, 0))
+					.unwrap();
+			}
+			batch.commit().unwrap();
+		}
+		assert_eq!(
+			rate_at(oracle, 1_562_050_300, 300).unwrap(),
+			rate(1_562_050_320, 6.87)
+		);
+
+		// nothing before the first rate
+		match rate_at(oracle, 1_562_050_190, 300).unwrap_err().kind() {
+			ErrorKind::NotFound(_) => {}
+			kind => panic!("unexpected error {:?}", kind),
+		}
+		// the rates of the candles are too old
+		match rate_at(oracle, 1_562_057_000, 300).unwrap_err().kind() {
+			ErrorKind::Stale(_) => {}
+			kind => panic!("unexpected error {:?}", kind),
+		}
+	}
+
+	#[test]
+	fn twap_compacted() {
+		let mut db = TempBackend::new("gotts_oracle_twap_compacted");
+		let oracle = db.oracle();
+		// a rate a minute over two hours, the first hour of them compacted
+		let t0 = 1_562_050_200;
+		for i in 0..120 {
+			let value = if i < 60 { 6.0 } else { 7.0 };
+			save(oracle, rate(t0 + i * 60, value));
+		}
+		save(oracle, rate(t0 + 30, 6.0));
+		let kept = CandlesConfig {
+			minute_retention_days: 0,
+			hour_retention_days: 0,
+			day_retention_days: 0,
+		};
+		candles::compact(oracle, Utc.timestamp(t0 + 3600, 0), &kept).unwrap();
+		assert_eq!(
+			oracle
+				.range("USD2CNY", Utc.timestamp(0, 0), Utc.timestamp(t0 + 3599, 0))
+				.count(),
+			0
+		);
+
+		let twap = twap::twap_of(
+			&*oracle,
+			"USD2CNY",
+			Duration::hours(2),
+			Utc.timestamp(t0 + 7200, 0),
+			Duration::seconds(120),
+		)
+		.unwrap();
+		assert!(twap.gaps.is_empty());
+		assert_eq!(twap.covered_secs, 7200);
+		assert!((twap.rate - 6.5).abs() < 1e-9);
+		assert_eq!(twap.observations, 121);
+	}
+
+	#[test]
+	fn candles_range() {
+		let mut db = TempBackend::new("gotts_oracle_candles_range");
+		let oracle = db.oracle();
+		{
+			let mut batch = oracle.batch().unwrap();
+			for timestamp in &[1_562_050_200, 1_562_050_260, 1_562_050_320] {
+				let tick = rate(*timestamp, 6.87);
+				batch
+					.save_candle(&Candle::from_tick(&tick, Resolution::Minute))
+					.unwrap();
+				batch
+					.save_candle(&Candle::from_tick(&tick, Resolution::Hour))
+					.unwrap();
+			}
+			batch.commit().unwrap();
+		}
+
+		// both bounds are included
+		let starts = |from: i64, to: i64| -> Vec<i64> {
+			oracle
+				.candles_range(
+					"USD2CNY",
+					Resolution::Minute,
+					Utc.timestamp(from, 0),
+					Utc.timestamp(to, 0),
+				)
+				.map(|c| c.start.timestamp())
+				.collect()
+		};
+		assert_eq!(
+			starts(1_562_050_260, 1_562_050_320),
+			vec![1_562_050_260, 1_562_050_320]
+		);
+		assert_eq!(starts(1_562_050_201, 1_562_050_319), vec![1_562_050_260]);
+		assert_eq!(starts(0, 1_562_050_199), Vec::<i64>::new());
+		let hours: Vec<Candle> = oracle
+			.candles_range(
+				"USD2CNY",
+				Resolution::Hour,
+				Utc.timestamp(0, 0),
+				Utc.timestamp(1_562_053_800, 0),
+			)
+			.collect();
+		assert_eq!(hours.len(), 1);
+		assert_eq!(hours[0].start.timestamp(), 1_562_047_200);
+		assert!(oracle
+			.candles_range(
+				"EUR2USD",
+				Resolution::Minute,
+				Utc.timestamp(0, 0),
+				Utc.timestamp(1_562_053_800, 0),
+			)
+			.next()
+			.is_none());
+	}
+
+	#[test]
+	fn snapshot_restore() {
+		let root = std::env::temp_dir().join("gotts_oracle_snapshot_restore");
+		let _ = fs::remove_dir_all(&root);
+		let config = ServerConfig {
+			db_root: root.join("oracle").to_string_lossy().into_owned(),
+			..ServerConfig::default()
+		};
+		let snapshot = root.join("snapshot").to_string_lossy().into_owned();
+
+		let mut oracle = LMDBBackend::new(config.clone()).unwrap();
+		save(&mut oracle, rate(1_562_050_200, 6.87));
+		let size = oracle.snapshot(&snapshot).unwrap();
+		assert!(size > 0);
+		// a snapshot isn't written over another
+		assert!(oracle.snapshot(&snapshot).is_err());
+		save(&mut oracle, rate(1_562_050_260, 6.88));
+		drop(oracle);
+
+		let replaced = restore_snapshot(&config, &snapshot).unwrap().unwrap();
+		assert!(replaced.join(DATA_FILE).is_file());
+		let oracle = LMDBBackend::new(config.clone()).unwrap();
+		let rates: Vec<ExchangeRateResult> = oracle.iter().collect();
+		assert_eq!(rates, vec![rate(1_562_050_200, 6.87)]);
+		drop(oracle);
+
+		let missing = root.join("missing").to_string_lossy().into_owned();
+		assert!(restore_snapshot(&config, &missing).is_err());
+		let _ = fs::remove_dir_all(&root);
+	}
+}
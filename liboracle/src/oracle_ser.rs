@@ -21,6 +21,8 @@
 //! `serialize` or `deserialize` functions on them as appropriate.
 
 use crate::alphavantage::ExchangeRateResult;
+use crate::candles::{Candle, Resolution};
+use crate::sources::SourceReading;
 
 use byteorder::{BigEndian, ByteOrder, ReadBytesExt};
 use chrono::{DateTime, NaiveDateTime, Utc};
@@ -29,6 +31,12 @@ use std::io::{self, Read, Write};
 use std::marker;
 use std::{error, fmt};
 
+/// Version of the binary encodings of the values stored by the oracle. Every
+/// stored value is prefixed with the version it was encoded with, which the
+/// `Readable` implementations get from `Reader::encoding_version`, so that an
+/// encoding can evolve while the values already stored stay readable.
+pub const ENCODING_VERSION: u8 = 1;
+
 /// Possible errors deriving from serializing or deserializing.
 #[derive(Clone, Eq, PartialEq, Debug)]
 pub enum Error {
@@ -170,6 +178,8 @@ pub trait Writer {
 /// Implementations defined how different numbers and binary structures are
 /// read from an underlying stream or container (depending on implementation).
 pub trait Reader {
+	/// Version of the encoding being read
+	fn encoding_version(&self) -> u8;
 	/// Read a u8 from the underlying Read
 	fn read_u8(&mut self) -> Result<u8, Error>;
 	/// Read a u16 from the underlying Read
@@ -279,6 +289,12 @@ pub fn deserialize_default<T: Readable>(source: &mut dyn Read) -> Result<T, Erro
 	deserialize(source)
 }
 
+/// Deserializes a Readable encoded with the given encoding version.
+pub fn deserialize_version<T: Readable>(source: &mut dyn Read, version: u8) -> Result<T, Error> {
+	let mut reader = BinReader::with_version(source, version);
+	T::read(&mut reader)
+}
+
 /// Serializes a Writeable into any std::io::Write implementation.
 pub fn serialize<W: Writeable>(sink: &mut dyn Write, thing: &W) -> Result<(), Error> {
 	let mut writer = BinWriter::new(sink);
@@ -301,12 +317,19 @@ pub fn ser_vec<W: Writeable>(thing: &W) -> Result<Vec<u8>, Error> {
 /// Utility to read from a binary source
 pub struct BinReader<'a> {
 	source: &'a mut dyn Read,
+	version: u8,
 }
 
 impl<'a> BinReader<'a> {
-	/// Constructor for a new BinReader for the provided source and protocol version.
+	/// Constructor for a new BinReader for the provided source, in the current
+	/// encoding version.
 	pub fn new(source: &'a mut dyn Read) -> BinReader<'a> {
-		BinReader { source }
+		BinReader::with_version(source, ENCODING_VERSION)
+	}
+
+	/// Constructor for a new BinReader for the provided source and encoding version.
+	pub fn with_version(source: &'a mut dyn Read, version: u8) -> BinReader<'a> {
+		BinReader { source, version }
 	}
 }
 
@@ -317,6 +340,9 @@ fn map_io_err(err: io::Error) -> Error {
 /// Utility wrapper for an underlying byte Reader. Defines higher level methods
 /// to read numbers, byte vectors, hashes, etc.
 impl<'a> Reader for BinReader<'a> {
+	fn encoding_version(&self) -> u8 {
+		self.version
+	}
 	fn read_u8(&mut self) -> Result<u8, Error> {
 		self.source.read_u8().map_err(map_io_err)
 	}
@@ -399,6 +425,9 @@ impl<'a> StreamingReader<'a> {
 
 /// Note: We use read_fixed_bytes() here to ensure our "async" I/O behaves as expected.
 impl<'a> Reader for StreamingReader<'a> {
+	fn encoding_version(&self) -> u8 {
+		ENCODING_VERSION
+	}
 	fn read_u8(&mut self) -> Result<u8, Error> {
 		let buf = self.read_fixed_bytes(1)?;
 		Ok(buf[0])
@@ -492,6 +521,78 @@ impl Writeable for ExchangeRateResult {
 	}
 }
 
+impl Readable for SourceReading {
+	fn read(reader: &mut dyn Reader) -> Result<SourceReading, Error> {
+		let data = reader.read_bytes_len_prefix()?;
+		let source = std::str::from_utf8(&data)
+			.map_err(|_| Error::CorruptedData)?
+			.to_string();
+		let rate = ExchangeRateResult::read(reader)?;
+		Ok(SourceReading { source, rate })
+	}
+}
+
+impl Writeable for SourceReading {
+	fn write<W: Writer>(&self, writer: &mut W) -> Result<(), Error> {
+		writer.write_bytes(&self.source.as_bytes().to_vec())?;
+		self.rate.write(writer)
+	}
+}
+
+impl Readable for Candle {
+	fn read(reader: &mut dyn Reader) -> Result<Candle, Error> {
+		let data = reader.read_bytes_len_prefix()?;
+		let from = std::str::from_utf8(&data)
+			.map_err(|_| Error::CorruptedData)?
+			.to_string();
+		let data = reader.read_bytes_len_prefix()?;
+		let to = std::str::from_utf8(&data)
+			.map_err(|_| Error::CorruptedData)?
+			.to_string();
+		let resolution = Resolution::from_u8(reader.read_u8()?).ok_or(Error::CorruptedData)?;
+		let start = reader.read_i64()?;
+		let open = reader.read_f64()?;
+		let high = reader.read_f64()?;
+		let low = reader.read_f64()?;
+		let close = reader.read_f64()?;
+		let ticks = reader.read_u64()?;
+		let first_tick = reader.read_i64()?;
+		let last_tick = reader.read_i64()?;
+		Ok(Candle {
+			from,
+			to,
+			resolution,
+			start: DateTime::<Utc>::from_utc(NaiveDateTime::from_timestamp(start, 0), Utc),
+			open,
+			high,
+			low,
+			close,
+			ticks,
+			first_tick: DateTime::<Utc>::from_utc(
+				NaiveDateTime::from_timestamp(first_tick, 0),
+				Utc,
+			),
+			last_tick: DateTime::<Utc>::from_utc(NaiveDateTime::from_timestamp(last_tick, 0), Utc),
+		})
+	}
+}
+
+impl Writeable for Candle {
+	fn write<W: Writer>(&self, writer: &mut W) -> Result<(), Error> {
+		writer.write_bytes(&self.from.as_bytes().to_vec())?;
+		writer.write_bytes(&self.to.as_bytes().to_vec())?;
+		writer.write_u8(self.resolution.to_u8())?;
+		writer.write_i64(self.start.timestamp())?;
+		writer.write_f64(self.open)?;
+		writer.write_f64(self.high)?;
+		writer.write_f64(self.low)?;
+		writer.write_f64(self.close)?;
+		writer.write_u64(self.ticks)?;
+		writer.write_i64(self.first_tick.timestamp())?;
+		writer.write_i64(self.last_tick.timestamp())
+	}
+}
+
 /// Utility wrapper for an underlying byte Writer. Defines higher level methods
 /// to write numbers, byte vectors, hashes, etc.
 pub struct BinWriter<'a> {
@@ -17,7 +17,6 @@
 
 use failure::Fail;
 use std::fs;
-use std::marker;
 use std::sync::Arc;
 
 use lmdb_zero as lmdb;
@@ -25,7 +24,8 @@ use lmdb_zero::traits::CreateCursor;
 use lmdb_zero::LmdbResultExt;
 
 use super::oracle_ser as ser;
-use gotts_oracle_util::{RwLock, RwLockReadGuard};
+use super::types::DbStats;
+use gotts_oracle_util::{to_hex, RwLock, RwLockReadGuard};
 
 /// number of bytes to grow the database by when needed
 pub const ALLOC_CHUNK_SIZE: usize = 134_217_728; //128 MB
@@ -45,7 +45,7 @@ pub enum Error {
 	#[fail(display = "LMDB error")]
 	LmdbErr(lmdb::error::Error),
 	/// Wraps a serialization error for Writeable or Readable
-	#[fail(display = "Serialization Error")]
+	#[fail(display = "Serialization Error: {}", _0)]
 	SerErr(String),
 }
 
@@ -64,14 +64,56 @@ pub fn option_to_not_found<T>(res: Result<Option<T>, Error>, field_name: &str) -
 	}
 }
 
+/// Encodes a value to store, prefixed with the version of its encoding
+pub fn encode<W: ser::Writeable>(value: &W) -> Result<Vec<u8>, Error> {
+	let mut data = vec![ser::ENCODING_VERSION];
+	ser::serialize(&mut data, value).map_err(|e| Error::SerErr(format!("{}", e)))?;
+	Ok(data)
+}
+
+/// Decodes a stored value, prefixed with the version of its encoding
+pub fn decode<T: ser::Readable>(value: &[u8]) -> Result<T, Error> {
+	match value.split_first() {
+		Some((&version, mut data)) if version > 0 && version <= ser::ENCODING_VERSION => {
+			ser::deserialize_version(&mut data, version)
+				.map_err(|e| Error::SerErr(format!("{}", e)))
+		}
+		Some((&version, _)) => Err(Error::SerErr(format!(
+			"unsupported encoding version {}",
+			version
+		))),
+		None => Err(Error::SerErr("empty value".to_owned())),
+	}
+}
+
 /// LMDB-backed store facilitating data access and serialization. All writes
 /// are done through a Batch abstraction providing atomicity.
 pub struct Store {
 	env: Arc<lmdb::Environment>,
-	db: RwLock<Option<Arc<lmdb::Database<'static>>>>,
+	db: Arc<RwLock<Option<Arc<lmdb::Database<'static>>>>>,
 	name: String,
 }
 
+/// Writes the snapshots of a store without borrowing it, so that the store
+/// stays in use, by whoever owns it, while a snapshot is written.
+#[derive(Clone)]
+pub struct StoreSnapshotter {
+	env: Arc<lmdb::Environment>,
+	db: Arc<RwLock<Option<Arc<lmdb::Database<'static>>>>>,
+}
+
+impl StoreSnapshotter {
+	/// Writes a consistent copy of the environment into the `path` directory,
+	/// which must exist and be empty. The copy is done within a read
+	/// transaction, and compacted, leaving out the free pages. The database
+	/// isn't resized meanwhile.
+	pub fn snapshot(&self, path: &str) -> Result<(), Error> {
+		let _db = self.db.read();
+		self.env.copy(path, lmdb::copy::COMPACT)?;
+		Ok(())
+	}
+}
+
 impl Store {
 	/// Create a new LMDB env under the provided directory.
 	/// By default creates an environment named "lmdb".
@@ -111,7 +153,7 @@ impl Store {
 		);
 		let res = Store {
 			env: Arc::new(env),
-			db: RwLock::new(None),
+			db: Arc::new(RwLock::new(None)),
 			name: db_name,
 		};
 
@@ -205,6 +247,32 @@ impl Store {
 		Ok(())
 	}
 
+	/// The snapshotter of the store, which can be used without it
+	pub fn snapshotter(&self) -> StoreSnapshotter {
+		StoreSnapshotter {
+			env: self.env.clone(),
+			db: self.db.clone(),
+		}
+	}
+
+	/// Statistics of the database, but for the schema version which is left to
+	/// the caller
+	pub fn stats(&self) -> Result<DbStats, Error> {
+		let db = self.db.read();
+		let txn = lmdb::ReadTransaction::new(self.env.clone())?;
+		let stat = txn.db_stat(&db.as_ref().unwrap())?;
+		let env_info = self.env.info()?;
+		let page_size = self.env.stat()?.psize;
+		Ok(DbStats {
+			schema_version: 0,
+			entries: stat.entries,
+			depth: stat.depth,
+			page_size,
+			used_size: page_size as usize * env_info.last_pgno,
+			map_size: env_info.mapsize,
+		})
+	}
+
 	/// Gets a value from the db, provided its key
 	#[allow(dead_code)]
 	pub fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>, Error> {
@@ -234,11 +302,11 @@ impl Store {
 	) -> Result<Option<T>, Error> {
 		let res: lmdb::error::Result<&[u8]> = access.get(&db.as_ref().unwrap(), key);
 		match res.to_opt() {
-			Ok(Some(mut res)) => match ser::deserialize(&mut res) {
+			Ok(Some(res)) => match decode(res) {
 				Ok(res) => Ok(Some(res)),
 				Err(e) => {
 					debug!("store::get_ser failed. {}", e.to_string());
-					Err(Error::SerErr(format!("{}", e)))
+					Err(e)
 				}
 			},
 			Ok(None) => Ok(None),
@@ -270,7 +338,76 @@ impl Store {
 			cursor,
 			seek: false,
 			prefix: from.to_vec(),
-			_marker: marker::PhantomData,
+			start: from.to_vec(),
+			end: None,
+			reverse: false,
+			decode: decode::<T>,
+		})
+	}
+
+	/// Produces an iterator of (key, value) pairs over all the keys starting with
+	/// the provided prefix, or over the whole db for an empty prefix, the values
+	/// left as they're stored, undecoded.
+	pub fn iter_raw(&self, prefix: &[u8]) -> Result<SerIterator<Vec<u8>>, Error> {
+		let db = self.db.read();
+		let tx = Arc::new(lmdb::ReadTransaction::new(self.env.clone())?);
+		let cursor = Arc::new(tx.cursor(db.as_ref().unwrap().clone()).unwrap());
+		Ok(SerIterator {
+			tx,
+			cursor,
+			seek: false,
+			prefix: prefix.to_vec(),
+			start: prefix.to_vec(),
+			end: None,
+			reverse: false,
+			decode: |value| Ok(value.to_vec()),
+		})
+	}
+
+	/// Produces an iterator of (key, value) pairs, where values are `Readable` types
+	/// moving forward from the `from` key up to the `to` key included, over the keys
+	/// sharing the provided prefix. The cursor directly seeks to the `from` key.
+	pub fn iter_range<T: ser::Readable>(
+		&self,
+		prefix: &[u8],
+		from: &[u8],
+		to: &[u8],
+	) -> Result<SerIterator<T>, Error> {
+		let db = self.db.read();
+		let tx = Arc::new(lmdb::ReadTransaction::new(self.env.clone())?);
+		let cursor = Arc::new(tx.cursor(db.as_ref().unwrap().clone()).unwrap());
+		Ok(SerIterator {
+			tx,
+			cursor,
+			seek: false,
+			prefix: prefix.to_vec(),
+			start: from.to_vec(),
+			end: Some(to.to_vec()),
+			reverse: false,
+			decode: decode::<T>,
+		})
+	}
+
+	/// Produces an iterator of (key, value) pairs, where values are `Readable` types
+	/// moving backward from the last key lower than or equal to the `from` key, over
+	/// the keys sharing the provided prefix.
+	pub fn iter_rev<T: ser::Readable>(
+		&self,
+		prefix: &[u8],
+		from: &[u8],
+	) -> Result<SerIterator<T>, Error> {
+		let db = self.db.read();
+		let tx = Arc::new(lmdb::ReadTransaction::new(self.env.clone())?);
+		let cursor = Arc::new(tx.cursor(db.as_ref().unwrap().clone()).unwrap());
+		Ok(SerIterator {
+			tx,
+			cursor,
+			seek: false,
+			prefix: prefix.to_vec(),
+			start: from.to_vec(),
+			end: None,
+			reverse: true,
+			decode: decode::<T>,
 		})
 	}
 
@@ -307,11 +444,7 @@ impl<'a> Batch<'a> {
 	/// Writes a single key and its `Writeable` value to the db. Encapsulates
 	/// serialization.
 	pub fn put_ser<W: ser::Writeable>(&self, key: &[u8], value: &W) -> Result<(), Error> {
-		let ser_value = ser::ser_vec(value);
-		match ser_value {
-			Ok(data) => self.put(key, &data),
-			Err(err) => Err(Error::SerErr(format!("{}", err))),
-		}
+		self.put(key, &encode(value)?)
 	}
 
 	/// gets a value from the db, provided its key
@@ -332,6 +465,16 @@ impl<'a> Batch<'a> {
 		self.store.iter(from)
 	}
 
+	/// Produces an iterator of `Readable` types moving backward from the
+	/// provided key.
+	pub fn iter_rev<T: ser::Readable>(
+		&self,
+		prefix: &[u8],
+		from: &[u8],
+	) -> Result<SerIterator<T>, Error> {
+		self.store.iter_rev(prefix, from)
+	}
+
 	/// Gets a `Readable` value from the db, provided its key, taking the
 	/// content of the current batch into account.
 	pub fn get_ser<T: ser::Readable>(&self, key: &[u8]) -> Result<Option<T>, Error> {
@@ -365,55 +508,142 @@ impl<'a> Batch<'a> {
 }
 
 /// An iterator thad produces Readable instances back. Wraps the lower level
-/// DBIterator and deserializes the returned values.
-pub struct SerIterator<T>
-where
-	T: ser::Readable,
-{
+/// DBIterator and deserializes the returned values. The values which can't be
+/// deserialized are skipped, and logged.
+pub struct SerIterator<T> {
 	tx: Arc<lmdb::ReadTransaction<'static>>,
 	cursor: Arc<lmdb::Cursor<'static, 'static>>,
 	seek: bool,
 	prefix: Vec<u8>,
-	_marker: marker::PhantomData<T>,
+	start: Vec<u8>,
+	end: Option<Vec<u8>>,
+	reverse: bool,
+	decode: fn(&[u8]) -> Result<T, Error>,
 }
 
-impl<T> Iterator for SerIterator<T>
-where
-	T: ser::Readable,
-{
+impl<T> Iterator for SerIterator<T> {
 	type Item = (Vec<u8>, T);
 
 	fn next(&mut self) -> Option<(Vec<u8>, T)> {
 		let access = self.tx.access();
-		let kv = if self.seek {
-			Arc::get_mut(&mut self.cursor).unwrap().next(&access)
-		} else {
-			self.seek = true;
-			Arc::get_mut(&mut self.cursor)
-				.unwrap()
-				.seek_range_k(&access, &self.prefix[..])
-		};
-		match kv {
-			Ok((k, v)) => self.deser_if_prefix_match(k, v),
-			Err(_) => None,
+		loop {
+			let cursor = Arc::get_mut(&mut self.cursor).unwrap();
+			let kv = if self.seek {
+				if self.reverse {
+					cursor.prev(&access)
+				} else {
+					cursor.next(&access)
+				}
+			} else if self.start.is_empty() && !self.reverse {
+				self.seek = true;
+				cursor.first(&access)
+			} else {
+				self.seek = true;
+				let kv = cursor.seek_range_k(&access, &self.start[..]);
+				if self.reverse {
+					// positioned on the first key greater than or equal to the start
+					// one, step back unless it's an exact match, or from the very
+					// last key if there's none
+					match kv {
+						Ok((k, v)) if k == &self.start[..] => Ok((k, v)),
+						Ok(_) => cursor.prev(&access),
+						Err(_) => cursor.last(&access),
+					}
+				} else {
+					kv
+				}
+			};
+			let (key, value): (&[u8], &[u8]) = match kv {
+				Ok((k, v)) if self.prefix_match(k) => (k, v),
+				_ => return None,
+			};
+			match (self.decode)(value) {
+				Ok(value) => return Some((key.to_vec(), value)),
+				Err(e) => error!(
+					"skipping the undecodable value of key {}: {}",
+					to_hex(key.to_vec()),
+					e
+				),
+			}
 		}
 	}
 }
 
-impl<T> SerIterator<T>
-where
-	T: ser::Readable,
-{
-	fn deser_if_prefix_match(&self, key: &[u8], value: &[u8]) -> Option<(Vec<u8>, T)> {
+impl<T> SerIterator<T> {
+	/// Whether the key is within the range and shares the prefix
+	fn prefix_match(&self, key: &[u8]) -> bool {
 		let plen = self.prefix.len();
-		if plen == 0 || (key.len() >= plen && key[0..plen] == self.prefix[..]) {
-			if let Ok(value) = ser::deserialize(&mut &value[..]) {
-				Some((key.to_vec(), value))
-			} else {
-				None
+		if let Some(ref end) = self.end {
+			if key > &end[..] {
+				return false;
 			}
-		} else {
-			None
 		}
+		plen == 0 || (key.len() >= plen && key[0..plen] == self.prefix[..])
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::alphavantage::ExchangeRateResult;
+	use chrono::{TimeZone, Utc};
+
+	#[test]
+	fn versioned_encoding() {
+		let rate = ExchangeRateResult {
+			from: "USD".to_owned(),
+			to: "CNY".to_owned(),
+			rate: 6.8746,
+			date: Utc.timestamp(1_562_050_200, 0),
+		};
+		let data = encode(&rate).unwrap();
+		assert_eq!(data[0], ser::ENCODING_VERSION);
+		assert_eq!(data[1..].to_vec(), ser::ser_vec(&rate).unwrap());
+		assert_eq!(decode::<ExchangeRateResult>(&data).unwrap(), rate);
+
+		// the legacy values, without version, start with a zero byte
+		let legacy = ser::ser_vec(&rate).unwrap();
+		assert!(decode::<ExchangeRateResult>(&legacy).is_err());
+		let mut unknown = data.clone();
+		unknown[0] = ser::ENCODING_VERSION + 1;
+		assert!(decode::<ExchangeRateResult>(&unknown).is_err());
+		assert!(decode::<ExchangeRateResult>(&[]).is_err());
+	}
+
+	#[test]
+	fn reverse_seek() {
+		let root = std::env::temp_dir().join("gotts_oracle_reverse_seek");
+		let _ = fs::remove_dir_all(&root);
+		let store = Store::new(root.to_str().unwrap(), None, None, None).unwrap();
+		let rate = |rate: f64| ExchangeRateResult {
+			from: "USD".to_owned(),
+			to: "CNY".to_owned(),
+			rate,
+			date: Utc.timestamp(1_562_050_200, 0),
+		};
+		let batch = store.batch().unwrap();
+		batch.put_ser(b"a1", &rate(1.0)).unwrap();
+		batch.put_ser(b"a3", &rate(3.0)).unwrap();
+		batch.put_ser(b"b1", &rate(4.0)).unwrap();
+		batch.commit().unwrap();
+
+		let rev = |prefix: &[u8], from: &[u8]| -> Vec<f64> {
+			store
+				.iter_rev::<ExchangeRateResult>(prefix, from)
+				.unwrap()
+				.map(|(_, r)| r.rate)
+				.collect()
+		};
+		// from an exact match, from between two keys, and from before the first
+		assert_eq!(rev(b"a", b"a3"), vec![3.0, 1.0]);
+		assert_eq!(rev(b"a", b"a2"), vec![1.0]);
+		assert!(rev(b"a", b"a0").is_empty());
+		// from past the last key of the prefix, and past the last key of the db
+		assert_eq!(rev(b"a", b"a9"), vec![3.0, 1.0]);
+		assert_eq!(rev(b"b", b"b9"), vec![4.0]);
+		assert!(rev(b"c", b"c9").is_empty());
+
+		drop(store);
+		let _ = fs::remove_dir_all(&root);
 	}
 }
@@ -0,0 +1,182 @@
+// Copyright 2019 The Gotts Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Versioning of the database schema. The schema version is recorded in the
+//! store, and the migrations from the older versions are run when opening
+//! the database, one version after the other.
+//!
+//! Changing the encoding of a stored value only takes a new encoding version,
+//! see `oracle_ser::ENCODING_VERSION`. A migration is needed when the values
+//! already stored have to be rewritten, or their keys changed.
+
+use byteorder::{BigEndian, ByteOrder};
+
+use crate::error::{Error, ErrorKind};
+use crate::oracle_store::{Batch, Store};
+
+/// Version of the database schema, the databases predating the versioning
+/// being at version 0
+pub const SCHEMA_VERSION: u32 = 1;
+
+/// Key prefix of the schema records
+pub const SCHEMA_PREFIX: u8 = 'v' as u8;
+/// Key of the schema version record, a big endian u32
+const SCHEMA_VERSION_KEY: &[u8] = b"v:schema";
+
+/// A migration of the database from a schema version to the next one
+struct Migration {
+	/// Schema version migrated from
+	from: u32,
+	/// What the migration changes
+	description: &'static str,
+	/// Rewrites the entries within the batch, returns the number rewritten
+	run: fn(&Store, &Batch<'_>) -> Result<usize, Error>,
+}
+
+/// The migrations, ordered by schema version
+const MIGRATIONS: &[Migration] = &[Migration {
+	from: 0,
+	description: "prefix the values with the version of their encoding",
+	run: prefix_encoding_version,
+}];
+
+/// The schema version recorded in the store
+pub fn schema_version(store: &Store) -> Result<u32, Error> {
+	match store.get(SCHEMA_VERSION_KEY)? {
+		Some(ref data) if data.len() == 4 => Ok(BigEndian::read_u32(data)),
+		Some(_) => Err(ErrorKind::Backend("corrupted schema version record".to_owned()).into()),
+		None => Ok(0),
+	}
+}
+
+fn put_schema_version(batch: &Batch<'_>, version: u32) -> Result<(), Error> {
+	let mut data = [0; 4];
+	BigEndian::write_u32(&mut data, version);
+	batch.put(SCHEMA_VERSION_KEY, &data)?;
+	Ok(())
+}
+
+/// Migrate the database to the current schema version, each migration being
+/// committed along with the schema version it migrates to. A new database is
+/// created at the current version. Returns the schema version migrated from.
+pub fn migrate(store: &Store) -> Result<u32, Error> {
+	let version = schema_version(store)?;
+	if version > SCHEMA_VERSION {
+		return Err(ErrorKind::Backend(format!(
+			"database schema version {} is newer than the supported version {}",
+			version, SCHEMA_VERSION
+		))
+		.into());
+	}
+	if version == 0 && store.iter_raw(&[])?.next().is_none() {
+		let batch = store.batch()?;
+		put_schema_version(&batch, SCHEMA_VERSION)?;
+		batch.commit()?;
+		return Ok(SCHEMA_VERSION);
+	}
+
+	for migration in MIGRATIONS.iter().filter(|m| m.from >= version) {
+		info!(
+			"Migrating the database from schema version {}: {}",
+			migration.from, migration.description
+		);
+		let batch = store.batch()?;
+		let migrated = (migration.run)(store, &batch)?;
+		put_schema_version(&batch, migration.from + 1)?;
+		batch.commit()?;
+		info!(
+			"Database migrated to schema version {}, {} entries rewritten",
+			migration.from + 1,
+			migrated
+		);
+	}
+	Ok(version)
+}
+
+/// Version 0 to 1: the values get prefixed with the version of their encoding,
+/// the encodings are unchanged.
+fn prefix_encoding_version(store: &Store, batch: &Batch<'_>) -> Result<usize, Error> {
+	let entries: Vec<(Vec<u8>, Vec<u8>)> = store
+		.iter_raw(&[])?
+		.filter(|(key, _)| key.first() != Some(&SCHEMA_PREFIX))
+		.collect();
+	for (key, value) in entries.iter() {
+		let mut versioned = Vec::with_capacity(value.len() + 1);
+		versioned.push(1);
+		versioned.extend_from_slice(value);
+		batch.put(key, &versioned)?;
+	}
+	Ok(entries.len())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::alphavantage::ExchangeRateResult;
+	use crate::oracle_ser as ser;
+	use crate::oracle_store::decode;
+	use chrono::{TimeZone, Utc};
+	use std::{fs, path};
+
+	fn temp_store(name: &str) -> (path::PathBuf, Store) {
+		let root = std::env::temp_dir().join(format!("gotts_oracle_schema_{}", name));
+		let _ = fs::remove_dir_all(&root);
+		let store = Store::new(root.to_str().unwrap(), None, None, None).unwrap();
+		(root, store)
+	}
+
+	#[test]
+	fn migrate_from_v0() {
+		let (root, store) = temp_store("v0");
+		let rate = ExchangeRateResult {
+			from: "USD".to_owned(),
+			to: "CNY".to_owned(),
+			rate: 6.8746,
+			date: Utc.timestamp(1_562_050_200, 0),
+		};
+		let legacy = ser::ser_vec(&rate).unwrap();
+		let batch = store.batch().unwrap();
+		batch.put(b"e:USD2CNY", &legacy).unwrap();
+		batch.commit().unwrap();
+		assert_eq!(schema_version(&store).unwrap(), 0);
+
+		assert_eq!(migrate(&store).unwrap(), 0);
+		assert_eq!(schema_version(&store).unwrap(), SCHEMA_VERSION);
+		let value = store.get(b"e:USD2CNY").unwrap().unwrap();
+		assert_eq!(value[0], 1);
+		assert_eq!(value[1..].to_vec(), legacy);
+		assert_eq!(decode::<ExchangeRateResult>(&value).unwrap(), rate);
+
+		// a migrated database isn't migrated again
+		assert_eq!(migrate(&store).unwrap(), SCHEMA_VERSION);
+		assert_eq!(store.get(b"e:USD2CNY").unwrap().unwrap(), value);
+		drop(store);
+		let _ = fs::remove_dir_all(&root);
+	}
+
+	#[test]
+	fn migrate_new_and_newer() {
+		let (root, store) = temp_store("versions");
+		assert_eq!(migrate(&store).unwrap(), SCHEMA_VERSION);
+		assert_eq!(schema_version(&store).unwrap(), SCHEMA_VERSION);
+
+		let batch = store.batch().unwrap();
+		put_schema_version(&batch, SCHEMA_VERSION + 1).unwrap();
+		batch.commit().unwrap();
+		assert!(migrate(&store).is_err());
+		assert_eq!(schema_version(&store).unwrap(), SCHEMA_VERSION + 1);
+		drop(store);
+		let _ = fs::remove_dir_all(&root);
+	}
+}
@@ -0,0 +1,297 @@
+// Copyright 2019 The Gotts Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Time-weighted average prices over the stored history. Each observation
+//! holds until the next one, for at most a max gap: the time beyond is
+//! reported as a gap and left out of the average. The forex quotes carry no
+//! traded volume, so that there's no volume weighting.
+//!
+//! The raw rates are compacted into candles after about an hour, the older
+//! part of a window is read from the minute candles, each of them standing
+//! for its first and last tick.
+
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::alphavantage::ExchangeRateResult;
+use crate::candles::{Candle, Resolution};
+use crate::error::{Error, ErrorKind};
+use crate::types::OracleBackend;
+
+/// Default max time an observation holds, in seconds
+pub const DEFAULT_MAX_GAP_SECS: i64 = 300;
+
+/// Longest window, in seconds, the minute candles the compacted part of a
+/// window is read from being kept for a week by default. Beyond their
+/// configured retention, the compacted part is reported as a gap.
+pub const MAX_WINDOW_SECS: i64 = 7 * 24 * 3600;
+
+/// A period without any fresh observation
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Gap {
+	/// Start of the gap
+	pub start: DateTime<Utc>,
+	/// End of the gap
+	pub end: DateTime<Utc>,
+}
+
+/// A time-weighted average price
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Twap {
+	/// The currency the rate is quoted from
+	pub from: String,
+	/// The currency the rate is quoted to
+	pub to: String,
+	/// The time-weighted average rate
+	pub rate: f64,
+	/// Start of the window
+	pub start: DateTime<Utc>,
+	/// End of the window
+	pub end: DateTime<Utc>,
+	/// Number of observations the average is computed over
+	pub observations: usize,
+	/// Seconds of the window covered by the observations
+	pub covered_secs: i64,
+	/// Periods of the window not covered by any observation
+	pub gaps: Vec<Gap>,
+}
+
+/// Parse a window given as a number followed by a unit, "s", "m", "h" or
+/// "d", i.e. "15m". Returns `None` beyond `MAX_WINDOW_SECS`.
+pub fn parse_window(window: &str) -> Option<Duration> {
+	let window = window.trim();
+	if window.len() < 2 || !window.is_char_boundary(window.len() - 1) {
+		return None;
+	}
+	let (value, unit) = window.split_at(window.len() - 1);
+	let value: i64 = value.parse().ok()?;
+	if value <= 0 {
+		return None;
+	}
+	let unit_secs = match unit {
+		"s" => 1,
+		"m" => 60,
+		"h" => 3600,
+		"d" => 24 * 3600,
+		_ => return None,
+	};
+	match value.checked_mul(unit_secs) {
+		Some(secs) if secs <= MAX_WINDOW_SECS => Some(Duration::seconds(secs)),
+		_ => None,
+	}
+}
+
+/// Time-weighted average of the observations, ordered by date, over the
+/// window from `start` to `end`. The latest observation before the start of
+/// the window, if any, should be included, it holds until the first one in
+/// the window. Returns `None` if the observations don't cover any of the
+/// window.
+pub fn twap(
+	observations: &[ExchangeRateResult],
+	start: DateTime<Utc>,
+	end: DateTime<Utc>,
+	max_gap: Duration,
+) -> Option<Twap> {
+	let mut weighted_sum = 0.0;
+	let mut covered = Duration::zero();
+	let mut used = 0;
+	let mut gaps: Vec<Gap> = vec![];
+	let mut add_gap = |gap_start: DateTime<Utc>, gap_end: DateTime<Utc>| {
+		if gap_end > gap_start {
+			gaps.push(Gap {
+				start: gap_start,
+				end: gap_end,
+			});
+		}
+	};
+
+	match observations.first() {
+		None => add_gap(start, end),
+		Some(first) if first.date > start => add_gap(start, first.date.min(end)),
+		_ => {}
+	}
+
+	for (i, observation) in observations.iter().enumerate() {
+		let next = observations.get(i + 1).map_or(end, |n| n.date.min(end));
+		let seg_start = observation.date.max(start);
+		let seg_end = next.max(seg_start);
+		if seg_end <= seg_start || observation.date >= end {
+			continue;
+		}
+		let held_until = seg_end.min(observation.date + max_gap);
+		if held_until > seg_start {
+			let held = held_until - seg_start;
+			weighted_sum += observation.rate * held.num_milliseconds() as f64;
+			covered = covered + held;
+			used += 1;
+		}
+		add_gap(held_until.max(seg_start), seg_end);
+	}
+
+	if covered <= Duration::zero() {
+		return None;
+	}
+	let first = &observations[0];
+	Some(Twap {
+		from: first.from.clone(),
+		to: first.to.clone(),
+		rate: weighted_sum / covered.num_milliseconds() as f64,
+		start,
+		end,
+		observations: used,
+		covered_secs: covered.num_seconds(),
+		gaps,
+	})
+}
+
+/// The observations a candle stands for, its first tick then its last one
+fn candle_observations(candle: &Candle) -> Vec<ExchangeRateResult> {
+	let observation = |rate: f64, date: DateTime<Utc>| ExchangeRateResult {
+		from: candle.from.clone(),
+		to: candle.to.clone(),
+		rate,
+		date,
+	};
+	let mut observations = vec![observation(candle.open, candle.first_tick)];
+	if candle.last_tick > candle.first_tick {
+		observations.push(observation(candle.close, candle.last_tick));
+	}
+	observations
+}
+
+/// Time-weighted average price of a pair id over the `window` ending at
+/// `end`, computed from the stored history: the raw rates, and the minute
+/// candles before the first of them.
+pub fn twap_of<T: ?Sized>(
+	oracle: &T,
+	id: &str,
+	window: Duration,
+	end: DateTime<Utc>,
+	max_gap: Duration,
+) -> Result<Twap, Error>
+where
+	T: OracleBackend,
+{
+	let start = end - window;
+	let rates: Vec<ExchangeRateResult> = oracle.range(id, start, end).collect();
+	let compacted_end = rates.first().map_or(end, |r| r.date);
+
+	// the candles from the one holding at the start of the window, up to the
+	// raw rates
+	let mut observations: Vec<ExchangeRateResult> = vec![];
+	if compacted_end > start {
+		let first = oracle
+			.candle_at_or_before(id, Resolution::Minute, start - Duration::minutes(1))?
+			.map_or(start, |c| c.start);
+		for candle in oracle.candles_range(id, Resolution::Minute, first, compacted_end) {
+			observations.extend(candle_observations(&candle));
+		}
+		observations.retain(|o| o.date < compacted_end);
+	}
+	// the raw rate holding at the start of the window, then the ones after
+	if let Some(before) = oracle.at_or_before(id, start)? {
+		observations.push(before);
+	}
+	observations.extend(rates.into_iter().filter(|r| r.date > start));
+	observations.sort_by_key(|o| o.date);
+	observations.dedup_by_key(|o| o.date);
+
+	twap(&observations, start, end, max_gap).ok_or_else(|| {
+		ErrorKind::NotFound(format!(
+			"no {} observation between {} and {}",
+			id, start, end
+		))
+		.into()
+	})
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use chrono::TimeZone;
+
+	fn observation(rate: f64, timestamp: i64) -> ExchangeRateResult {
+		ExchangeRateResult {
+			from: "USD".to_owned(),
+			to: "CNY".to_owned(),
+			rate,
+			date: Utc.timestamp(timestamp, 0),
+		}
+	}
+
+	#[test]
+	fn window_parsing() {
+		assert_eq!(parse_window("15m"), Some(Duration::minutes(15)));
+		assert_eq!(parse_window("1h"), Some(Duration::hours(1)));
+		assert_eq!(parse_window("30s"), Some(Duration::seconds(30)));
+		assert_eq!(parse_window("2d"), Some(Duration::days(2)));
+		assert_eq!(parse_window("0m"), None);
+		assert_eq!(parse_window("15"), None);
+		assert_eq!(parse_window("m"), None);
+		assert_eq!(parse_window("7d"), Some(Duration::days(7)));
+		assert_eq!(parse_window("8d"), None);
+		assert_eq!(parse_window("999999999999d"), None);
+		assert_eq!(parse_window("9223372036854775807s"), None);
+	}
+
+	#[test]
+	fn time_weighted() {
+		let start = Utc.timestamp(1_000, 0);
+		let end = Utc.timestamp(1_600, 0);
+		// 6.0 holds from before the window for 100s, 7.0 for 200s, 8.0 for 300s
+		let observations = vec![
+			observation(6.0, 900),
+			observation(7.0, 1_100),
+			observation(8.0, 1_300),
+		];
+		let twap = twap(&observations, start, end, Duration::seconds(600)).unwrap();
+		assert!((twap.rate - (6.0 * 100.0 + 7.0 * 200.0 + 8.0 * 300.0) / 600.0).abs() < 1e-9);
+		assert_eq!(twap.observations, 3);
+		assert_eq!(twap.covered_secs, 600);
+		assert!(twap.gaps.is_empty());
+	}
+
+	#[test]
+	fn gaps() {
+		let start = Utc.timestamp(1_000, 0);
+		let end = Utc.timestamp(2_000, 0);
+		// nothing before 1100, then 7.0 only holds for 60s and 8.0 till the end
+		let observations = vec![observation(7.0, 1_100), observation(8.0, 1_500)];
+		let twap = twap(&observations, start, end, Duration::seconds(60)).unwrap();
+		assert_eq!(
+			twap.gaps,
+			vec![
+				Gap {
+					start,
+					end: Utc.timestamp(1_100, 0)
+				},
+				Gap {
+					start: Utc.timestamp(1_160, 0),
+					end: Utc.timestamp(1_500, 0)
+				},
+				Gap {
+					start: Utc.timestamp(1_560, 0),
+					end
+				},
+			]
+		);
+		assert_eq!(twap.covered_secs, 120);
+		assert!((twap.rate - 7.5).abs() < 1e-9);
+
+		// no observation at all in the window
+		let stale = vec![observation(7.0, 100)];
+		assert!(super::twap(&stale, start, end, Duration::seconds(60)).is_none());
+		assert!(super::twap(&[], start, end, Duration::seconds(60)).is_none());
+	}
+}
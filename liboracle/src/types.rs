@@ -16,10 +16,13 @@
 //! Types and traits that should be provided by a wallet
 //! implementation
 
-use chrono::{DateTime, Utc};
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
 
-use super::error::Error;
+use super::candles::{Candle, Resolution};
+use super::error::{Error, ErrorKind};
 use crate::alphavantage::ExchangeRateResult;
+use crate::sources::{AggregatedRate, SourceReading};
 
 /// Combined trait to allow dynamic oracle dispatch
 pub trait OracleInst: OracleBackend + Send + Sync + 'static {}
@@ -35,11 +38,181 @@ pub trait OracleBackend {
 	/// Iterate over all local exchange rate data stored by the backend with same id
 	fn iter_id<'a>(&'a self, id: &str) -> Box<dyn Iterator<Item = ExchangeRateResult> + 'a>;
 
-	/// Get self owned output data by id
+	/// Get the latest exchange rate of a pair id, or a not found error
 	fn get(&self, id: &str) -> Result<ExchangeRateResult, Error>;
 
+	/// Ids of the stored pairs starting with the given prefix, i.e. "USD", or all
+	/// of them for an empty prefix
+	fn ids(&self, prefix: &str) -> Vec<String>;
+
+	/// Iterate over the exchange rates of a pair id dated from `from` to `to` included,
+	/// ordered by date
+	fn range<'a>(
+		&'a self,
+		id: &str,
+		from: DateTime<Utc>,
+		to: DateTime<Utc>,
+	) -> Box<dyn Iterator<Item = ExchangeRateResult> + 'a>;
+
+	/// Get the latest exchange rate of a pair id
+	fn latest(&self, id: &str) -> Result<Option<ExchangeRateResult>, Error>;
+
+	/// Get the latest exchange rate of a pair id dated at or before `time`
+	fn at_or_before(
+		&self,
+		id: &str,
+		time: DateTime<Utc>,
+	) -> Result<Option<ExchangeRateResult>, Error>;
+
+	/// Get the exchange rate of a pair id at `time`, that is the latest one dated at or
+	/// before `time`, or from the candles once the rates of `time` are compacted. Fails
+	/// with a not found error if there's none, or with a stale error if it's older than
+	/// `max_staleness`.
+	fn rate_at(
+		&self,
+		id: &str,
+		time: DateTime<Utc>,
+		max_staleness: Duration,
+	) -> Result<ExchangeRateResult, Error> {
+		let rate = match self.at_or_before(id, time)? {
+			Some(rate) => Some(rate),
+			None => self.candle_rate_at(id, time)?,
+		};
+		let rate = rate.ok_or_else(|| {
+			ErrorKind::NotFound(format!("no {} exchange rate at or before {}", id, time))
+		})?;
+		if time.signed_duration_since(rate.date) > max_staleness {
+			return Err(ErrorKind::Stale(format!(
+				"{} exchange rate at {} is dated {}, beyond the max staleness of {}s",
+				id,
+				time,
+				rate.date,
+				max_staleness.num_seconds()
+			))
+			.into());
+		}
+		Ok(rate)
+	}
+
+	/// Get the exchange rate of a pair id at `time` from the candles the rates are
+	/// compacted into, at the finest resolution with a candle holding a rate at or
+	/// before `time`: the close of the candle, dated its last tick. The candle
+	/// spanning `time` may hold later rates too, its close being then up to its
+	/// span later than `time`.
+	fn candle_rate_at(
+		&self,
+		id: &str,
+		time: DateTime<Utc>,
+	) -> Result<Option<ExchangeRateResult>, Error> {
+		for resolution in Resolution::ALL.iter().cloned() {
+			let mut candle = self.candle_at_or_before(id, resolution, time)?;
+			if let Some(c) = candle.as_ref().filter(|c| c.first_tick > time) {
+				let before = c.start - Duration::seconds(1);
+				candle = self.candle_at_or_before(id, resolution, before)?;
+			}
+			if let Some(candle) = candle {
+				return Ok(Some(ExchangeRateResult {
+					from: candle.from,
+					to: candle.to,
+					rate: candle.close,
+					date: candle.last_tick,
+				}));
+			}
+		}
+		Ok(None)
+	}
+
+	/// Get the `items` most recent exchange rates of the pairs starting with the given
+	/// prefix, newest first
+	fn recent(&self, prefix: &str, items: usize) -> Result<Vec<ExchangeRateResult>, Error> {
+		let mut rates: Vec<ExchangeRateResult> = vec![];
+		for id in self.ids(prefix) {
+			let mut next = self.latest(&id)?;
+			for _ in 0..items {
+				let rate = match next {
+					Some(rate) => rate,
+					None => break,
+				};
+				next = self.at_or_before(&id, rate.date - Duration::seconds(1))?;
+				rates.push(rate);
+			}
+		}
+		rates.sort_by_key(|rate| std::cmp::Reverse(rate.date));
+		rates.truncate(items);
+		Ok(rates)
+	}
+
+	/// Iterate over the per-source readings stored with the same id, i.e. "USD2CNY"
+	fn iter_readings<'a>(&'a self, id: &str) -> Box<dyn Iterator<Item = SourceReading> + 'a>;
+
+	/// Iterate over the candles of a pair id at the given resolution, ordered by start date
+	fn iter_candles<'a>(
+		&'a self,
+		id: &str,
+		resolution: Resolution,
+	) -> Box<dyn Iterator<Item = Candle> + 'a>;
+
+	/// Get the latest candle of a pair id at the given resolution starting at or before
+	/// `time`
+	fn candle_at_or_before(
+		&self,
+		id: &str,
+		resolution: Resolution,
+		time: DateTime<Utc>,
+	) -> Result<Option<Candle>, Error>;
+
+	/// Iterate over the candles of a pair id at the given resolution starting from `from`
+	/// to `to` included, ordered by start date
+	fn candles_range<'a>(
+		&'a self,
+		id: &str,
+		resolution: Resolution,
+		from: DateTime<Utc>,
+		to: DateTime<Utc>,
+	) -> Box<dyn Iterator<Item = Candle> + 'a>;
+
 	/// Create a new write batch to update or remove output data
 	fn batch<'a>(&'a mut self) -> Result<Box<dyn ExchangePriceBatch + 'a>, Error>;
+
+	/// The snapshotter of the database, which writes the snapshots without
+	/// borrowing the backend, so that the backend needn't be locked meanwhile.
+	fn snapshotter(&self) -> Box<dyn Snapshotter>;
+
+	/// Write a compacted copy of the whole database into the `path` directory,
+	/// which must be missing or empty, while the database stays in use. Returns
+	/// the size of the copy in bytes.
+	fn snapshot(&self, path: &str) -> Result<u64, Error> {
+		self.snapshotter().snapshot(path)
+	}
+
+	/// Statistics of the database, i.e. its number of entries and size
+	fn stats(&self) -> Result<DbStats, Error>;
+}
+
+/// Writes the snapshots of a database, independently of its backend.
+pub trait Snapshotter: Send {
+	/// Write a compacted copy of the whole database into the `path` directory,
+	/// which must be missing or empty, while the database stays in use. Returns
+	/// the size of the copy in bytes.
+	fn snapshot(&self, path: &str) -> Result<u64, Error>;
+}
+
+/// Statistics of the oracle database
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct DbStats {
+	/// Schema version of the database
+	pub schema_version: u32,
+	/// Number of entries
+	pub entries: usize,
+	/// Depth of the B-tree
+	pub depth: u32,
+	/// Size of a page, in bytes
+	pub page_size: u32,
+	/// Size of the pages in use, in bytes
+	pub used_size: usize,
+	/// Size of the memory map, the database being resized when it's nearly
+	/// full, in bytes
+	pub map_size: usize,
 }
 
 /// Batch trait to update the exchange price data backend atomically. Trying to use a
@@ -50,7 +223,20 @@ pub trait ExchangePriceBatch {
 	fn save(&mut self, date: DateTime<Utc>, exchange_rate: ExchangeRateResult)
 		-> Result<(), Error>;
 
-	/// Gets exchange rate data by id
+	/// Add or update a single source reading an aggregated exchange rate was computed from,
+	/// keyed by the `date` of the aggregated rate
+	fn save_reading(&mut self, date: DateTime<Utc>, reading: SourceReading) -> Result<(), Error>;
+
+	/// Add an aggregated exchange rate along with all the readings it was computed from
+	fn save_aggregated(&mut self, aggregated: AggregatedRate) -> Result<(), Error> {
+		let date = aggregated.rate.date;
+		for reading in aggregated.readings {
+			self.save_reading(date, reading)?;
+		}
+		self.save(date, aggregated.rate)
+	}
+
+	/// Gets the latest exchange rate of a pair id, or a not found error
 	fn get(&self, id: &str) -> Result<ExchangeRateResult, Error>;
 
 	/// Iterate over all exchange rate data stored by the backend
@@ -59,9 +245,31 @@ pub trait ExchangePriceBatch {
 	/// Iterate over all exchange rate data stored by the backend with same id
 	fn iter_id<'a>(&'a self, id: &str) -> Box<dyn Iterator<Item = ExchangeRateResult> + 'a>;
 
-	/// Delete data about an exchange rate from the backend
+	/// Delete data about an exchange rate, and its source readings, from the backend
 	fn delete(&mut self, id: &str, date: DateTime<Utc>) -> Result<(), Error>;
 
+	/// Gets the candle of a pair id starting at `start`, if any
+	fn get_candle(
+		&self,
+		id: &str,
+		resolution: Resolution,
+		start: DateTime<Utc>,
+	) -> Result<Option<Candle>, Error>;
+
+	/// Add or update a candle
+	fn save_candle(&mut self, candle: &Candle) -> Result<(), Error>;
+
+	/// Iterate over all the candles of the given resolution
+	fn iter_candles(&self, resolution: Resolution) -> Box<dyn Iterator<Item = Candle>>;
+
+	/// Delete the candle of a pair id starting at `start`
+	fn delete_candle(
+		&mut self,
+		id: &str,
+		resolution: Resolution,
+		start: DateTime<Utc>,
+	) -> Result<(), Error>;
+
 	/// Write the oracle data to backend file
 	fn commit(&self) -> Result<(), Error>;
 }
@@ -0,0 +1,428 @@
+// Copyright 2019 The Gotts Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Sanity filters on the incoming quotes, applied before they are stored.
+
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::fmt;
+
+use crate::alphavantage::ExchangeRateResult;
+use crate::error::{Error, ErrorKind};
+use crate::sources::median;
+use crate::types::OracleBackend;
+use gotts_oracle_config::ValidationConfig;
+use gotts_oracle_util::Mutex;
+
+/// How far in the future a quote can be dated, to allow for clock skews
+pub const MAX_FUTURE_SKEW_SECS: i64 = 300;
+
+/// Number of recently rejected quotes kept in the stats
+pub const MAX_RECENT_REJECTIONS: usize = 16;
+
+/// Why a quote was rejected
+#[derive(Debug, Clone, PartialEq)]
+pub enum Rejection {
+	/// The rate is zero, negative, infinite or not a number
+	InvalidRate(f64),
+	/// The quote is older than the max quote age
+	Stale(DateTime<Utc>),
+	/// The quote is dated in the future
+	InFuture(DateTime<Utc>),
+	/// The rate is too far from the recent median of the pair
+	Jump {
+		/// Recent median of the pair
+		median: f64,
+		/// Deviation from the median, in percent
+		percent: f64,
+	},
+}
+
+impl Rejection {
+	/// Short name of the rejection reason, used to count the rejections
+	pub fn reason(&self) -> &'static str {
+		match self {
+			Rejection::InvalidRate(_) => "invalid_rate",
+			Rejection::Stale(_) => "stale",
+			Rejection::InFuture(_) => "in_future",
+			Rejection::Jump { .. } => "jump",
+		}
+	}
+}
+
+impl fmt::Display for Rejection {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			Rejection::InvalidRate(rate) => write!(f, "invalid rate {}", rate),
+			Rejection::Stale(date) => write!(f, "stale quote dated {}", date),
+			Rejection::InFuture(date) => write!(f, "quote dated in the future {}", date),
+			Rejection::Jump { median, percent } => write!(
+				f,
+				"rate jumps {:.2}% from the recent median {}",
+				percent, median
+			),
+		}
+	}
+}
+
+/// A rejected quote
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RejectedQuote {
+	/// The quote
+	pub rate: ExchangeRateResult,
+	/// Why it was rejected
+	pub reason: String,
+	/// When it was rejected
+	pub rejected_at: DateTime<Utc>,
+}
+
+/// Counters of the validated quotes
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ValidationStats {
+	/// Number of accepted quotes
+	pub accepted: u64,
+	/// Number of rejected quotes
+	pub rejected: u64,
+	/// Number of quotes accepted as the new baseline of their pair, after
+	/// consecutive consistent jumps
+	pub rebaselined: u64,
+	/// Number of rejected quotes, per rejection reason
+	pub rejected_by_reason: BTreeMap<String, u64>,
+	/// Number of rejected quotes, per pair id
+	pub rejected_by_pair: BTreeMap<String, u64>,
+	/// The most recently rejected quotes, oldest first
+	pub recent_rejections: VecDeque<RejectedQuote>,
+}
+
+/// Validates the incoming quotes against the configured sanity filters and
+/// the recent history of their pair, counting the rejections.
+///
+/// Only the accepted quotes are stored, so that after a real move larger than
+/// the max jump every later quote would be rejected against the old median.
+/// The rates rejected for a jump are then kept per pair, and once enough
+/// consecutive ones agree with each other the pair is re-baselined on them:
+/// the stored quotes older than the new baseline don't count anymore.
+pub struct QuoteValidator {
+	config: ValidationConfig,
+	stats: Mutex<ValidationStats>,
+	baselines: Mutex<HashMap<String, Baseline>>,
+}
+
+/// Baseline of the jump check of a pair
+#[derive(Debug, Default)]
+struct Baseline {
+	/// Consecutive rates rejected for a jump, oldest first
+	jumps: Vec<f64>,
+	/// Date of the quote the pair was last re-baselined on
+	since: Option<DateTime<Utc>>,
+}
+
+impl QuoteValidator {
+	/// Create a new validator
+	pub fn new(config: ValidationConfig) -> QuoteValidator {
+		QuoteValidator {
+			config,
+			stats: Mutex::new(ValidationStats::default()),
+			baselines: Mutex::new(HashMap::new()),
+		}
+	}
+
+	/// Check a quote against the sanity filters, `recent` being the recent
+	/// quotes of its pair.
+	pub fn check(
+		&self,
+		rate: &ExchangeRateResult,
+		recent: &[ExchangeRateResult],
+		now: DateTime<Utc>,
+	) -> Result<(), Rejection> {
+		if !rate.rate.is_finite() || rate.rate <= 0.0 {
+			return Err(Rejection::InvalidRate(rate.rate));
+		}
+
+		let max_age = Duration::seconds(self.config.max_quote_age_secs as i64);
+		if rate.date < now - max_age {
+			return Err(Rejection::Stale(rate.date));
+		}
+		if rate.date > now + Duration::seconds(MAX_FUTURE_SKEW_SECS) {
+			return Err(Rejection::InFuture(rate.date));
+		}
+
+		if self.config.max_jump_percent > 0.0 {
+			let rates: Vec<f64> = recent
+				.iter()
+				.filter(|r| r.date >= now - max_age)
+				.map(|r| r.rate)
+				.collect();
+			if let Some(median) = median(&rates) {
+				let percent = (rate.rate - median).abs() / median * 100.0;
+				if percent > self.config.max_jump_percent {
+					return Err(Rejection::Jump { median, percent });
+				}
+			}
+		}
+
+		Ok(())
+	}
+
+	/// Validate a quote before it's stored, against the recent quotes of its
+	/// pair stored by the backend. Rejections are logged and counted.
+	pub fn validate<T: ?Sized>(&self, oracle: &T, rate: &ExchangeRateResult) -> Result<(), Error>
+	where
+		T: OracleBackend,
+	{
+		let id = format!("{}2{}", rate.from, rate.to);
+		let recent = oracle.recent(&id, self.config.median_window)?;
+		self.validate_with(&id, rate, &recent, Utc::now())
+	}
+
+	fn validate_with(
+		&self,
+		id: &str,
+		rate: &ExchangeRateResult,
+		recent: &[ExchangeRateResult],
+		now: DateTime<Utc>,
+	) -> Result<(), Error> {
+		let since = self.baselines.lock().get(id).and_then(|b| b.since);
+		let result = match since {
+			Some(since) => {
+				let recent: Vec<ExchangeRateResult> =
+					recent.iter().filter(|r| r.date >= since).cloned().collect();
+				self.check(rate, &recent, now)
+			}
+			None => self.check(rate, recent, now),
+		};
+		let rebaselined = match result {
+			Ok(()) => {
+				if let Some(baseline) = self.baselines.lock().get_mut(id) {
+					baseline.jumps.clear();
+				}
+				false
+			}
+			Err(Rejection::Jump { .. }) => self.rebaseline(id, rate),
+			Err(_) => false,
+		};
+
+		let mut stats = self.stats.lock();
+		match result {
+			Ok(()) => {
+				stats.accepted += 1;
+				Ok(())
+			}
+			Err(rejection) if rebaselined => {
+				warn!(
+					"quote {} accepted as the new baseline, after {} consecutive jumps: {}",
+					id, self.config.rebaseline_after, rejection
+				);
+				stats.accepted += 1;
+				stats.rebaselined += 1;
+				Ok(())
+			}
+			Err(rejection) => {
+				warn!("quote {} rejected: {}", id, rejection);
+				stats.rejected += 1;
+				*stats
+					.rejected_by_reason
+					.entry(rejection.reason().to_owned())
+					.or_insert(0) += 1;
+				*stats.rejected_by_pair.entry(id.to_owned()).or_insert(0) += 1;
+				if stats.recent_rejections.len() >= MAX_RECENT_REJECTIONS {
+					stats.recent_rejections.pop_front();
+				}
+				stats.recent_rejections.push_back(RejectedQuote {
+					rate: rate.clone(),
+					reason: rejection.to_string(),
+					rejected_at: now,
+				});
+				Err(ErrorKind::Rejected(format!("{}: {}", id, rejection)).into())
+			}
+		}
+	}
+
+	/// Current validation counters
+	pub fn stats(&self) -> ValidationStats {
+		self.stats.lock().clone()
+	}
+
+	/// Keep a quote of the pair `id` rejected for a jump, returns whether the
+	/// last consecutive ones are enough, and close enough to each other, to
+	/// re-baseline the pair on them.
+	fn rebaseline(&self, id: &str, rate: &ExchangeRateResult) -> bool {
+		let wanted = self.config.rebaseline_after;
+		if wanted == 0 {
+			return false;
+		}
+		let mut baselines = self.baselines.lock();
+		let baseline = baselines.entry(id.to_owned()).or_default();
+		let rates = &mut baseline.jumps;
+		rates.push(rate.rate);
+		if rates.len() > wanted {
+			let excess = rates.len() - wanted;
+			rates.drain(..excess);
+		}
+		if rates.len() < wanted {
+			return false;
+		}
+		let consistent = match median(rates) {
+			Some(median) => rates
+				.iter()
+				.all(|r| (r - median).abs() / median * 100.0 <= self.config.max_jump_percent),
+			None => false,
+		};
+		if consistent {
+			rates.clear();
+			baseline.since = Some(rate.date);
+		}
+		consistent
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn quote(rate: f64, date: DateTime<Utc>) -> ExchangeRateResult {
+		ExchangeRateResult {
+			from: "USD".to_owned(),
+			to: "CNY".to_owned(),
+			rate,
+			date,
+		}
+	}
+
+	#[test]
+	fn sanity_filters() {
+		let validator = QuoteValidator::new(ValidationConfig::default());
+		let now = Utc::now();
+
+		assert!(validator.check(&quote(6.86, now), &[], now).is_ok());
+		assert_eq!(
+			validator.check(&quote(0.0, now), &[], now),
+			Err(Rejection::InvalidRate(0.0))
+		);
+		assert_eq!(
+			validator.check(&quote(-1.0, now), &[], now),
+			Err(Rejection::InvalidRate(-1.0))
+		);
+		assert_eq!(
+			validator
+				.check(&quote(std::f64::NAN, now), &[], now)
+				.unwrap_err()
+				.reason(),
+			"invalid_rate"
+		);
+		assert_eq!(
+			validator.check(&quote(std::f64::INFINITY, now), &[], now),
+			Err(Rejection::InvalidRate(std::f64::INFINITY))
+		);
+
+		let old = now - Duration::days(4);
+		assert_eq!(
+			validator.check(&quote(6.86, old), &[], now),
+			Err(Rejection::Stale(old))
+		);
+		let future = now + Duration::hours(1);
+		assert_eq!(
+			validator.check(&quote(6.86, future), &[], now),
+			Err(Rejection::InFuture(future))
+		);
+	}
+
+	#[test]
+	fn jump_from_median() {
+		let validator = QuoteValidator::new(ValidationConfig::default());
+		let now = Utc::now();
+		let recent: Vec<ExchangeRateResult> = [6.85, 6.86, 6.87, 9.99]
+			.iter()
+			.map(|r| quote(*r, now - Duration::minutes(5)))
+			.collect();
+
+		assert!(validator.check(&quote(6.90, now), &recent, now).is_ok());
+		match validator.check(&quote(7.80, now), &recent, now) {
+			Err(Rejection::Jump { median, percent }) => {
+				assert!((median - 6.865).abs() < 1e-9);
+				assert!(percent > 10.0);
+			}
+			r => panic!("unexpected {:?}", r),
+		}
+
+		// the quotes older than the max quote age don't count
+		let old: Vec<ExchangeRateResult> = [1.0, 1.1]
+			.iter()
+			.map(|r| quote(*r, now - Duration::days(4)))
+			.collect();
+		assert!(validator.check(&quote(6.90, now), &old, now).is_ok());
+
+		// and the check can be disabled
+		let mut config = ValidationConfig::default();
+		config.max_jump_percent = 0.0;
+		let validator = QuoteValidator::new(config);
+		assert!(validator.check(&quote(7.80, now), &recent, now).is_ok());
+	}
+
+	#[test]
+	fn rebaseline_after_consistent_jumps() {
+		let validator = QuoteValidator::new(ValidationConfig::default());
+		let now = Utc::now();
+		let recent: Vec<ExchangeRateResult> = [6.85, 6.86, 6.87]
+			.iter()
+			.map(|r| quote(*r, now - Duration::minutes(5)))
+			.collect();
+
+		// inconsistent jumps, or jumps interrupted by an accepted quote, don't
+		// move the baseline
+		assert!(validator
+			.validate_with("USD2CNY", &quote(7.80, now), &recent, now)
+			.is_err());
+		assert!(validator
+			.validate_with("USD2CNY", &quote(9.90, now), &recent, now)
+			.is_err());
+		assert!(validator
+			.validate_with("USD2CNY", &quote(6.86, now), &recent, now)
+			.is_ok());
+		assert!(validator
+			.validate_with("USD2CNY", &quote(7.80, now), &recent, now)
+			.is_err());
+		assert!(validator
+			.validate_with("USD2CNY", &quote(7.82, now), &recent, now)
+			.is_err());
+		assert_eq!(validator.stats().rebaselined, 0);
+
+		// the third consecutive consistent jump is the new baseline
+		assert!(validator
+			.validate_with("USD2CNY", &quote(7.81, now), &recent, now)
+			.is_ok());
+		let stats = validator.stats();
+		assert_eq!(stats.rebaselined, 1);
+		assert_eq!(stats.accepted, 2);
+		assert_eq!(stats.rejected, 4);
+
+		// the quotes stored before the new baseline don't count anymore
+		let mut recent = recent;
+		recent.push(quote(7.81, now));
+		assert!(validator
+			.validate_with("USD2CNY", &quote(7.83, now), &recent, now)
+			.is_ok());
+
+		// unless disabled
+		let mut config = ValidationConfig::default();
+		config.rebaseline_after = 0;
+		let validator = QuoteValidator::new(config);
+		for _ in 0..5 {
+			assert!(validator
+				.validate_with("USD2CNY", &quote(7.80, now), &recent, now)
+				.is_err());
+		}
+	}
+}
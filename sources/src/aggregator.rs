@@ -0,0 +1,332 @@
+// Copyright 2019 The Gotts Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Median aggregation over all the registered price sources
+
+use futures::future::{self, Future};
+use std::cmp::Ordering;
+
+use crate::alphavantage::ExchangeRateResult;
+use crate::error::Error;
+use crate::registry::SourceRegistry;
+use crate::types::{AggregatedRate, SourceReading};
+
+/// Queries every registered source for a pair and reduces the readings to
+/// their median.
+pub struct Aggregator {
+	registry: SourceRegistry,
+	min_sources: usize,
+}
+
+impl Aggregator {
+	/// Create an aggregator over `registry`, which refuses to produce a
+	/// price unless at least `min_sources` sources answered.
+	pub fn new(registry: SourceRegistry, min_sources: usize) -> Aggregator {
+		Aggregator {
+			registry,
+			min_sources: std::cmp::max(min_sources, 1),
+		}
+	}
+
+	/// The sources queried by this aggregator.
+	pub fn registry(&self) -> &SourceRegistry {
+		&self.registry
+	}
+
+	/// Number of pairs which can be aggregated right now without hitting
+	/// the rate limit of any source, `None` if no source is rate limited.
+	pub fn available_calls(&self) -> Option<usize> {
+		self.registry
+			.iter()
+			.filter_map(|source| source.available_calls())
+			.min()
+	}
+
+	/// Query all sources for the exchange rate from `from` to `to` and
+	/// return the median along with the individual readings. Failing
+	/// sources are logged and skipped.
+	pub fn aggregate(&self, from: &str, to: &str) -> Result<AggregatedRate, Error> {
+		let mut readings: Vec<SourceReading> = Vec::with_capacity(self.registry.len());
+		for source in self.registry.iter() {
+			match source.get_rate(from, to) {
+				Ok(rate) => readings.push(SourceReading {
+					source: source.name().to_owned(),
+					rate,
+				}),
+				Err(e) => warn!("aggregate {}2{}: {}", from, to, e),
+			}
+		}
+		aggregate_readings(from, to, readings, self.min_sources)
+	}
+
+	/// Asynchronous version of `aggregate`, querying all the sources
+	/// concurrently. The returned future must be run on a tokio runtime.
+	pub fn aggregate_async(
+		&self,
+		from: &str,
+		to: &str,
+	) -> Box<dyn Future<Item = AggregatedRate, Error = Error> + Send> {
+		let queries: Vec<_> = self
+			.registry
+			.iter()
+			.map(|source| {
+				let name = source.name().to_owned();
+				source
+					.get_rate_async(from, to)
+					.then(move |result| Ok::<_, Error>((name, result)))
+			})
+			.collect();
+		let (from, to, min_sources) = (from.to_owned(), to.to_owned(), self.min_sources);
+		Box::new(future::join_all(queries).and_then(move |results| {
+			let mut readings: Vec<SourceReading> = Vec::with_capacity(results.len());
+			for (source, result) in results {
+				match result {
+					Ok(rate) => readings.push(SourceReading { source, rate }),
+					Err(e) => warn!("aggregate {}2{}: {}", from, to, e),
+				}
+			}
+			aggregate_readings(&from, &to, readings, min_sources)
+		}))
+	}
+}
+
+/// The median of the readings, if there are enough of them. The readings
+/// whose rate isn't a finite positive number are dropped, as they would be
+/// stored along with the median.
+fn aggregate_readings(
+	from: &str,
+	to: &str,
+	mut readings: Vec<SourceReading>,
+	min_sources: usize,
+) -> Result<AggregatedRate, Error> {
+	readings.retain(|r| {
+		let valid = r.rate.rate.is_finite() && r.rate.rate > 0.0;
+		if !valid {
+			warn!(
+				"aggregate {}2{}: invalid rate {} from {}",
+				from, to, r.rate.rate, r.source
+			);
+		}
+		valid
+	});
+	if readings.len() < min_sources {
+		return Err(Error::InsufficientSources {
+			pair: format!("{}2{}", from, to),
+			required: min_sources,
+			got: readings.len(),
+		});
+	}
+
+	let rates: Vec<f64> = readings.iter().map(|r| r.rate.rate).collect();
+	let date = readings.iter().map(|r| r.rate.date).max().unwrap();
+	let rate = ExchangeRateResult {
+		from: readings[0].rate.from.clone(),
+		to: readings[0].rate.to.clone(),
+		rate: median(&rates).unwrap(),
+		date,
+	};
+
+	Ok(AggregatedRate { rate, readings })
+}
+
+/// Median of a set of values, the mean of the two middle values for an even
+/// count. Returns `None` for an empty set.
+pub fn median(values: &[f64]) -> Option<f64> {
+	if values.is_empty() {
+		return None;
+	}
+	let mut sorted = values.to_vec();
+	sorted.sort_by(|a, b| a.partial_cmp(b).unwrap_or(Ordering::Equal));
+	let mid = sorted.len() / 2;
+	if sorted.len() % 2 == 0 {
+		Some((sorted[mid - 1] + sorted[mid]) / 2.0)
+	} else {
+		Some(sorted[mid])
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::types::PriceSource;
+	use chrono::{Duration, Utc};
+	use std::sync::Arc;
+
+	struct FakeSource {
+		name: String,
+		rate: Option<f64>,
+		age_secs: i64,
+	}
+
+	impl FakeSource {
+		fn new(name: &str, rate: Option<f64>, age_secs: i64) -> Arc<dyn PriceSource> {
+			Arc::new(FakeSource {
+				name: name.to_owned(),
+				rate,
+				age_secs,
+			})
+		}
+	}
+
+	impl PriceSource for FakeSource {
+		fn name(&self) -> &str {
+			&self.name
+		}
+
+		fn get_rate(&self, from: &str, to: &str) -> Result<ExchangeRateResult, Error> {
+			match self.rate {
+				Some(rate) => Ok(ExchangeRateResult {
+					from: from.to_owned(),
+					to: to.to_owned(),
+					rate,
+					date: Utc::now() - Duration::seconds(self.age_secs),
+				}),
+				None => Err(Error::SourceError {
+					source: self.name.clone(),
+					message: "unavailable".to_owned(),
+				}),
+			}
+		}
+	}
+
+	#[test]
+	fn median_of_values() {
+		assert_eq!(median(&[]), None);
+		assert_eq!(median(&[3.0]), Some(3.0));
+		assert_eq!(median(&[3.0, 1.0, 2.0]), Some(2.0));
+		assert_eq!(median(&[4.0, 1.0, 3.0, 2.0]), Some(2.5));
+	}
+
+	#[test]
+	fn registry_rejects_duplicates() {
+		let mut registry = SourceRegistry::new();
+		registry
+			.register(FakeSource::new("a", Some(1.0), 0))
+			.unwrap();
+		assert_eq!(
+			registry.register(FakeSource::new("a", Some(2.0), 0)),
+			Err(Error::DuplicateSource("a".to_owned()))
+		);
+		assert_eq!(registry.names(), vec!["a".to_owned()]);
+	}
+
+	#[test]
+	fn aggregate_median_with_readings() {
+		let mut registry = SourceRegistry::new();
+		registry
+			.register(FakeSource::new("a", Some(6.9), 30))
+			.unwrap();
+		registry
+			.register(FakeSource::new("b", Some(7.1), 10))
+			.unwrap();
+		registry.register(FakeSource::new("c", None, 0)).unwrap();
+		registry
+			.register(FakeSource::new("d", Some(6.8), 20))
+			.unwrap();
+
+		let aggregated = Aggregator::new(registry, 3)
+			.aggregate("USD", "CNY")
+			.unwrap();
+		assert_eq!(aggregated.rate.from, "USD");
+		assert_eq!(aggregated.rate.to, "CNY");
+		assert_eq!(aggregated.rate.rate, 6.9);
+		assert_eq!(aggregated.rate.date, aggregated.readings[1].rate.date);
+		let sources: Vec<&str> = aggregated
+			.readings
+			.iter()
+			.map(|r| r.source.as_str())
+			.collect();
+		assert_eq!(sources, vec!["a", "b", "d"]);
+	}
+
+	#[test]
+	fn aggregate_insufficient_sources() {
+		let mut registry = SourceRegistry::new();
+		registry
+			.register(FakeSource::new("a", Some(6.9), 0))
+			.unwrap();
+		registry.register(FakeSource::new("b", None, 0)).unwrap();
+
+		assert_eq!(
+			Aggregator::new(registry, 2).aggregate("USD", "CNY"),
+			Err(Error::InsufficientSources {
+				pair: "USD2CNY".to_owned(),
+				required: 2,
+				got: 1,
+			})
+		);
+	}
+
+	#[test]
+	fn aggregate_drops_invalid_readings() {
+		let mut registry = SourceRegistry::new();
+		registry
+			.register(FakeSource::new("a", Some(6.9), 0))
+			.unwrap();
+		registry
+			.register(FakeSource::new("b", Some(std::f64::NAN), 0))
+			.unwrap();
+		registry
+			.register(FakeSource::new("c", Some(std::f64::INFINITY), 0))
+			.unwrap();
+		registry
+			.register(FakeSource::new("d", Some(-1.0), 0))
+			.unwrap();
+		registry
+			.register(FakeSource::new("e", Some(7.1), 0))
+			.unwrap();
+
+		let aggregated = Aggregator::new(registry.clone(), 2)
+			.aggregate("USD", "CNY")
+			.unwrap();
+		assert_eq!(aggregated.rate.rate, 7.0);
+		let sources: Vec<&str> = aggregated
+			.readings
+			.iter()
+			.map(|r| r.source.as_str())
+			.collect();
+		assert_eq!(sources, vec!["a", "e"]);
+		assert_eq!(
+			Aggregator::new(registry, 3).aggregate("USD", "CNY"),
+			Err(Error::InsufficientSources {
+				pair: "USD2CNY".to_owned(),
+				required: 3,
+				got: 2,
+			})
+		);
+	}
+
+	#[test]
+	fn aggregate_async() {
+		let mut registry = SourceRegistry::new();
+		registry
+			.register(FakeSource::new("a", Some(6.9), 0))
+			.unwrap();
+		registry.register(FakeSource::new("b", None, 0)).unwrap();
+		registry
+			.register(FakeSource::new("c", Some(7.1), 0))
+			.unwrap();
+
+		let aggregated = Aggregator::new(registry.clone(), 2)
+			.aggregate_async("USD", "CNY")
+			.wait()
+			.unwrap();
+		assert_eq!(aggregated.rate.rate, 7.0);
+		assert_eq!(aggregated.readings.len(), 2);
+		assert!(Aggregator::new(registry, 3)
+			.aggregate_async("USD", "CNY")
+			.wait()
+			.is_err());
+	}
+}
@@ -0,0 +1,65 @@
+// Copyright 2019 The Gotts Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Alpha Vantage as a price source
+
+use crate::alphavantage::{self, exchange_rate::ExchangeRate, ExchangeRateResult};
+use crate::error::Error;
+use crate::types::{PriceSource, RateFuture};
+use futures::Future;
+
+/// Registry name of the Alpha Vantage source.
+pub const ALPHA_VANTAGE_SOURCE: &str = "alpha_vantage";
+
+impl PriceSource for alphavantage::Client {
+	fn name(&self) -> &str {
+		ALPHA_VANTAGE_SOURCE
+	}
+
+	fn get_rate(&self, from: &str, to: &str) -> Result<ExchangeRateResult, Error> {
+		self.get_exchange_rate(from, to)
+			.map(to_result)
+			.map_err(to_source_error)
+	}
+
+	fn get_rate_async(&self, from: &str, to: &str) -> RateFuture {
+		Box::new(
+			self.get_exchange_rate_async(from, to)
+				.map(to_result)
+				.map_err(to_source_error),
+		)
+	}
+}
+
+fn to_result(exchange_rate: ExchangeRate) -> ExchangeRateResult {
+	ExchangeRateResult {
+		from: exchange_rate.from.code,
+		to: exchange_rate.to.code,
+		rate: exchange_rate.rate,
+		date: exchange_rate.date,
+	}
+}
+
+fn to_source_error(error: alphavantage::Error) -> Error {
+	match error {
+		alphavantage::Error::RateLimited { message } => Error::RateLimited {
+			source: ALPHA_VANTAGE_SOURCE.to_owned(),
+			message,
+		},
+		e => Error::SourceError {
+			source: ALPHA_VANTAGE_SOURCE.to_owned(),
+			message: format!("{}", e),
+		},
+	}
+}
@@ -0,0 +1,185 @@
+// Copyright 2019 The Gotts Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Control of the polling daemon by the owner API. The commands are queued,
+//! then applied by the daemon to its scheduler on its next round.
+
+use std::collections::BTreeSet;
+
+use gotts_oracle_util::Mutex;
+
+/// A command to the polling daemon
+#[derive(Debug, Clone, PartialEq)]
+pub enum PollCommand {
+	/// Poll a pair right away, within the rate limits of the price sources
+	Poll(String, String),
+	/// Stop polling a pair
+	Pause(String, String),
+	/// Resume the polling of a paused pair
+	Resume(String, String),
+	/// Replace the polled pairs, i.e. on a reload of the configuration
+	SetPairs(Vec<(String, String)>),
+}
+
+struct ControlState {
+	pairs: Vec<(String, String)>,
+	paused: BTreeSet<(String, String)>,
+	commands: Vec<PollCommand>,
+}
+
+/// Polling state shared by the owner API and the polling daemon.
+pub struct PollControl {
+	state: Mutex<ControlState>,
+}
+
+impl PollControl {
+	/// Create the control of the daemon polling `pairs`, none of them paused.
+	pub fn new(pairs: Vec<(String, String)>) -> PollControl {
+		PollControl {
+			state: Mutex::new(ControlState {
+				pairs,
+				paused: BTreeSet::new(),
+				commands: vec![],
+			}),
+		}
+	}
+
+	/// The polled pairs, the paused ones included.
+	pub fn pairs(&self) -> Vec<(String, String)> {
+		self.state.lock().pairs.clone()
+	}
+
+	/// The paused pairs.
+	pub fn paused(&self) -> Vec<(String, String)> {
+		self.state.lock().paused.iter().cloned().collect()
+	}
+
+	/// Request a poll of a pair, returns false if the pair isn't polled.
+	pub fn poll(&self, from: &str, to: &str) -> bool {
+		self.command(from, to, PollCommand::Poll)
+	}
+
+	/// Pause the polling of a pair, returns false if the pair isn't polled.
+	pub fn pause(&self, from: &str, to: &str) -> bool {
+		self.command(from, to, PollCommand::Pause)
+	}
+
+	/// Resume the polling of a pair, returns false if the pair isn't polled.
+	pub fn resume(&self, from: &str, to: &str) -> bool {
+		self.command(from, to, PollCommand::Resume)
+	}
+
+	/// Replace the polled pairs, the pairs kept staying paused if they were.
+	pub fn set_pairs(&self, pairs: Vec<(String, String)>) {
+		let mut state = self.state.lock();
+		state.paused.retain(|pair| pairs.contains(pair));
+		state.pairs = pairs.clone();
+		state.commands.push(PollCommand::SetPairs(pairs));
+	}
+
+	/// Whether commands are waiting for the daemon.
+	pub fn has_commands(&self) -> bool {
+		!self.state.lock().commands.is_empty()
+	}
+
+	/// Take the commands waiting for the daemon, in the order they were given.
+	pub fn take_commands(&self) -> Vec<PollCommand> {
+		self.state.lock().commands.drain(..).collect()
+	}
+
+	/// Queue a command on a polled pair, matched ignoring case.
+	fn command<F>(&self, from: &str, to: &str, command: F) -> bool
+	where
+		F: Fn(String, String) -> PollCommand,
+	{
+		let mut state = self.state.lock();
+		let pair = match state
+			.pairs
+			.iter()
+			.find(|(f, t)| f.eq_ignore_ascii_case(from) && t.eq_ignore_ascii_case(to))
+		{
+			Some(pair) => pair.clone(),
+			None => return false,
+		};
+		let command = command(pair.0.clone(), pair.1.clone());
+		match command {
+			PollCommand::Pause(..) => {
+				state.paused.insert(pair);
+			}
+			PollCommand::Resume(..) => {
+				state.paused.remove(&pair);
+			}
+			_ => {}
+		}
+		state.commands.push(command);
+		true
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::scheduler::PollScheduler;
+	use std::time::{Duration, Instant};
+
+	fn pair(from: &str, to: &str) -> (String, String) {
+		(from.to_owned(), to.to_owned())
+	}
+
+	#[test]
+	fn commands_applied_by_the_scheduler() {
+		let start = Instant::now();
+		let control = PollControl::new(vec![pair("EUR", "USD"), pair("USD", "CNY")]);
+		let mut scheduler = PollScheduler::new(
+			control.pairs(),
+			Duration::from_secs(60),
+			Duration::from_secs(10),
+			Duration::from_secs(300),
+			start,
+		);
+		scheduler.succeeded("EUR", "USD", start);
+		scheduler.succeeded("USD", "CNY", start);
+		assert!(scheduler.due(start, None).is_empty());
+
+		assert!(!control.poll("USD", "JPY"));
+		assert!(control.poll("eur", "usd"));
+		assert!(control.pause("USD", "CNY"));
+		assert_eq!(control.paused(), vec![pair("USD", "CNY")]);
+		assert!(control.has_commands());
+		for command in control.take_commands() {
+			scheduler.apply(command, start);
+		}
+		assert!(!control.has_commands());
+		assert_eq!(scheduler.due(start, None), vec![pair("EUR", "USD")]);
+
+		// a paused pair is never due, until resumed
+		let later = start + Duration::from_secs(120);
+		assert_eq!(scheduler.due(later, None), vec![pair("EUR", "USD")]);
+		assert_eq!(scheduler.next_due(), Some(start));
+		assert!(control.resume("USD", "CNY"));
+		for command in control.take_commands() {
+			scheduler.apply(command, later);
+		}
+		assert_eq!(scheduler.due(later, None).len(), 2);
+
+		// the schedule of the pairs kept is kept, the new pairs are due
+		scheduler.succeeded("EUR", "USD", later);
+		control.set_pairs(vec![pair("EUR", "USD"), pair("USD", "JPY")]);
+		for command in control.take_commands() {
+			scheduler.apply(command, later);
+		}
+		assert_eq!(scheduler.due(later, None), vec![pair("USD", "JPY")]);
+		assert_eq!(control.pairs(), scheduler.pairs());
+	}
+}
@@ -0,0 +1,52 @@
+// Copyright 2019 The Gotts Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use failure_derive::Fail;
+
+/// Set of errors which can occur when querying price sources.
+#[derive(Clone, Debug, Eq, PartialEq, Fail)]
+pub enum Error {
+	/// A single price source failed to return a rate.
+	#[fail(display = "price source {} failed: {}", source, message)]
+	SourceError {
+		/// Name of the failing source.
+		source: String,
+		/// Description of the failure.
+		message: String,
+	},
+	/// A price source refused the query, being over its call limits.
+	#[fail(display = "price source {} rate limited: {}", source, message)]
+	RateLimited {
+		/// Name of the throttled source.
+		source: String,
+		/// Description of the limit hit.
+		message: String,
+	},
+	/// A source with the same name has already been registered.
+	#[fail(display = "price source {} already registered", _0)]
+	DuplicateSource(String),
+	/// Not enough sources returned a rate to compute a trustworthy median.
+	#[fail(
+		display = "not enough price sources for {}: required {}, got {}",
+		pair, required, got
+	)]
+	InsufficientSources {
+		/// The queried currency pair, i.e. "USD2CNY".
+		pair: String,
+		/// Minimum number of readings required.
+		required: usize,
+		/// Number of readings actually obtained.
+		got: usize,
+	},
+}
@@ -0,0 +1,45 @@
+// Copyright 2019 The Gotts Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Pluggable price sources for Gotts Oracle, and the aggregation of their
+//! readings into a single median price.
+
+#![deny(non_upper_case_globals)]
+#![deny(non_camel_case_types)]
+#![deny(non_snake_case)]
+#![deny(unused_mut)]
+#![warn(missing_docs)]
+
+#[macro_use]
+extern crate log;
+
+use gotts_oracle_alphavantage as alphavantage;
+
+mod aggregator;
+mod alpha_vantage;
+mod control;
+mod error;
+mod rate_limit;
+mod registry;
+mod scheduler;
+mod types;
+
+pub use crate::aggregator::{median, Aggregator};
+pub use crate::alpha_vantage::ALPHA_VANTAGE_SOURCE;
+pub use crate::control::{PollCommand, PollControl};
+pub use crate::error::Error;
+pub use crate::rate_limit::{RateLimitedSource, TokenBucket};
+pub use crate::registry::SourceRegistry;
+pub use crate::scheduler::PollScheduler;
+pub use crate::types::{AggregatedRate, PriceSource, RateFuture, SourceReading};
@@ -0,0 +1,192 @@
+// Copyright 2019 The Gotts Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Client side rate limiting of the price sources, so that the calls made
+//! with a vendor key stay within the limits of that key.
+
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::alphavantage::ExchangeRateResult;
+use crate::error::Error;
+use crate::types::{PriceSource, RateFuture};
+use futures::future::{self, Future};
+use gotts_oracle_util::Mutex;
+
+/// A token bucket, holding up to `capacity` calls and refilled at a steady
+/// rate.
+#[derive(Debug, Clone)]
+pub struct TokenBucket {
+	capacity: f64,
+	tokens: f64,
+	per_sec: f64,
+	last_refill: Instant,
+}
+
+impl TokenBucket {
+	/// Create a full bucket of `capacity` calls, refilled with `per_minute`
+	/// calls every minute.
+	pub fn new(capacity: u32, per_minute: u32, now: Instant) -> TokenBucket {
+		TokenBucket {
+			capacity: capacity as f64,
+			tokens: capacity as f64,
+			per_sec: per_minute as f64 / 60.0,
+			last_refill: now,
+		}
+	}
+
+	fn refill(&mut self, now: Instant) {
+		if now > self.last_refill {
+			let elapsed = now - self.last_refill;
+			let elapsed = elapsed.as_secs() as f64 + elapsed.subsec_nanos() as f64 * 1e-9;
+			self.tokens = (self.tokens + elapsed * self.per_sec).min(self.capacity);
+			self.last_refill = now;
+		}
+	}
+
+	/// Take a call from the bucket, returns false if it's empty.
+	pub fn try_take(&mut self, now: Instant) -> bool {
+		self.refill(now);
+		if self.tokens >= 1.0 {
+			self.tokens -= 1.0;
+			true
+		} else {
+			false
+		}
+	}
+
+	/// Number of calls left in the bucket.
+	pub fn available(&mut self, now: Instant) -> usize {
+		self.refill(now);
+		self.tokens as usize
+	}
+
+	/// Whether the bucket is full, its calls being all left.
+	pub fn is_full(&mut self, now: Instant) -> bool {
+		self.refill(now);
+		self.tokens >= self.capacity
+	}
+
+	/// Time until a call can be taken from the bucket, `None` if it's never
+	/// refilled.
+	pub fn next_available(&mut self, now: Instant) -> Option<Duration> {
+		self.refill(now);
+		if self.tokens >= 1.0 {
+			Some(Duration::from_secs(0))
+		} else if self.per_sec > 0.0 {
+			let secs = (1.0 - self.tokens) / self.per_sec;
+			Some(Duration::from_millis((secs * 1000.0).ceil() as u64))
+		} else {
+			None
+		}
+	}
+
+	/// Empty the bucket, when the vendor reports the limit is hit anyway.
+	pub fn drain(&mut self, now: Instant) {
+		self.refill(now);
+		self.tokens = 0.0;
+	}
+}
+
+/// A price source whose calls are limited by a token bucket. The bucket is
+/// drained when the source reports it's rate limited, as its key may be
+/// shared with other clients.
+pub struct RateLimitedSource {
+	source: Arc<dyn PriceSource>,
+	bucket: Arc<Mutex<TokenBucket>>,
+}
+
+impl RateLimitedSource {
+	/// Limit `source` to `per_minute` calls every minute, with bursts of up
+	/// to `burst` calls.
+	pub fn new(source: Arc<dyn PriceSource>, per_minute: u32, burst: u32) -> RateLimitedSource {
+		RateLimitedSource {
+			source,
+			bucket: Arc::new(Mutex::new(TokenBucket::new(
+				burst,
+				per_minute,
+				Instant::now(),
+			))),
+		}
+	}
+
+	/// Take a call from the bucket, or fail as rate limited.
+	fn take(&self) -> Result<(), Error> {
+		if self.bucket.lock().try_take(Instant::now()) {
+			Ok(())
+		} else {
+			Err(Error::RateLimited {
+				source: self.name().to_owned(),
+				message: "no call left for this minute".to_owned(),
+			})
+		}
+	}
+}
+
+impl PriceSource for RateLimitedSource {
+	fn name(&self) -> &str {
+		self.source.name()
+	}
+
+	fn get_rate(&self, from: &str, to: &str) -> Result<ExchangeRateResult, Error> {
+		self.take()?;
+		let result = self.source.get_rate(from, to);
+		if let Err(Error::RateLimited { .. }) = result {
+			self.bucket.lock().drain(Instant::now());
+		}
+		result
+	}
+
+	fn get_rate_async(&self, from: &str, to: &str) -> RateFuture {
+		if let Err(e) = self.take() {
+			return Box::new(future::err(e));
+		}
+		let bucket = self.bucket.clone();
+		Box::new(self.source.get_rate_async(from, to).map_err(move |e| {
+			if let Error::RateLimited { .. } = e {
+				bucket.lock().drain(Instant::now());
+			}
+			e
+		}))
+	}
+
+	fn available_calls(&self) -> Option<usize> {
+		Some(self.bucket.lock().available(Instant::now()))
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn token_bucket() {
+		let start = Instant::now();
+		let mut bucket = TokenBucket::new(2, 6, start);
+		assert_eq!(bucket.available(start), 2);
+		assert!(bucket.try_take(start));
+		assert!(bucket.try_take(start));
+		assert!(!bucket.try_take(start));
+		assert_eq!(bucket.next_available(start), Some(Duration::from_secs(10)));
+
+		// 6 calls per minute, one every 10 seconds
+		assert!(!bucket.try_take(start + Duration::from_secs(9)));
+		assert!(bucket.try_take(start + Duration::from_secs(11)));
+		assert_eq!(bucket.available(start + Duration::from_secs(600)), 2);
+		assert!(bucket.is_full(start + Duration::from_secs(600)));
+
+		bucket.drain(start + Duration::from_secs(600));
+		assert_eq!(bucket.available(start + Duration::from_secs(600)), 0);
+	}
+}
@@ -0,0 +1,66 @@
+// Copyright 2019 The Gotts Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::sync::Arc;
+
+use crate::error::Error;
+use crate::types::PriceSource;
+
+/// Set of named price sources, kept in registration order.
+#[derive(Clone, Default)]
+pub struct SourceRegistry {
+	sources: Vec<Arc<dyn PriceSource>>,
+}
+
+impl SourceRegistry {
+	/// Create an empty registry.
+	pub fn new() -> SourceRegistry {
+		SourceRegistry { sources: vec![] }
+	}
+
+	/// Register a new price source. Source names must be unique.
+	pub fn register(&mut self, source: Arc<dyn PriceSource>) -> Result<(), Error> {
+		if self.get(source.name()).is_some() {
+			return Err(Error::DuplicateSource(source.name().to_owned()));
+		}
+		info!("price source {} registered", source.name());
+		self.sources.push(source);
+		Ok(())
+	}
+
+	/// Get a registered price source by name.
+	pub fn get(&self, name: &str) -> Option<Arc<dyn PriceSource>> {
+		self.sources.iter().find(|s| s.name() == name).cloned()
+	}
+
+	/// Names of all registered sources.
+	pub fn names(&self) -> Vec<String> {
+		self.sources.iter().map(|s| s.name().to_owned()).collect()
+	}
+
+	/// Iterate over all registered sources.
+	pub fn iter(&self) -> impl Iterator<Item = &Arc<dyn PriceSource>> {
+		self.sources.iter()
+	}
+
+	/// Number of registered sources.
+	pub fn len(&self) -> usize {
+		self.sources.len()
+	}
+
+	/// Whether no source has been registered yet.
+	pub fn is_empty(&self) -> bool {
+		self.sources.is_empty()
+	}
+}
@@ -0,0 +1,268 @@
+// Copyright 2019 The Gotts Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Scheduling of the polled currency pairs. Each pair is polled once per
+//! poll interval, the stalest pairs first when the price sources can't take
+//! them all, and the failing pairs are retried after an exponential backoff.
+//! The paused pairs are not polled.
+
+use rand::{thread_rng, Rng};
+use std::time::{Duration, Instant};
+
+use crate::control::PollCommand;
+
+struct PairSchedule {
+	from: String,
+	to: String,
+	last_success: Option<Instant>,
+	failures: u32,
+	next_poll: Instant,
+	paused: bool,
+}
+
+impl PairSchedule {
+	fn new(from: String, to: String, now: Instant) -> PairSchedule {
+		PairSchedule {
+			from,
+			to,
+			last_success: None,
+			failures: 0,
+			next_poll: now,
+			paused: false,
+		}
+	}
+}
+
+/// Decides which pairs are polled, and when.
+pub struct PollScheduler {
+	pairs: Vec<PairSchedule>,
+	interval: Duration,
+	backoff_initial: Duration,
+	backoff_max: Duration,
+}
+
+impl PollScheduler {
+	/// Create a scheduler polling every pair once per `interval`, all of them
+	/// being due right away. The failing pairs are retried after
+	/// `backoff_initial`, doubled on every failure up to `backoff_max`.
+	pub fn new(
+		pairs: Vec<(String, String)>,
+		interval: Duration,
+		backoff_initial: Duration,
+		backoff_max: Duration,
+		now: Instant,
+	) -> PollScheduler {
+		PollScheduler {
+			pairs: pairs
+				.into_iter()
+				.map(|(from, to)| PairSchedule::new(from, to, now))
+				.collect(),
+			interval,
+			backoff_initial,
+			backoff_max,
+		}
+	}
+
+	/// The pairs due for a poll, the stalest first, and at most `budget` of
+	/// them if the price sources are rate limited.
+	pub fn due(&self, now: Instant, budget: Option<usize>) -> Vec<(String, String)> {
+		let mut due: Vec<&PairSchedule> = self
+			.pairs
+			.iter()
+			.filter(|p| !p.paused && p.next_poll <= now)
+			.collect();
+		// the pairs never polled successfully come first
+		due.sort_by_key(|p| p.last_success);
+		due.into_iter()
+			.take(budget.unwrap_or(self.pairs.len()))
+			.map(|p| (p.from.clone(), p.to.clone()))
+			.collect()
+	}
+
+	/// When the next pair is due.
+	pub fn next_due(&self) -> Option<Instant> {
+		self.pairs
+			.iter()
+			.filter(|p| !p.paused)
+			.map(|p| p.next_poll)
+			.min()
+	}
+
+	/// The scheduled pairs, the paused ones included.
+	pub fn pairs(&self) -> Vec<(String, String)> {
+		self.pairs
+			.iter()
+			.map(|p| (p.from.clone(), p.to.clone()))
+			.collect()
+	}
+
+	/// Apply a command of the owner API.
+	pub fn apply(&mut self, command: PollCommand, now: Instant) {
+		match command {
+			PollCommand::Poll(from, to) => {
+				if let Some(pair) = self.pair_mut(&from, &to) {
+					pair.next_poll = now;
+				}
+			}
+			PollCommand::Pause(from, to) => {
+				if let Some(pair) = self.pair_mut(&from, &to) {
+					pair.paused = true;
+				}
+			}
+			PollCommand::Resume(from, to) => {
+				if let Some(pair) = self.pair_mut(&from, &to) {
+					pair.paused = false;
+				}
+			}
+			PollCommand::SetPairs(pairs) => {
+				let mut schedules = Vec::with_capacity(pairs.len());
+				for (from, to) in pairs {
+					let index = self.pairs.iter().position(|p| p.from == from && p.to == to);
+					schedules.push(match index {
+						Some(index) => self.pairs.remove(index),
+						None => PairSchedule::new(from, to, now),
+					});
+				}
+				self.pairs = schedules;
+			}
+		}
+	}
+
+	/// Record a successful poll of a pair.
+	pub fn succeeded(&mut self, from: &str, to: &str, now: Instant) {
+		let interval = self.interval;
+		if let Some(pair) = self.pair_mut(from, to) {
+			pair.last_success = Some(now);
+			pair.failures = 0;
+			pair.next_poll = now + interval;
+		}
+	}
+
+	/// Record a failed poll of a pair, returns the delay before it's retried.
+	pub fn failed(&mut self, from: &str, to: &str, now: Instant) -> Duration {
+		let failures = match self.pair_mut(from, to) {
+			Some(pair) => {
+				pair.failures += 1;
+				pair.failures
+			}
+			None => return Duration::from_secs(0),
+		};
+		let delay = with_jitter(self.backoff(failures));
+		if let Some(pair) = self.pair_mut(from, to) {
+			pair.next_poll = now + delay;
+		}
+		delay
+	}
+
+	/// Backoff after a number of consecutive failures, before the jitter.
+	pub fn backoff(&self, failures: u32) -> Duration {
+		let factor = 1u32 << failures.saturating_sub(1).min(16);
+		self.backoff_initial
+			.checked_mul(factor)
+			.map_or(self.backoff_max, |backoff| backoff.min(self.backoff_max))
+	}
+
+	fn pair_mut(&mut self, from: &str, to: &str) -> Option<&mut PairSchedule> {
+		self.pairs.iter_mut().find(|p| p.from == from && p.to == to)
+	}
+}
+
+/// Randomize a delay between its half and itself, so that the retries of
+/// the failing pairs get spread.
+fn with_jitter(delay: Duration) -> Duration {
+	let millis = delay.as_secs() * 1000 + u64::from(delay.subsec_millis());
+	let half = millis / 2;
+	Duration::from_millis(half + thread_rng().gen_range(0, millis - half + 1))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn pairs() -> Vec<(String, String)> {
+		vec![
+			("EUR".to_owned(), "USD".to_owned()),
+			("USD".to_owned(), "CNY".to_owned()),
+			("USD".to_owned(), "JPY".to_owned()),
+		]
+	}
+
+	#[test]
+	fn stale_pairs_first() {
+		let start = Instant::now();
+		let interval = Duration::from_secs(60);
+		let mut scheduler = PollScheduler::new(
+			pairs(),
+			interval,
+			Duration::from_secs(10),
+			Duration::from_secs(300),
+			start,
+		);
+		assert_eq!(scheduler.due(start, None).len(), 3);
+		assert_eq!(
+			scheduler.due(start, Some(1)),
+			vec![("EUR".to_owned(), "USD".to_owned())]
+		);
+
+		scheduler.succeeded("USD", "CNY", start);
+		scheduler.succeeded("EUR", "USD", start + Duration::from_secs(5));
+		assert_eq!(
+			scheduler.due(start, None),
+			vec![("USD".to_owned(), "JPY".to_owned())]
+		);
+		scheduler.succeeded("USD", "JPY", start + Duration::from_secs(10));
+		assert_eq!(scheduler.next_due(), Some(start + interval));
+
+		// all due again, USD2CNY being the stalest
+		let later = start + Duration::from_secs(120);
+		assert_eq!(
+			scheduler.due(later, Some(2)),
+			vec![
+				("USD".to_owned(), "CNY".to_owned()),
+				("EUR".to_owned(), "USD".to_owned())
+			]
+		);
+	}
+
+	#[test]
+	fn backoff_with_jitter() {
+		let start = Instant::now();
+		let mut scheduler = PollScheduler::new(
+			pairs(),
+			Duration::from_secs(60),
+			Duration::from_secs(10),
+			Duration::from_secs(300),
+			start,
+		);
+		assert_eq!(scheduler.backoff(1), Duration::from_secs(10));
+		assert_eq!(scheduler.backoff(2), Duration::from_secs(20));
+		assert_eq!(scheduler.backoff(5), Duration::from_secs(160));
+		assert_eq!(scheduler.backoff(6), Duration::from_secs(300));
+		assert_eq!(scheduler.backoff(100), Duration::from_secs(300));
+
+		for failures in 1..8 {
+			let delay = scheduler.failed("USD", "CNY", start);
+			let backoff = scheduler.backoff(failures);
+			assert!(delay >= backoff / 2 && delay <= backoff);
+		}
+		assert!(!scheduler
+			.due(start + Duration::from_secs(100), None)
+			.contains(&("USD".to_owned(), "CNY".to_owned())));
+
+		// a success resets the backoff
+		scheduler.succeeded("USD", "CNY", start);
+		let delay = scheduler.failed("USD", "CNY", start);
+		assert!(delay <= Duration::from_secs(10));
+	}
+}
@@ -0,0 +1,67 @@
+// Copyright 2019 The Gotts Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Types and traits that should be provided by a price source
+
+use futures::future::{self, Future};
+use serde::{Deserialize, Serialize};
+
+use crate::alphavantage::ExchangeRateResult;
+use crate::error::Error;
+
+/// A future resolving to the rate quoted by a price source.
+pub type RateFuture = Box<dyn Future<Item = ExchangeRateResult, Error = Error> + Send>;
+
+/// A data vendor able to quote the exchange rate of a currency pair.
+/// Implementations are expected to be cheap to share between threads, the
+/// same instance is queried by the polling daemon and the API handlers.
+pub trait PriceSource: Send + Sync {
+	/// Unique name of this source, used as the key in the registry and
+	/// stored along with each reading.
+	fn name(&self) -> &str;
+
+	/// Query the current exchange rate from `from` to `to`.
+	fn get_rate(&self, from: &str, to: &str) -> Result<ExchangeRateResult, Error>;
+
+	/// Query the current exchange rate from `from` to `to` asynchronously,
+	/// on a tokio runtime. The default implementation runs the blocking query.
+	fn get_rate_async(&self, from: &str, to: &str) -> RateFuture {
+		Box::new(future::result(self.get_rate(from, to)))
+	}
+
+	/// Number of queries this source accepts right now, `None` if it's not
+	/// rate limited.
+	fn available_calls(&self) -> Option<usize> {
+		None
+	}
+}
+
+/// A rate as reported by a single price source.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SourceReading {
+	/// Name of the price source.
+	pub source: String,
+	/// The rate reported by this source.
+	pub rate: ExchangeRateResult,
+}
+
+/// The median rate of a currency pair, together with the readings it was
+/// computed from.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AggregatedRate {
+	/// Median of all readings, dated with the most recent reading.
+	pub rate: ExchangeRateResult,
+	/// Per-source readings used to compute the median.
+	pub readings: Vec<SourceReading>,
+}
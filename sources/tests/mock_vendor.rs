@@ -0,0 +1,101 @@
+// Copyright 2019 The Gotts Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! The price sources pipeline against the mock Alpha Vantage API.
+
+use gotts_oracle_alphavantage as alphavantage;
+use gotts_oracle_sources as sources;
+
+use self::alphavantage::mock::{Fixtures, MockServer};
+use self::sources::{Aggregator, Error, RateLimitedSource, SourceRegistry};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::runtime::Runtime;
+
+fn start_mock() -> MockServer {
+	let fixtures = Fixtures::from_dir(concat!(
+		env!("CARGO_MANIFEST_DIR"),
+		"/../alphavantage/tests/mock"
+	))
+	.unwrap();
+	MockServer::start(&([127, 0, 0, 1], 0).into(), fixtures).unwrap()
+}
+
+fn aggregator(mock: &MockServer, per_minute: u32) -> Aggregator {
+	let client =
+		alphavantage::Client::with_endpoint("demo", &mock.endpoint(), Duration::from_secs(10));
+	let mut registry = SourceRegistry::new();
+	registry
+		.register(Arc::new(RateLimitedSource::new(
+			Arc::new(client),
+			per_minute,
+			per_minute,
+		)))
+		.unwrap();
+	Aggregator::new(registry, 1)
+}
+
+#[test]
+fn aggregate_recorded_rates() {
+	let mock = start_mock();
+	let aggregator = aggregator(&mock, 5);
+
+	let aggregated = aggregator.aggregate("EUR", "USD").unwrap();
+	assert_eq!(aggregated.rate.rate, 1.16665014);
+	assert_eq!(aggregated.readings.len(), 1);
+
+	let mut runtime = Runtime::new().unwrap();
+	let aggregated = runtime
+		.block_on(aggregator.aggregate_async("USD", "CNY"))
+		.unwrap();
+	assert_eq!(aggregated.rate.to, "CNY");
+	assert_eq!(aggregated.rate.rate, 6.8746);
+	assert_eq!(mock.requests(), 2);
+}
+
+#[test]
+fn vendor_errors_and_throttling() {
+	let mock = start_mock();
+	let aggregator = aggregator(&mock, 5);
+	let insufficient = |pair: &str| Error::InsufficientSources {
+		pair: pair.to_owned(),
+		required: 1,
+		got: 0,
+	};
+
+	// an error message of the vendor fails the source
+	assert_eq!(
+		aggregator.aggregate("USD", "XXX").unwrap_err(),
+		insufficient("USD2XXX")
+	);
+	assert_eq!(aggregator.available_calls(), Some(4));
+
+	// so does an HTTP error, whatever its body, without draining the rate
+	// limiter
+	assert_eq!(
+		aggregator.aggregate("USD", "GBP").unwrap_err(),
+		insufficient("USD2GBP")
+	);
+	assert_eq!(aggregator.available_calls(), Some(3));
+
+	// a throttled vendor drains the rate limiter, so that no more calls are
+	// made until it's refilled
+	assert_eq!(
+		aggregator.aggregate("USD", "JPY").unwrap_err(),
+		insufficient("USD2JPY")
+	);
+	assert_eq!(aggregator.available_calls(), Some(0));
+	assert!(aggregator.aggregate("EUR", "USD").is_err());
+	assert_eq!(mock.requests(), 3);
+}
@@ -0,0 +1,45 @@
+// Copyright 2019 The Gotts Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Parsing of the command line arguments shared by the subcommands
+
+use chrono::{DateTime, Duration, NaiveDate, TimeZone, Utc};
+
+/// Pairs given as "EUR2USD,USD2CNY"
+pub fn parse_pairs(pairs: &str) -> Option<Vec<(String, String)>> {
+	pairs
+		.split(',')
+		.map(|pair| {
+			let codes: Vec<&str> = pair.trim().split('2').collect();
+			match codes.as_slice() {
+				[from, to] if !from.is_empty() && !to.is_empty() => {
+					Some((from.to_uppercase(), to.to_uppercase()))
+				}
+				_ => None,
+			}
+		})
+		.collect()
+}
+
+/// Start of a day given as "YYYY-MM-DD"
+pub fn parse_day(day: &str) -> Option<DateTime<Utc>> {
+	NaiveDate::parse_from_str(day, "%Y-%m-%d")
+		.ok()
+		.map(|day| Utc.from_utc_date(&day).and_hms(0, 0, 0))
+}
+
+/// Last second of a day given as "YYYY-MM-DD"
+pub fn parse_end_of_day(day: &str) -> Option<DateTime<Utc>> {
+	parse_day(day).map(|day| day + Duration::days(1) - Duration::seconds(1))
+}
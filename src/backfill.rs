@@ -0,0 +1,259 @@
+// Copyright 2019 The Gotts Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! The backfill command, populating the oracle database with the daily or
+//! intraday historical series of the vendor. Each pair is committed once
+//! backfilled, and the history already stored is skipped, so that the
+//! command can be run again after an interruption.
+
+use chrono::{Duration, Utc};
+use clap::ArgMatches;
+use failure::err_msg;
+use std::{thread, time};
+
+use crate::alphavantage::time_series::{IntradayInterval, OutputSize};
+use crate::alphavantage::{self, ExchangeRateResult};
+use crate::args::{parse_day, parse_end_of_day, parse_pairs};
+use crate::{alpha_vantage_client, instantiate_oracle};
+use gotts_oracle_config::ServerConfig;
+use gotts_oracle_lib::backfill;
+use gotts_oracle_sources::TokenBucket;
+
+/// Digital currencies, whose history comes from the digital currency series
+/// instead of the FX ones.
+const DIGITAL_CURRENCIES: &[&str] = &["BTC", "ETH", "LTC", "XRP", "BCH"];
+
+/// Attempts of a vendor call, when it's throttled.
+const MAX_ATTEMPTS: usize = 3;
+
+/// Time span of the historical bars.
+#[derive(Debug, Clone, Copy)]
+enum Granularity {
+	Daily,
+	Intraday(IntradayInterval),
+}
+
+impl Granularity {
+	fn from_arg(arg: &str) -> Option<Granularity> {
+		match arg {
+			"daily" => Some(Granularity::Daily),
+			"60min" => Some(Granularity::Intraday(IntradayInterval::SixtyMinutes)),
+			"30min" => Some(Granularity::Intraday(IntradayInterval::ThirtyMinutes)),
+			"15min" => Some(Granularity::Intraday(IntradayInterval::FifteenMinutes)),
+			"5min" => Some(Granularity::Intraday(IntradayInterval::FiveMinutes)),
+			"1min" => Some(Granularity::Intraday(IntradayInterval::OneMinute)),
+			_ => None,
+		}
+	}
+
+	fn length(self) -> Duration {
+		match self {
+			Granularity::Daily => Duration::days(1),
+			Granularity::Intraday(interval) => Duration::minutes(match interval {
+				IntradayInterval::OneMinute => 1,
+				IntradayInterval::FiveMinutes => 5,
+				IntradayInterval::FifteenMinutes => 15,
+				IntradayInterval::ThirtyMinutes => 30,
+				IntradayInterval::SixtyMinutes => 60,
+			}),
+		}
+	}
+}
+
+/// Handles the backfill command, returns the exit code.
+pub fn backfill_command(args: &ArgMatches<'_>, config: ServerConfig) -> i32 {
+	let pairs = match args.value_of("pairs") {
+		Some(pairs) => match parse_pairs(pairs) {
+			Some(pairs) => pairs,
+			None => {
+				eprintln!("Invalid pairs '{}', expecting i.e. EUR2USD,USD2CNY", pairs);
+				return 1;
+			}
+		},
+		None => config.pairs.pairs(),
+	};
+	let start = match args.value_of("from").and_then(parse_day) {
+		Some(day) => day,
+		None => {
+			eprintln!("Invalid or missing --from day, expecting YYYY-MM-DD");
+			return 1;
+		}
+	};
+	let end = match args.value_of("to") {
+		Some(day) => match parse_end_of_day(day) {
+			Some(day) => day,
+			None => {
+				eprintln!("Invalid --to day '{}', expecting YYYY-MM-DD", day);
+				return 1;
+			}
+		},
+		None => Utc::now(),
+	};
+	let granularity = match Granularity::from_arg(args.value_of("interval").unwrap_or("daily")) {
+		Some(granularity) => granularity,
+		None => {
+			eprintln!("Invalid --interval, use 'gotts_oracle help backfill' for details");
+			return 1;
+		}
+	};
+
+	let client = alpha_vantage_client(&config);
+	let mut bucket = TokenBucket::new(
+		config.scheduler.alpha_vantage_burst,
+		config.scheduler.alpha_vantage_calls_per_minute,
+		time::Instant::now(),
+	);
+	let oracle = instantiate_oracle(config, "backfill").expect("instantiate_oracle failed");
+
+	let mut failures = 0;
+	for (from, to) in pairs {
+		let id = format!("{}2{}", from, to);
+		let observations = match fetch_history(&client, &mut bucket, &from, &to, granularity) {
+			Ok(observations) => observations
+				.into_iter()
+				.filter(|r| r.date >= start && r.date <= end)
+				.collect(),
+			Err(e) => {
+				error!("backfill {} failed: {}", id, e);
+				eprintln!("{}: backfill failed: {}", id, e);
+				failures += 1;
+				continue;
+			}
+		};
+
+		let mut oracle = oracle.lock();
+		match backfill::backfill(&mut *oracle, &id, observations) {
+			Ok(stats) => {
+				info!("backfill {}: {:?}", id, stats);
+				eprintln!(
+					"{}: {} observations backfilled, {} already stored",
+					id, stats.stored, stats.skipped
+				);
+			}
+			Err(e) => {
+				error!("backfill {} failed: {}", id, e);
+				eprintln!("{}: backfill failed: {}", id, e);
+				failures += 1;
+			}
+		}
+	}
+
+	if failures > 0 {
+		1
+	} else {
+		0
+	}
+}
+
+fn is_digital(code: &str) -> bool {
+	DIGITAL_CURRENCIES.contains(&code)
+}
+
+/// The close rate of every historical bar of a pair, dated at the end of the
+/// bar, or at the last refresh for the bar in progress.
+fn fetch_history(
+	client: &alphavantage::Client,
+	bucket: &mut TokenBucket,
+	from: &str,
+	to: &str,
+	granularity: Granularity,
+) -> Result<Vec<ExchangeRateResult>, failure::Error> {
+	let (last_refreshed, bars, inverted) = match (is_digital(from), is_digital(to)) {
+		(false, false) => {
+			let series = call(bucket, || match granularity {
+				Granularity::Daily => client.get_fx_daily(from, to, OutputSize::Full),
+				Granularity::Intraday(interval) => {
+					client.get_fx_intraday(from, to, interval, OutputSize::Full)
+				}
+			})?;
+			let bars: Vec<_> = series
+				.entries
+				.iter()
+				.map(|e| (e.date.with_timezone(&Utc), e.close))
+				.collect();
+			(series.last_refreshed.with_timezone(&Utc), bars, false)
+		}
+		(true, true) => {
+			return Err(err_msg(
+				"no historical series between two digital currencies",
+			));
+		}
+		(from_digital, _) => {
+			// a digital currency is quoted in the market currency
+			let (symbol, market) = if from_digital { (from, to) } else { (to, from) };
+			let (last_refreshed, bars) = match granularity {
+				Granularity::Daily => {
+					let series =
+						call(bucket, || client.get_digital_currency_daily(symbol, market))?;
+					let bars: Vec<_> = series
+						.entries
+						.iter()
+						.map(|e| (e.date.with_timezone(&Utc), e.close))
+						.collect();
+					(series.last_refreshed.with_timezone(&Utc), bars)
+				}
+				Granularity::Intraday(interval) => {
+					let series = call(bucket, || {
+						client.get_crypto_intraday(symbol, market, interval, OutputSize::Full)
+					})?;
+					let bars: Vec<_> = series
+						.entries
+						.iter()
+						.map(|e| (e.date.with_timezone(&Utc), e.close))
+						.collect();
+					(series.last_refreshed.with_timezone(&Utc), bars)
+				}
+			};
+			(last_refreshed, bars, !from_digital)
+		}
+	};
+
+	let mut observations = Vec::with_capacity(bars.len());
+	for (date, close) in bars {
+		if close <= 0.0 {
+			return Err(err_msg("invalid close rate in the historical series"));
+		}
+		let end = date + granularity.length() - Duration::seconds(1);
+		observations.push(ExchangeRateResult {
+			from: from.to_owned(),
+			to: to.to_owned(),
+			rate: if inverted { 1.0 / close } else { close },
+			date: end.min(last_refreshed),
+		});
+	}
+	Ok(observations)
+}
+
+/// Call the vendor within the rate limits of the key, and retry the calls
+/// throttled anyway a minute later.
+fn call<T, F>(bucket: &mut TokenBucket, f: F) -> Result<T, alphavantage::Error>
+where
+	F: Fn() -> Result<T, alphavantage::Error>,
+{
+	let mut attempts = 1;
+	loop {
+		while !bucket.try_take(time::Instant::now()) {
+			thread::sleep(time::Duration::from_secs(1));
+		}
+		match f() {
+			Err(alphavantage::Error::RateLimited { message }) if attempts < MAX_ATTEMPTS => {
+				warn!("backfill throttled, retrying in a minute: {}", message);
+				bucket.drain(time::Instant::now());
+				thread::sleep(time::Duration::from_secs(60));
+				attempts += 1;
+			}
+			result => return result,
+		}
+	}
+}
@@ -0,0 +1,397 @@
+// Copyright 2019 The Gotts Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! The export and import commands, streaming the stored exchange rates to
+//! and from CSV or JSON Lines files, for audits and for migrating between
+//! oracle instances. A file name of "-" stands for the standard output or
+//! input.
+
+use chrono::Utc;
+use clap::ArgMatches;
+use failure::format_err;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Write};
+
+use crate::alphavantage::ExchangeRateResult;
+use crate::args::{parse_day, parse_end_of_day, parse_pairs};
+use crate::instantiate_oracle;
+use gotts_oracle_config::ServerConfig;
+use gotts_oracle_lib::OracleBackend;
+
+/// Number of exchange rates imported per batch
+const DEFAULT_BATCH_SIZE: usize = 1000;
+
+/// Format of the exported and imported files
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Format {
+	Csv,
+	JsonLines,
+}
+
+impl Format {
+	/// The format given as argument, or else the one of the file extension,
+	/// JSON Lines by default.
+	fn from_args(format: Option<&str>, path: &str) -> Format {
+		match format {
+			Some("csv") => Format::Csv,
+			Some(_) => Format::JsonLines,
+			None if path.to_lowercase().ends_with(".csv") => Format::Csv,
+			None => Format::JsonLines,
+		}
+	}
+}
+
+/// Handles the export command, returns the exit code.
+pub fn export_command(args: &ArgMatches<'_>, config: ServerConfig) -> i32 {
+	let path = args.value_of("output").unwrap_or("-");
+	let format = Format::from_args(args.value_of("format"), path);
+	let pairs = match args.value_of("pairs") {
+		Some(pairs) => match parse_pairs(pairs) {
+			Some(pairs) => Some(pairs),
+			None => {
+				eprintln!("Invalid pairs '{}', expecting i.e. EUR2USD,USD2CNY", pairs);
+				return 1;
+			}
+		},
+		None => None,
+	};
+	let start = match args.value_of("from").map(parse_day) {
+		Some(None) => {
+			eprintln!("Invalid --from day, expecting YYYY-MM-DD");
+			return 1;
+		}
+		Some(day) => day,
+		None => None,
+	};
+	let end = match args.value_of("to").map(parse_end_of_day) {
+		Some(None) => {
+			eprintln!("Invalid --to day, expecting YYYY-MM-DD");
+			return 1;
+		}
+		Some(day) => day,
+		None => None,
+	};
+
+	let oracle = instantiate_oracle(config, "export").expect("instantiate_oracle failed");
+	let oracle = oracle.lock();
+	let rates = oracle.iter().filter(|rate| {
+		if let Some(pairs) = &pairs {
+			if !pairs
+				.iter()
+				.any(|(from, to)| *from == rate.from && *to == rate.to)
+			{
+				return false;
+			}
+		}
+		match (start, end) {
+			(Some(start), _) if rate.date < start => false,
+			(_, Some(end)) if rate.date > end => false,
+			_ => true,
+		}
+	});
+
+	match export(rates, format, path) {
+		Ok(exported) => {
+			info!("exported {} exchange rates to {}", exported, path);
+			eprintln!("{} exchange rates exported", exported);
+			0
+		}
+		Err(e) => {
+			error!("export to {} failed: {}", path, e);
+			eprintln!("Export failed: {}", e);
+			1
+		}
+	}
+}
+
+/// Handles the import command, returns the exit code.
+pub fn import_command(args: &ArgMatches<'_>, config: ServerConfig) -> i32 {
+	let path = args.value_of("input").unwrap_or("-");
+	let format = Format::from_args(args.value_of("format"), path);
+	let batch_size = match args.value_of("batch_size").map(str::parse::<usize>) {
+		Some(Ok(size)) if size > 0 => size,
+		None => DEFAULT_BATCH_SIZE,
+		_ => {
+			eprintln!("Invalid --batch_size, expecting a positive number");
+			return 1;
+		}
+	};
+
+	let oracle = instantiate_oracle(config, "import").expect("instantiate_oracle failed");
+	let mut oracle = oracle.lock();
+	let result = read_rates(format, path).and_then(|rates| import(&mut *oracle, rates, batch_size));
+
+	match result {
+		Ok(imported) => {
+			info!("imported {} exchange rates from {}", imported, path);
+			eprintln!("{} exchange rates imported", imported);
+			0
+		}
+		Err(e) => {
+			error!("import from {} failed: {}", path, e);
+			eprintln!(
+				"Import failed, the batches before the error are kept: {}",
+				e
+			);
+			1
+		}
+	}
+}
+
+/// Write the exchange rates to `path`, returns the number written.
+fn export<I>(rates: I, format: Format, path: &str) -> Result<usize, failure::Error>
+where
+	I: Iterator<Item = ExchangeRateResult>,
+{
+	let output: Box<dyn Write> = if path == "-" {
+		Box::new(io::stdout())
+	} else {
+		Box::new(File::create(path)?)
+	};
+	let output = BufWriter::new(output);
+
+	let mut exported = 0;
+	match format {
+		Format::Csv => {
+			let mut writer = csv::Writer::from_writer(output);
+			for rate in rates {
+				writer.serialize(&rate)?;
+				exported += 1;
+			}
+			writer.flush()?;
+		}
+		Format::JsonLines => {
+			let mut writer = output;
+			for rate in rates {
+				serde_json::to_writer(&mut writer, &rate)?;
+				writer.write_all(b"\n")?;
+				exported += 1;
+			}
+			writer.flush()?;
+		}
+	}
+	Ok(exported)
+}
+
+/// Save the exchange rates into the oracle, `batch_size` per batch, returns
+/// the number saved. The batches committed before an error are kept.
+fn import<T: ?Sized>(
+	oracle: &mut T,
+	mut rates: RateResults,
+	batch_size: usize,
+) -> Result<usize, failure::Error>
+where
+	T: OracleBackend,
+{
+	let mut imported = 0;
+	loop {
+		let mut batch = oracle.batch()?;
+		let mut saved = 0;
+		while saved < batch_size {
+			match rates.next() {
+				Some(rate) => {
+					let rate = rate?;
+					batch.save(rate.date, rate)?;
+					saved += 1;
+				}
+				None => break,
+			}
+		}
+		batch.commit()?;
+		imported += saved;
+		if saved < batch_size {
+			return Ok(imported);
+		}
+	}
+}
+
+type RateResults = Box<dyn Iterator<Item = Result<ExchangeRateResult, failure::Error>>>;
+
+/// Stream the exchange rates of `path`, checked one by one.
+fn read_rates(format: Format, path: &str) -> Result<RateResults, failure::Error> {
+	let input: Box<dyn Read> = if path == "-" {
+		Box::new(io::stdin())
+	} else {
+		Box::new(File::open(path)?)
+	};
+	let input = BufReader::new(input);
+
+	let rates: RateResults = match format {
+		Format::Csv => Box::new(
+			csv::Reader::from_reader(input)
+				.into_deserialize::<ExchangeRateResult>()
+				.enumerate()
+				.map(|(i, rate)| {
+					// the header is the first line
+					let rate = rate.map_err(|e| format_err!("line {}: {}", i + 2, e))?;
+					check(rate).map_err(|e| format_err!("line {}: {}", i + 2, e))
+				}),
+		),
+		Format::JsonLines => Box::new(
+			input
+				.lines()
+				.enumerate()
+				.filter(|(_, line)| match line {
+					Ok(line) => !line.trim().is_empty(),
+					Err(_) => true,
+				})
+				.map(|(i, line)| {
+					let rate = serde_json::from_str::<ExchangeRateResult>(&line?)
+						.map_err(|e| format_err!("line {}: {}", i + 1, e))?;
+					check(rate).map_err(|e| format_err!("line {}: {}", i + 1, e))
+				}),
+		),
+	};
+	Ok(rates)
+}
+
+/// Sanity check of an imported exchange rate
+fn check(rate: ExchangeRateResult) -> Result<ExchangeRateResult, failure::Error> {
+	if rate.from.is_empty() || rate.to.is_empty() {
+		return Err(format_err!("missing currency code"));
+	}
+	if !rate.rate.is_finite() || rate.rate <= 0.0 {
+		return Err(format_err!("invalid rate {}", rate.rate));
+	}
+	if rate.date > Utc::now() {
+		return Err(format_err!("rate dated in the future, at {}", rate.date));
+	}
+	Ok(rate)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use chrono::TimeZone;
+	use gotts_oracle_lib::LMDBBackend;
+	use std::fs;
+	use std::path::PathBuf;
+
+	/// A store in a temp directory, removed once dropped
+	struct TempStore {
+		root: PathBuf,
+		oracle: LMDBBackend,
+	}
+
+	impl TempStore {
+		fn new(name: &str) -> TempStore {
+			let root = std::env::temp_dir().join(format!("gotts_oracle_data_{}", name));
+			let _ = fs::remove_dir_all(&root);
+			let config = ServerConfig {
+				db_root: root.join("db").to_string_lossy().into_owned(),
+				..ServerConfig::default()
+			};
+			TempStore {
+				oracle: LMDBBackend::new(config).unwrap(),
+				root,
+			}
+		}
+
+		fn file(&self, name: &str, content: &str) -> String {
+			let path = self.root.join(name);
+			fs::write(&path, content).unwrap();
+			path.to_string_lossy().into_owned()
+		}
+
+		fn rates(&self) -> Vec<ExchangeRateResult> {
+			self.oracle.iter().collect()
+		}
+	}
+
+	impl Drop for TempStore {
+		fn drop(&mut self) {
+			let _ = fs::remove_dir_all(&self.root);
+		}
+	}
+
+	fn rate(from: &str, to: &str, rate: f64, timestamp: i64) -> ExchangeRateResult {
+		ExchangeRateResult {
+			from: from.to_owned(),
+			to: to.to_owned(),
+			rate,
+			date: Utc.timestamp(timestamp, 0),
+		}
+	}
+
+	fn error_of(format: Format, path: &str) -> String {
+		read_rates(format, path)
+			.unwrap()
+			.filter_map(|rate| rate.err())
+			.map(|e| e.to_string())
+			.next()
+			.unwrap()
+	}
+
+	#[test]
+	fn export_import_round_trip() {
+		let mut source = TempStore::new("source");
+		let rates = vec![
+			rate("EUR", "USD", 1.1234, 1_562_050_200),
+			rate("USD", "CNY", 6.8746, 1_562_050_200),
+			rate("USD", "CNY", 6.8751, 1_562_050_260),
+		];
+		{
+			let mut batch = source.oracle.batch().unwrap();
+			for rate in &rates {
+				batch.save(rate.date, rate.clone()).unwrap();
+			}
+			batch.commit().unwrap();
+		}
+
+		for (format, name) in vec![
+			(Format::Csv, "rates.csv"),
+			(Format::JsonLines, "rates.jsonl"),
+		] {
+			let path = source.root.join(name).to_string_lossy().into_owned();
+			assert_eq!(export(source.oracle.iter(), format, &path).unwrap(), 3);
+
+			let mut target = TempStore::new(&format!("target_{:?}", format));
+			let imported = import(&mut target.oracle, read_rates(format, &path).unwrap(), 2);
+			assert_eq!(imported.unwrap(), 3);
+			assert_eq!(target.rates(), source.rates());
+		}
+	}
+
+	#[test]
+	fn parse_errors() {
+		let mut store = TempStore::new("errors");
+		let csv = store.file(
+			"rates.csv",
+			"from,to,rate,date\n\
+			 USD,CNY,6.8746,2019-07-02T06:50:00Z\n\
+			 USD,CNY,abc,2019-07-02T06:51:00Z\n",
+		);
+		assert!(error_of(Format::Csv, &csv).starts_with("line 3:"));
+		let csv = store.file(
+			"rates.csv",
+			"from,to,rate,date\nUSD,CNY,-1,2019-07-02T06:50:00Z\n",
+		);
+		assert_eq!(error_of(Format::Csv, &csv), "line 2: invalid rate -1");
+
+		let valid = r#"{"from":"USD","to":"CNY","rate":6.8746,"date":"2019-07-02T06:50:00Z"}"#;
+		let jsonl = store.file("rates.jsonl", &format!("{}\n\n{{\"from\":\n", valid));
+		assert!(error_of(Format::JsonLines, &jsonl).starts_with("line 3:"));
+		let future = r#"{"from":"USD","to":"CNY","rate":6.8,"date":"2999-01-01T00:00:00Z"}"#;
+		let jsonl = store.file("rates.jsonl", &format!("{}\n{}\n", valid, future));
+		assert!(error_of(Format::JsonLines, &jsonl).starts_with("line 2: rate dated in the future"));
+
+		// the batches before the error are kept
+		let rates = read_rates(Format::JsonLines, &jsonl).unwrap();
+		assert!(import(&mut store.oracle, rates, 1).is_err());
+		assert_eq!(
+			store.rates(),
+			vec![rate("USD", "CNY", 6.8746, 1_562_050_200)]
+		);
+	}
+}
@@ -0,0 +1,119 @@
+// Copyright 2019 The Gotts Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! The db command, maintaining the oracle database. A snapshot or a check can
+//! be done while the server is running, a restore needs the server to be
+//! stopped.
+
+use chrono::Utc;
+use clap::ArgMatches;
+use std::path::Path;
+
+use crate::instantiate_oracle;
+use gotts_oracle_config::ServerConfig;
+use gotts_oracle_lib::schema;
+use gotts_oracle_lib::{restore_snapshot, LMDBBackend, SNAPSHOT_DIR};
+
+/// Handles the db command, returns the exit code.
+pub fn db_command(args: &ArgMatches<'_>, config: ServerConfig) -> i32 {
+	match args.subcommand() {
+		("snapshot", Some(args)) => snapshot(args, config),
+		("restore", Some(args)) => restore(args, config),
+		("check", _) => check(config),
+		_ => {
+			println!("Subcommand required, use 'gotts_oracle help db' for details");
+			1
+		}
+	}
+}
+
+fn snapshot(args: &ArgMatches<'_>, config: ServerConfig) -> i32 {
+	let path = match args.value_of("path") {
+		Some(path) => path.to_owned(),
+		None => Path::new(&config.db_root)
+			.join(SNAPSHOT_DIR)
+			.join(Utc::now().format("%Y%m%d-%H%M%S").to_string())
+			.to_string_lossy()
+			.to_string(),
+	};
+
+	let oracle = instantiate_oracle(config, "snapshot").expect("instantiate_oracle failed");
+	let result = oracle.lock().snapshot(&path);
+	match result {
+		Ok(size) => {
+			println!("Database snapshot of {} bytes written to {}", size, path);
+			0
+		}
+		Err(e) => {
+			error!("database snapshot to {} failed: {}", path, e);
+			println!("Database snapshot failed: {}", e);
+			1
+		}
+	}
+}
+
+fn restore(args: &ArgMatches<'_>, config: ServerConfig) -> i32 {
+	let path = args.value_of("path").unwrap();
+	match restore_snapshot(&config, path) {
+		Ok(replaced) => {
+			println!("Database restored from {}", path);
+			if let Some(replaced) = replaced {
+				println!("The replaced database is kept in {}", replaced.display());
+			}
+			0
+		}
+		Err(e) => {
+			error!("database restore from {} failed: {}", path, e);
+			println!("Database restore failed: {}", e);
+			1
+		}
+	}
+}
+
+fn check(config: ServerConfig) -> i32 {
+	if !LMDBBackend::exists(config.clone()) {
+		println!("No database in {}", config.db_root);
+		return 1;
+	}
+	// the database is checked as it is, it's only migrated by the oracle
+	let report = match LMDBBackend::open_unmigrated(config).and_then(|oracle| oracle.check()) {
+		Ok(report) => report,
+		Err(e) => {
+			error!("database check failed: {}", e);
+			println!("Database check failed: {}", e);
+			return 1;
+		}
+	};
+
+	println!(
+		"Database schema version {}, {} entries decoded, {} undecodable",
+		report.schema_version,
+		report.decoded,
+		report.undecodable.len()
+	);
+	if report.schema_version < schema::SCHEMA_VERSION {
+		println!(
+			"The database is migrated to schema version {} when the oracle starts",
+			schema::SCHEMA_VERSION
+		);
+	}
+	for (key, e) in report.undecodable.iter() {
+		println!("{}: {}", key, e);
+	}
+	if report.undecodable.is_empty() {
+		0
+	} else {
+		1
+	}
+}
@@ -16,24 +16,45 @@
 extern crate clap;
 #[macro_use]
 extern crate log;
-use alphavantage::exchange_rate::ExchangeRateResult;
 use clap::{App, ArgMatches};
-use config::{GlobalConfig, ServerConfig};
+use config::{CandlesConfig, GlobalConfig, SchedulerConfig, ServerConfig};
 use gotts_oracle_alphavantage as alphavantage;
 use gotts_oracle_api as api;
 use gotts_oracle_config as config;
-use gotts_oracle_lib::{Error, LMDBBackend, OracleBackend, OracleInst};
-
-use gotts_oracle_util::init_logger;
-use gotts_oracle_util::Mutex;
-
-use chrono::{DateTime, Duration, Timelike, Utc};
+use gotts_oracle_lib::candles;
+use gotts_oracle_lib::{
+	Error, LMDBBackend, OracleBackend, OracleInst, OracleKeypair, QuoteValidator, SNAPSHOT_DIR,
+};
+use gotts_oracle_sources::{
+	Aggregator, PollControl, PollScheduler, RateLimitedSource, SourceRegistry,
+};
+
+use gotts_oracle_util::{get_first_line, init_logger};
+use gotts_oracle_util::{Mutex, RwLock};
+
+use chrono::{DateTime, Duration, Utc};
 use colored::*;
 use futures;
-use futures::executor::block_on;
-use gotts_oracle_alphavantage::exchange_rate::ExchangeRate;
+use futures::compat::Future01CompatExt;
+use futures::future::{FutureExt, TryFutureExt};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
-use std::{thread, time};
+use std::time;
+use tokio::runtime::current_thread::Runtime;
+use tokio::timer::Delay;
+
+#[cfg(unix)]
+use signal_hook::{iterator::Signals, SIGHUP, SIGTERM};
+
+mod args;
+mod backfill;
+mod data;
+mod db;
+
+/// Minimum number of price sources which must answer before a median price
+/// is stored. Only one vendor is supported for the moment.
+const MIN_PRICE_SOURCES: usize = 1;
 
 // include build information
 pub mod built_info {
@@ -113,6 +134,25 @@ fn real_main() -> i32 {
 		// server commands and options
 		("server", Some(server_args)) => server_command(Some(server_args), oracle_config.unwrap()),
 
+		// backfill command
+		("backfill", Some(backfill_args)) => backfill::backfill_command(
+			backfill_args,
+			oracle_config.unwrap().members.unwrap().server,
+		),
+
+		// export and import commands
+		("export", Some(export_args)) => {
+			data::export_command(export_args, oracle_config.unwrap().members.unwrap().server)
+		}
+		("import", Some(import_args)) => {
+			data::import_command(import_args, oracle_config.unwrap().members.unwrap().server)
+		}
+
+		// db command
+		("db", Some(db_args)) => {
+			db::db_command(db_args, oracle_config.unwrap().members.unwrap().server)
+		}
+
 		// clean command
 		("clean", _) => {
 			let db_root_path = oracle_config.unwrap().members.unwrap().server.db_root;
@@ -164,11 +204,12 @@ fn config_command_server(file_name: &str) {
 fn server_command(server_args: Option<&ArgMatches<'_>>, global_config: GlobalConfig) -> i32 {
 	// just get defaults from the global config
 	let server_config = global_config.members.as_ref().unwrap().server.clone();
+	let config_file = global_config.config_file_path.clone();
 
 	if let Some(a) = server_args {
 		match a.subcommand() {
 			("run", _) => {
-				start_server(server_config);
+				start_server(server_config, config_file);
 			}
 			("", _) => {
 				println!("Subcommand required, use 'gotts_oracle help server' for details");
@@ -182,15 +223,178 @@ fn server_command(server_args: Option<&ArgMatches<'_>>, global_config: GlobalCon
 			}
 		}
 	} else {
-		start_server(server_config);
+		start_server(server_config, config_file);
 	}
 	0
 }
 
-fn start_server(config: ServerConfig) {
+fn start_server(config: ServerConfig, config_file: Option<PathBuf>) {
 	let oracle =
 		instantiate_oracle(config.clone(), "alpha_vantage").expect("instantiate_oracle failed");
 
+	//load the oracle key, which signs the aggregated prices
+	let oracle_key_path =
+		config::config::get_oracle_key_path(config.oracle_key_path.as_ref().map(|p| p.as_str()))
+			.expect("oracle key path failed");
+	let keypair =
+		Arc::new(OracleKeypair::from_seed_file(&oracle_key_path).expect("load oracle key failed"));
+	warn!("oracle public key: {}", keypair.public_key());
+	println!(
+		"\noracle public key: {}",
+		keypair.public_key().bright_green()
+	);
+
+	//register all the price sources, within the rate limits of their keys
+	let mut registry = SourceRegistry::new();
+	registry
+		.register(Arc::new(RateLimitedSource::new(
+			Arc::new(alpha_vantage_client(&config)),
+			config.scheduler.alpha_vantage_calls_per_minute,
+			config.scheduler.alpha_vantage_burst,
+		)))
+		.expect("register price source failed");
+	let shared_aggregator = Arc::new(Aggregator::new(registry, MIN_PRICE_SOURCES));
+	let validator = Arc::new(QuoteValidator::new(config.validation.clone()));
+
+	//the candles retention and the polled pairs can be changed by the owner API
+	let candles = Arc::new(RwLock::new(config.candles.clone()));
+	let control = Arc::new(PollControl::new(config.pairs.pairs()));
+
+	//start api server
+	let oracle_bind_address = config.api_http_addr.clone();
+	println!(
+		"\ngotts oracle is serving on {}",
+		oracle_bind_address.bright_green()
+	);
+	//stop gracefully on SIGINT (Ctrl+C), SIGTERM (kill) being handled along with SIGHUP
+	let stop_state = Arc::new(AtomicBool::new(false));
+	{
+		let stop_state = stop_state.clone();
+		ctrlc::set_handler(move || {
+			warn!("Received a shutdown signal, stopping...");
+			stop_state.store(true, Ordering::SeqCst);
+		})
+		.expect("Error setting handler for SIGINT (Ctrl+C)");
+	}
+
+	let tls_config =
+		tls_config(&config).unwrap_or_else(|e| panic!("Invalid TLS configuration: {}", e));
+
+	let context = api::ApiContext {
+		oracle: oracle.clone(),
+		aggregator: shared_aggregator.clone(),
+		keypair,
+		validator: validator.clone(),
+		candles: candles.clone(),
+		control: control.clone(),
+	};
+	let api_config = api::ApiConfig {
+		addr: oracle_bind_address,
+		tls_config,
+		api_secret: get_first_line(config.api_secret_path.clone()),
+		rate_limit: config.rate_limit.clone(),
+		pairs: config.pairs.clone(),
+		max_staleness: Duration::seconds(config.max_staleness_secs as i64),
+		exchange_max_age: Duration::seconds(config.exchange_max_age_secs as i64),
+		snapshot_dir: Path::new(&config.db_root).join(SNAPSHOT_DIR),
+		config_file,
+	};
+	let res = api::start_rest_apis(context, api_config);
+
+	#[cfg(unix)]
+	handle_signals(
+		stop_state.clone(),
+		res.as_ref().ok().and_then(|(apis, _)| apis.tls_config()),
+	);
+
+	//the daemon polls the price sources concurrently, on a tokio runtime
+	let daemon = daemon_price_sources(
+		oracle.clone(),
+		shared_aggregator,
+		validator,
+		candles,
+		control,
+		config.scheduler.clone(),
+		stop_state,
+	);
+	let mut runtime = Runtime::new().expect("failed to create the daemon runtime");
+	runtime
+		.block_on(Box::pin(daemon.unit_error()).compat())
+		.expect("price sources daemon failed");
+
+	warn!("Shutting down...");
+	if let Ok((mut apis, handle)) = res {
+		apis.stop();
+		handle.join().expect("The thread being joined has panicked");
+	}
+
+	// the daemon commits its batches before it returns, and the API handlers
+	// are gone with the server thread, so that this is the last reference on
+	// the LMDB env, which is closed when dropped.
+	if Arc::strong_count(&oracle) > 1 {
+		warn!("the oracle database is still referenced, it's not closed");
+	}
+	drop(oracle);
+	warn!("Shutdown complete.");
+}
+
+/// The TLS configuration of the API server, checked to load, if a certificate
+/// is configured
+fn tls_config(config: &ServerConfig) -> Result<Option<api::TLSConfig>, api::Error> {
+	let tls_config = match (&config.tls_certificate_file, &config.tls_certificate_key) {
+		(Some(certificate), Some(key)) => api::TLSConfig::new(certificate.clone(), key.clone()),
+		(None, None) if config.tls_client_ca_file.is_none() => return Ok(None),
+		_ => {
+			return Err(api::ErrorKind::Argument(
+				"both tls_certificate_file and tls_certificate_key are required for TLS".to_owned(),
+			)
+			.into());
+		}
+	};
+	let tls_config = match &config.tls_client_ca_file {
+		Some(client_ca) => tls_config.with_client_ca(client_ca.clone()),
+		None => tls_config,
+	};
+	tls_config.validate()?;
+	Ok(Some(tls_config))
+}
+
+/// Handle the signals on a dedicated thread: SIGTERM stops the oracle
+/// gracefully, and SIGHUP reloads the TLS configuration of the API server,
+/// i.e. once its certificate is renewed.
+#[cfg(unix)]
+fn handle_signals(stop_state: Arc<AtomicBool>, tls_config: Option<api::ReloadableTLSConfig>) {
+	let signals =
+		Signals::new(&[SIGTERM, SIGHUP]).expect("Error setting handler for SIGTERM and SIGHUP");
+	std::thread::Builder::new()
+		.name("signals".to_string())
+		.spawn(move || {
+			for signal in signals.forever() {
+				match signal {
+					SIGTERM => {
+						warn!("Received a shutdown signal, stopping...");
+						stop_state.store(true, Ordering::SeqCst);
+					}
+					SIGHUP => match &tls_config {
+						Some(tls_config) => {
+							if let Err(e) = tls_config.reload() {
+								error!(
+									"TLS configuration reload failed, the current one is kept: {}",
+									e
+								);
+							}
+						}
+						None => warn!("Received SIGHUP, but the API server doesn't use TLS"),
+					},
+					_ => {}
+				}
+			}
+		})
+		.expect("failed to spawn the signals thread");
+}
+
+/// Create the Alpha Vantage client, with the configured key and endpoint
+pub fn alpha_vantage_client(config: &ServerConfig) -> alphavantage::Client {
 	//the api key integrated here is just for demo, with very limited access,
 	// please claim your own api key and set it as an environment variable before running.
 	// the free api key can be requested here: https://www.alphavantage.co/support/#api-key
@@ -198,6 +402,7 @@ fn start_server(config: ServerConfig) {
 	let default_alpha_vantage_api_key = "2BY6TAJHCM9Z7HQT";
 	let alpha_vantage_api_key = config
 		.alpha_vantage_api_key
+		.clone()
 		.unwrap_or_else(|| default_alpha_vantage_api_key.to_string());
 	if alpha_vantage_api_key == default_alpha_vantage_api_key {
 		println!(
@@ -208,34 +413,14 @@ fn start_server(config: ServerConfig) {
 		);
 	}
 
-	//create alphavantage client
-	let shared_client = Arc::new(alphavantage::Client::new(alpha_vantage_api_key.as_str()));
-
-	//start api server
-	let oracle_bind_address = config.api_http_addr.clone();
-	println!(
-		"\ngotts oracle is serving on {}",
-		oracle_bind_address.bright_green()
-	);
-	let res = api::start_rest_apis(
-		oracle.clone(),
-		shared_client.clone(),
-		oracle_bind_address,
-		None,
-	);
-
-	block_on(daemon_alpha_vantage(oracle, shared_client));
-
-	if let Ok(handle) = res {
-		handle.join().expect("The thread being joined has panicked");
-	}
-
-	// Just kill process for now, otherwise the process
-	// hangs around until sigint because the API server
-	// currently has no shutdown facility
-	warn!("Shutting down...");
-	thread::sleep(time::Duration::from_millis(1000));
-	warn!("Shutdown complete.");
+	alphavantage::Client::with_endpoint(
+		alpha_vantage_api_key.as_str(),
+		config
+			.alpha_vantage_endpoint
+			.as_ref()
+			.map_or(alphavantage::URL_ENDPOINT, |endpoint| endpoint.as_str()),
+		time::Duration::from_secs(config.vendor_timeout_secs),
+	)
 }
 
 /// Helper to create an instance of the LMDB oracle
@@ -248,115 +433,133 @@ pub fn instantiate_oracle(
 	Ok(Arc::new(Mutex::new(db_oracle)))
 }
 
-/// Daemon for Alpha Vantage exchange data query
-async fn daemon_alpha_vantage<T: ?Sized>(oracle: Arc<Mutex<T>>, client: Arc<alphavantage::Client>)
-where
+/// Daemon for exchange data query over all the price sources. The pairs are
+/// polled as scheduled, within the rate limits of the price sources, and the
+/// pairs due are queried concurrently. The commands of the owner API are
+/// applied on every round.
+async fn daemon_price_sources<T: ?Sized>(
+	oracle: Arc<Mutex<T>>,
+	aggregator: Arc<Aggregator>,
+	validator: Arc<QuoteValidator>,
+	candles_config: Arc<RwLock<CandlesConfig>>,
+	control: Arc<PollControl>,
+	scheduler_config: SchedulerConfig,
+	stop_state: Arc<AtomicBool>,
+) where
 	T: OracleBackend + Send + Sync + 'static,
 {
-	let currencies_a = vec!["EUR", "GBP", "BTC", "ETH"];
-	let currencies_b = vec!["CNY", "JPY", "CAD"];
-	let base_currencies_number = currencies_a.len() + currencies_b.len();
+	let mut scheduler = PollScheduler::new(
+		control.pairs(),
+		time::Duration::from_secs(scheduler_config.poll_interval_secs),
+		time::Duration::from_secs(scheduler_config.backoff_initial_secs),
+		time::Duration::from_secs(scheduler_config.backoff_max_secs),
+		time::Instant::now(),
+	);
 	let compact_interval = Duration::minutes(60);
 	let mut last_compact_time: DateTime<Utc> = Utc::now() - compact_interval;
-	loop {
-		let mut f = Vec::with_capacity(base_currencies_number);
-		for from in &currencies_a {
-			f.push(query_once_alpha_vantage(
-				oracle.clone(),
-				client.clone(),
-				from,
-				"USD",
-			));
+	while !stop_state.load(Ordering::SeqCst) {
+		for command in control.take_commands() {
+			debug!("daemon_price_sources: {:?}", command);
+			scheduler.apply(command, time::Instant::now());
 		}
-		for to in &currencies_b {
-			f.push(query_once_alpha_vantage(
+		let due = scheduler.due(time::Instant::now(), aggregator.available_calls());
+		let mut f = Vec::with_capacity(due.len());
+		for (from, to) in &due {
+			f.push(query_once_price_sources(
 				oracle.clone(),
-				client.clone(),
-				"USD",
+				aggregator.clone(),
+				validator.clone(),
+				from,
 				to,
 			));
 		}
 
 		let f_all = futures::future::join_all(f);
-		f_all.await;
-		debug!("daemon_alpha_vantage: query");
+		let results = f_all.await;
+		debug!("daemon_price_sources: {} pairs queried", due.len());
+
+		let now = time::Instant::now();
+		for ((from, to), result) in due.iter().zip(results) {
+			match result {
+				Ok(()) => scheduler.succeeded(from, to, now),
+				Err(e) => {
+					api::metrics::record_poll_failure(&format!("{}2{}", from, to));
+					let delay = scheduler.failed(from, to, now);
+					error!(
+						"query price sources failed on {}2{}, retry in {}s. {}",
+						from,
+						to,
+						delay.as_secs(),
+						e
+					);
+				}
+			}
+		}
 
-		// And compact in every 'compact_interval' minutes to avoid large history data storage
+		// And compact in every 'compact_interval' minutes to avoid large history data storage,
+		// the compacted history being rolled up into candles
 		let now_time: DateTime<Utc> = Utc::now();
 		if now_time.signed_duration_since(last_compact_time) > compact_interval {
 			last_compact_time = Utc::now();
 			let cutoff_time: DateTime<Utc> = Utc::now() - compact_interval;
+			let retention = candles_config.read().clone();
 
 			let mut oracle = oracle.lock();
-			let mut batch = oracle.batch().expect("batch failed");
-			let mut total_cleaned = 0;
-			for rate in batch.iter() {
-				if rate.date < cutoff_time {
-					total_cleaned += 1;
-					let mut id = rate.from.clone();
-					id.push('2');
-					id.push_str(&rate.to);
-					batch.delete(&id, rate.date).expect("batch delete failed");
-				}
-			}
-			batch.commit().expect("batch commit failed");
+			let total_cleaned =
+				candles::compact(&mut *oracle, cutoff_time, &retention).expect("compact failed");
 			debug!(
-				"daemon_alpha_vantage: compact get {} items cleaned",
+				"daemon_price_sources: compact get {} items cleaned",
 				total_cleaned
 			);
 		}
 
-		let now_time: DateTime<Utc> = Utc::now();
-		thread::sleep(time::Duration::from_secs(60 - now_time.second() as u64));
+		// sleep until the next pair is due, checking every second for a stop or
+		// a command
+		let wake_up = scheduler
+			.next_due()
+			.unwrap_or_else(|| time::Instant::now() + time::Duration::from_secs(60));
+		loop {
+			let _ = Delay::new(time::Instant::now() + time::Duration::from_secs(1))
+				.compat()
+				.await;
+			if stop_state.load(Ordering::SeqCst)
+				|| control.has_commands()
+				|| time::Instant::now() >= wake_up
+			{
+				break;
+			}
+		}
 	}
+	debug!("daemon_price_sources: stopped");
 }
 
-async fn query_once_alpha_vantage<T: ?Sized>(
+/// Query the price sources for a pair, then validate and save the median.
+/// A quote rejected by the validation doesn't count as a failure, as the
+/// price sources did answer.
+async fn query_once_price_sources<T: ?Sized>(
 	oracle: Arc<Mutex<T>>,
-	client: Arc<alphavantage::Client>,
+	aggregator: Arc<Aggregator>,
+	validator: Arc<QuoteValidator>,
 	from: &str,
 	to: &str,
-) where
+) -> Result<(), gotts_oracle_sources::Error>
+where
 	T: OracleBackend + Send + Sync + 'static,
 {
 	trace!("querying {}2{}", from, to);
-	let mut retries = 0;
-	let mut is_success = false;
-	let mut exchange_rate = ExchangeRate::default();
-	while !is_success {
-		let exchange_result = client.get_exchange_rate(from, to);
-		match exchange_result {
-			Ok(result) => {
-				is_success = true;
-				exchange_rate = result;
-			}
-			Err(_e) => {
-				error!(
-					"query alphavantage failed on {}2{}, retires={}",
-					from, to, retries
-				);
-				if retries >= 3 {
-					return;
-				}
-				retries += 1;
-			}
-		};
-	}
+	let aggregated_rate = aggregator.aggregate_async(from, to).compat().await?;
 
-	let exchange_rate_result = ExchangeRateResult {
-		from: exchange_rate.from.code.clone(),
-		to: exchange_rate.to.code.clone(),
-		rate: exchange_rate.rate,
-		date: exchange_rate.date,
-	};
-
-	// save the query data into local database for aggregation
-	{
-		let mut oracle = oracle.lock();
-		let mut batch = oracle.batch().expect("batch failed");
-		batch
-			.save(exchange_rate_result.date, exchange_rate_result.clone())
-			.expect("batch save failed");
-		batch.commit().expect("batch commit failed");
+	// validate and save the median and the readings into local database for aggregation
+	let mut oracle = oracle.lock();
+	if validator.validate(&*oracle, &aggregated_rate.rate).is_err() {
+		// already logged and counted by the validator
+		return Ok(());
 	}
+	let mut batch = oracle.batch().expect("batch failed");
+	batch
+		.save_aggregated(aggregated_rate)
+		.expect("batch save failed");
+	batch.commit().expect("batch commit failed");
+	api::metrics::record_update(&format!("{}2{}", from, to), Utc::now());
+	Ok(())
 }
@@ -0,0 +1,31 @@
+// Copyright 2018 The Grin Developers
+// Modifications Copyright 2019 The Gotts Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! File utilities
+
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+
+/// The first line of a file, without its line ending, or `None` if the file
+/// can't be read.
+pub fn get_first_line(file_path: Option<String>) -> Option<String> {
+	file_path.and_then(|path| match File::open(path) {
+		Ok(file) => BufReader::new(file)
+			.lines()
+			.next()
+			.and_then(|line| line.ok()),
+		Err(_) => None,
+	})
+}
@@ -0,0 +1,80 @@
+// Copyright 2018 The Grin Developers
+// Modifications Copyright 2019 The Gotts Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Implements hex-encoding from bytes to string and decoding of strings
+//! to bytes. Given that rustc-serialize is deprecated and serde doesn't
+//! provide easy hex encoding, hex is a bit in limbo right now in Rust-
+//! land. It's simple enough that we can just have our own.
+
+use std::fmt::Write;
+
+/// Encode the provided bytes into a hex string
+pub fn to_hex(bytes: Vec<u8>) -> String {
+	let mut s = String::with_capacity(bytes.len() * 2);
+	for byte in bytes {
+		write!(&mut s, "{:02x}", byte).expect("Unable to write");
+	}
+	s
+}
+
+/// Decode a hex string into bytes, an optional "0x" prefix is accepted.
+/// The error doesn't echo the string, which may be a secret.
+pub fn from_hex(hex_str: String) -> Result<Vec<u8>, String> {
+	let hex_trim = hex_str.trim();
+	let hex_trim = if hex_trim.starts_with("0x") {
+		&hex_trim[2..]
+	} else {
+		hex_trim
+	};
+	let invalid = || format!("invalid hex string of length {}", hex_trim.len());
+	if hex_trim.len() % 2 == 1 || !hex_trim.is_ascii() {
+		return Err(invalid());
+	}
+	(0..hex_trim.len())
+		.step_by(2)
+		.map(|i| u8::from_str_radix(&hex_trim[i..i + 2], 16).map_err(|_| invalid()))
+		.collect()
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	#[test]
+	fn test_to_hex() {
+		assert_eq!(to_hex(vec![0, 0, 0, 0]), "00000000");
+		assert_eq!(to_hex(vec![10, 11, 12, 13]), "0a0b0c0d");
+		assert_eq!(to_hex(vec![0, 0, 0, 255]), "000000ff");
+	}
+
+	#[test]
+	fn test_from_hex() {
+		assert_eq!(from_hex("00000000".to_string()).unwrap(), vec![0, 0, 0, 0]);
+		assert_eq!(
+			from_hex("0a0b0c0d".to_string()).unwrap(),
+			vec![10, 11, 12, 13]
+		);
+		assert_eq!(
+			from_hex("0x000000ff".to_string()).unwrap(),
+			vec![0, 0, 0, 255]
+		);
+		assert!(from_hex("0a0".to_string()).is_err());
+		assert!(from_hex("zz".to_string()).is_err());
+		assert_eq!(
+			from_hex("0x0a0b0c0z".to_string()).unwrap_err(),
+			"invalid hex string of length 8"
+		);
+	}
+}
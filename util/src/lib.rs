@@ -41,5 +41,11 @@ pub mod types;
 #[allow(unused_imports)]
 use std::ops::Deref;
 
+mod hex;
+pub use crate::hex::*;
+
+pub mod file;
+pub use crate::file::get_first_line;
+
 pub use crate::logger::{init_logger, init_test_logger};
 pub use crate::types::{LogLevel, LoggingConfig};